@@ -1,76 +1,433 @@
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+
+mod tests;
+
+/// Рациональное число с числителем и знаменателем в i32.
+///
+/// Дробь всегда хранится сокращённой и с положительным знаменателем, поэтому
+/// равные дроби совпадают побитово, и выведенные Eq и Hash согласованы
+/// с равенством чисел. Промежуточные вычисления идут в i128, так что операция
+/// переполняется, только если её сокращённый результат не помещается в i32.
+/// Операторы при переполнении и делении на ноль паникуют, как операторы целых
+/// чисел, а checked_* возвращают None.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ConstRational {
     numerator: i32,
     denominator: i32,
 }
 
+/// Ошибки создания дроби, разбора строки и преобразований
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RationalError {
+    ZeroDenominator,
+    Overflow,
+    NotAnInteger,
+    NotFinite,
+    Parse(String),
+}
+
+impl fmt::Display for RationalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RationalError::ZeroDenominator => write!(f, "Denominator is zero"),
+            RationalError::Overflow => write!(f, "Rational does not fit into i32"),
+            RationalError::NotAnInteger => write!(f, "Rational is not an integer"),
+            RationalError::NotFinite => write!(f, "Float is not finite"),
+            RationalError::Parse(source) => write!(f, "Can't parse rational from {source:?}"),
+        }
+    }
+}
+
+impl std::error::Error for RationalError {}
+
 impl ConstRational {
+    pub const ZERO: Self = Self::from_int(0);
+    pub const ONE: Self = Self::from_int(1);
+
+    /// Дробь numerator / denominator. Паникует, если знаменатель равен нулю
+    /// или сокращённая дробь не помещается в i32.
     pub const fn new(numerator: i32, denominator: i32) -> Self {
+        match Self::checked_new(numerator, denominator) {
+            Some(rational) => rational,
+            None if denominator == 0 => panic!("Знаменатель дроби равен нулю"),
+            None => panic!("Дробь не помещается в i32"),
+        }
+    }
+    pub const fn checked_new(numerator: i32, denominator: i32) -> Option<Self> {
+        Self::reduce(numerator as i128, denominator as i128)
+    }
+    pub const fn from_int(value: i32) -> Self {
         Self {
-            numerator,
-            denominator,
+            numerator: value,
+            denominator: 1,
+        }
+    }
+
+    /// Сокращает дробь и делает знаменатель положительным
+    const fn reduce(numerator: i128, denominator: i128) -> Option<Self> {
+        if denominator == 0 {
+            return None;
+        }
+        let (n, d) = (numerator.unsigned_abs(), denominator.unsigned_abs());
+        // Деление u128 программное и во много раз медленнее, а произведения
+        // и суммы произведений i32 помещаются в u64
+        let (n, d) = if n <= u64::MAX as u128 && d <= u64::MAX as u128 {
+            let gcd = gcd(n as u64, d as u64);
+            ((n as u64 / gcd) as u128, (d as u64 / gcd) as u128)
+        } else {
+            let gcd = gcd_wide(n, d);
+            (n / gcd, d / gcd)
+        };
+        let negative = (numerator < 0) != (denominator < 0);
+        if d > i32::MAX as u128 || n > i32::MAX as u128 + negative as u128 {
+            return None;
         }
+        let numerator = if negative { -(n as i64) } else { n as i64 };
+        Some(Self {
+            numerator: numerator as i32,
+            denominator: d as i32,
+        })
+    }
+
+    pub const fn numerator(self) -> i32 {
+        self.numerator
+    }
+    /// Знаменатель, всегда положительный
+    pub const fn denominator(self) -> i32 {
+        self.denominator
+    }
+    pub const fn is_integer(self) -> bool {
+        self.denominator == 1
     }
-    pub const fn add(self, rhs: Self) -> Self {
-        Self::new(
-            self.numerator * rhs.denominator + rhs.numerator * self.denominator,
-            self.denominator * rhs.denominator,
+
+    pub const fn checked_add(self, rhs: Self) -> Option<Self> {
+        Self::reduce(
+            self.numerator as i128 * rhs.denominator as i128
+                + rhs.numerator as i128 * self.denominator as i128,
+            self.denominator as i128 * rhs.denominator as i128,
         )
     }
-    pub const fn mul(self, rhs: Self) -> Self {
-        Self::new(
-            self.numerator * rhs.numerator,
-            self.denominator * rhs.denominator,
+    pub const fn checked_sub(self, rhs: Self) -> Option<Self> {
+        Self::reduce(
+            self.numerator as i128 * rhs.denominator as i128
+                - rhs.numerator as i128 * self.denominator as i128,
+            self.denominator as i128 * rhs.denominator as i128,
         )
     }
-    pub const fn neg(self) -> Self {
-        Self::new(-self.numerator, self.denominator)
+    pub const fn checked_mul(self, rhs: Self) -> Option<Self> {
+        Self::reduce(
+            self.numerator as i128 * rhs.numerator as i128,
+            self.denominator as i128 * rhs.denominator as i128,
+        )
     }
-
-    pub const fn sub(self, rhs: Self) -> Self {
-        self.add(rhs.neg())
+    pub const fn checked_div(self, rhs: Self) -> Option<Self> {
+        Self::reduce(
+            self.numerator as i128 * rhs.denominator as i128,
+            self.denominator as i128 * rhs.numerator as i128,
+        )
     }
-
-    pub const fn invert(self) -> Self {
-        Self::new(self.denominator, self.numerator)
+    pub const fn checked_neg(self) -> Option<Self> {
+        Self::reduce(-(self.numerator as i128), self.denominator as i128)
+    }
+    /// Обратная дробь или None для нуля
+    pub const fn checked_recip(self) -> Option<Self> {
+        Self::reduce(self.denominator as i128, self.numerator as i128)
     }
 
-    pub const fn div(self, rhs: Self) -> Self {
-        self.mul(rhs.invert())
+    pub const fn recip(self) -> Self {
+        match self.checked_recip() {
+            Some(rational) => rational,
+            None if self.numerator == 0 => panic!("Деление на ноль"),
+            None => panic!("Переполнение при обращении дроби"),
+        }
     }
-    pub fn into_f64(self) -> f64 {
-        self.numerator as f64 / self.denominator as f64
+    pub const fn abs(self) -> Self {
+        if self.numerator < 0 {
+            expect(self.checked_neg(), "Переполнение при взятии модуля дроби")
+        } else {
+            self
+        }
     }
-    pub const fn normalize(self) -> Self {
-        let gcd = gcd(self.numerator, self.denominator);
-        Self::new(self.numerator / gcd, self.denominator / gcd)
+    pub const fn signum(self) -> i32 {
+        self.numerator.signum()
     }
+
+    /// Наибольшее целое, не большее дроби
     pub const fn floor(self) -> i32 {
+        self.numerator.div_euclid(self.denominator)
+    }
+    /// Наименьшее целое, не меньшее дроби
+    pub const fn ceil(self) -> i32 {
+        // Без -floor(-x), чтобы не переполниться на i32::MIN
+        let floor = self.floor();
+        if self.is_integer() {
+            floor
+        } else {
+            floor + 1
+        }
+    }
+    /// Ближайшее целое, половины округляются от нуля, как у f64::round
+    pub const fn round(self) -> i32 {
+        let (numerator, denominator) = (self.numerator as i64, self.denominator as i64);
+        let rounded = (2 * numerator.abs() + denominator) / (2 * denominator);
+        (numerator.signum() * rounded) as i32
+    }
+    /// Отбрасывает дробную часть
+    pub const fn trunc(self) -> i32 {
         self.numerator / self.denominator
     }
-    pub const fn ge(self, other: Self) -> bool {
-        let a = self.numerator * other.denominator;
-        let b = other.numerator * self.denominator;
-        a >= b
+
+    pub fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
     }
-    pub const fn le(self, other: Self) -> bool {
-        let a = self.numerator * other.denominator;
-        let b = other.numerator * self.denominator;
-        a <= b
+
+    /// Ближайшая к value дробь, которая помещается в i32. Дробь, которую
+    /// можно записать в f64 точно, получается точно.
+    pub fn from_f64(value: f64) -> Result<Self, RationalError> {
+        if !value.is_finite() {
+            return Err(RationalError::NotFinite);
+        }
+        if !(i32::MIN as f64 - 0.5..=i32::MAX as f64 + 0.5).contains(&value) {
+            return Err(RationalError::Overflow);
+        }
+        // value = mantissa * 2^exponent
+        let bits = value.abs().to_bits();
+        let (exponent_bits, fraction) = ((bits >> 52) as i32, bits & ((1 << 52) - 1));
+        let (mantissa, exponent) = if exponent_bits == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | 1 << 52, exponent_bits - 1075)
+        };
+        let sign = if value < 0. { -1 } else { 1 };
+        if exponent >= 0 {
+            return Self::reduce(sign * ((mantissa as i128) << exponent), 1)
+                .ok_or(RationalError::Overflow);
+        }
+        // Меньше 2^-33, то есть меньше половины наименьшей положительной дроби
+        // 1 / i32::MAX. Заодно знаменатель 2^-exponent не переполнит i128.
+        if exponent < -85 {
+            return Ok(Self::ZERO);
+        }
+        let (numerator, denominator) = (mantissa as i128, 1i128 << -exponent);
+        // Больше единицы приближается обратное число, чтобы ограничить числитель.
+        // Отрицательный числитель может быть на единицу больше по модулю.
+        let (numerator, denominator) = if numerator > denominator {
+            let max_numerator = if sign < 0 { 1 << 31 } else { i32::MAX as i128 };
+            let (d, n) = limit_denominator(denominator, numerator, max_numerator);
+            (n, d)
+        } else {
+            limit_denominator(numerator, denominator, i32::MAX as i128)
+        };
+        Self::reduce(sign * numerator, denominator).ok_or(RationalError::Overflow)
     }
 }
 
-const fn gcd(a: i32, b: i32) -> i32 {
-    match (a, b) {
-        (0, x) => x,
-        (x, y) => gcd((x - y).abs(), min(x, y)),
+/// Ближайшая к неотрицательной дроби numerator / denominator дробь
+/// со знаменателем не больше max_denominator, по подходящим дробям цепной дроби
+fn limit_denominator(numerator: i128, denominator: i128, max_denominator: i128) -> (i128, i128) {
+    if denominator <= max_denominator {
+        return (numerator, denominator);
+    }
+    let (mut p0, mut q0, mut p1, mut q1) = (0, 1, 1, 0);
+    let (mut n, mut d) = (numerator, denominator);
+    while d != 0 {
+        let a = n / d;
+        let q2 = q0 + a * q1;
+        if q2 > max_denominator {
+            break;
+        }
+        (p0, q0, p1, q1) = (p1, q1, p0 + a * p1, q2);
+        (n, d) = (d, n - a * d);
+    }
+    // Последняя подходящая дробь или промежуточная между ней и предыдущей
+    let k = (max_denominator - q0) / q1;
+    let (bound_p, bound_q) = (p0 + k * p1, q0 + k * q1);
+    // |p / q - numerator / denominator|, умноженное на q * denominator
+    let distance = |p: i128, q: i128| (p * denominator - numerator * q).abs();
+    if distance(bound_p, bound_q) * q1 < distance(p1, q1) * bound_q {
+        (bound_p, bound_q)
+    } else {
+        (p1, q1)
     }
 }
 
-const fn min(a: i32, b: i32) -> i32 {
-    if a > b {
-        b
-    } else {
-        a
+/// Двоичный алгоритм Евклида: только сдвиги и вычитания, без деления
+const fn gcd(mut a: u64, mut b: u64) -> u64 {
+    if a == 0 || b == 0 {
+        return a | b;
+    }
+    let shift = (a | b).trailing_zeros();
+    a >>= a.trailing_zeros();
+    loop {
+        b >>= b.trailing_zeros();
+        if a > b {
+            (a, b) = (b, a);
+        }
+        b -= a;
+        if b == 0 {
+            return a << shift;
+        }
+    }
+}
+
+const fn gcd_wide(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+const fn expect(rational: Option<ConstRational>, message: &str) -> ConstRational {
+    match rational {
+        Some(rational) => rational,
+        None => panic!("{}", message),
+    }
+}
+
+impl Default for ConstRational {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl Ord for ConstRational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Знаменатели положительны, и произведения i32 помещаются в i64
+        let a = self.numerator as i64 * other.denominator as i64;
+        let b = other.numerator as i64 * self.denominator as i64;
+        a.cmp(&b)
+    }
+}
+
+impl PartialOrd for ConstRational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+macro_rules! impl_op {
+    ($op:ident, $method:ident, $assign:ident, $assign_method:ident, $checked:ident, $message:literal) => {
+        impl $op for ConstRational {
+            type Output = Self;
+            fn $method(self, rhs: Self) -> Self {
+                match self.$checked(rhs) {
+                    Some(rational) => rational,
+                    None => panic!($message),
+                }
+            }
+        }
+        impl $assign for ConstRational {
+            fn $assign_method(&mut self, rhs: Self) {
+                *self = $op::$method(*self, rhs);
+            }
+        }
+    };
+}
+
+impl_op!(
+    Add,
+    add,
+    AddAssign,
+    add_assign,
+    checked_add,
+    "Переполнение при сложении дробей"
+);
+impl_op!(
+    Sub,
+    sub,
+    SubAssign,
+    sub_assign,
+    checked_sub,
+    "Переполнение при вычитании дробей"
+);
+impl_op!(
+    Mul,
+    mul,
+    MulAssign,
+    mul_assign,
+    checked_mul,
+    "Переполнение при умножении дробей"
+);
+impl_op!(
+    Div,
+    div,
+    DivAssign,
+    div_assign,
+    checked_div,
+    "Деление дробей на ноль или переполнение"
+);
+
+impl Neg for ConstRational {
+    type Output = Self;
+    fn neg(self) -> Self {
+        expect(self.checked_neg(), "Переполнение при смене знака дроби")
+    }
+}
+
+impl From<i32> for ConstRational {
+    fn from(value: i32) -> Self {
+        Self::from_int(value)
+    }
+}
+
+impl From<ConstRational> for f64 {
+    fn from(value: ConstRational) -> Self {
+        value.to_f64()
+    }
+}
+
+/// Целое число, если дробь целая
+impl TryFrom<ConstRational> for i32 {
+    type Error = RationalError;
+    fn try_from(value: ConstRational) -> Result<Self, Self::Error> {
+        if value.is_integer() {
+            Ok(value.numerator)
+        } else {
+            Err(RationalError::NotAnInteger)
+        }
+    }
+}
+
+impl TryFrom<f64> for ConstRational {
+    type Error = RationalError;
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        Self::from_f64(value)
+    }
+}
+
+/// Целые записываются без знаменателя, остальные дроби как 3/4 или -1/2
+impl fmt::Display for ConstRational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+/// Разбирает целое или дробь вида 6/-8. Дробь сокращается, поэтому числитель
+/// и знаменатель сами по себе могут не помещаться в i32.
+impl FromStr for ConstRational {
+    type Err = RationalError;
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let parse = |part: &str| {
+            part.trim()
+                .parse::<i64>()
+                .map_err(|_| RationalError::Parse(source.to_owned()))
+        };
+        let (numerator, denominator) = match source.split_once('/') {
+            Some((numerator, denominator)) => (parse(numerator)?, parse(denominator)?),
+            None => (parse(source)?, 1),
+        };
+        if denominator == 0 {
+            return Err(RationalError::ZeroDenominator);
+        }
+        Self::reduce(numerator as i128, denominator as i128).ok_or(RationalError::Overflow)
     }
 }
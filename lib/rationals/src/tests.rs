@@ -0,0 +1,239 @@
+#![cfg(test)]
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use proptest::prelude::*;
+
+use crate::{ConstRational, RationalError};
+
+fn r(numerator: i32, denominator: i32) -> ConstRational {
+    ConstRational::new(numerator, denominator)
+}
+
+fn hash(value: ConstRational) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Дроби с небольшими числителями и знаменателями, в том числе отрицательными
+fn small() -> impl Strategy<Value = ConstRational> {
+    (
+        -1000..=1000,
+        (-1000..=1000).prop_filter("знаменатель не ноль", |d| *d != 0),
+    )
+        .prop_map(|(n, d)| r(n, d))
+}
+
+/// Любые дроби, в том числе у краёв i32
+fn any_rational() -> impl Strategy<Value = ConstRational> {
+    let part = prop_oneof![any::<i32>(), -1000..=1000, Just(i32::MIN), Just(i32::MAX)];
+    (part.clone(), part).prop_filter_map("дробь помещается в i32", |(n, d)| {
+        ConstRational::checked_new(n, d)
+    })
+}
+
+#[test]
+fn normalized() {
+    assert_eq!(r(2, 4), r(1, 2));
+    assert_eq!(r(3, -6), r(-1, 2));
+    assert_eq!(r(-3, -6), r(1, 2));
+    assert_eq!(r(0, -5), ConstRational::ZERO);
+    assert_eq!(r(-3, -6).denominator(), 2);
+    assert_eq!(hash(r(2, 4)), hash(r(1, 2)));
+    assert_eq!(ConstRational::checked_new(1, 0), None);
+    assert_eq!(ConstRational::checked_new(1, i32::MIN), None);
+    assert_eq!(ConstRational::checked_new(i32::MIN, -1), None);
+    assert_eq!(
+        ConstRational::checked_new(i32::MIN, -2),
+        Some(r(1 << 30, 1))
+    );
+}
+
+#[test]
+#[should_panic]
+fn zero_denominator() {
+    r(1, 0);
+}
+
+#[test]
+fn rounding() {
+    let cases = [
+        (r(7, 2), 3, 4, 4, 3),
+        (r(-7, 2), -4, -3, -4, -3),
+        (r(5, 3), 1, 2, 2, 1),
+        (r(-5, 3), -2, -1, -2, -1),
+        (r(4, 3), 1, 2, 1, 1),
+        (r(-4, 3), -2, -1, -1, -1),
+        (r(-6, 3), -2, -2, -2, -2),
+        (
+            ConstRational::from(i32::MIN),
+            i32::MIN,
+            i32::MIN,
+            i32::MIN,
+            i32::MIN,
+        ),
+        (
+            r(i32::MAX, 2),
+            i32::MAX / 2,
+            i32::MAX / 2 + 1,
+            i32::MAX / 2 + 1,
+            i32::MAX / 2,
+        ),
+    ];
+    for (value, floor, ceil, round, trunc) in cases {
+        assert_eq!(
+            (value.floor(), value.ceil(), value.round(), value.trunc()),
+            (floor, ceil, round, trunc),
+            "{value}"
+        );
+    }
+}
+
+#[test]
+fn overflow() {
+    let max = ConstRational::from(i32::MAX);
+    assert_eq!(max.checked_add(ConstRational::ONE), None);
+    assert_eq!(max.checked_mul(r(1, 2)), Some(r(i32::MAX, 2)));
+    assert_eq!(r(1, i32::MAX).checked_mul(r(1, 2)), None);
+    assert_eq!(ConstRational::from(i32::MIN).checked_neg(), None);
+    assert_eq!(ConstRational::ONE.checked_div(ConstRational::ZERO), None);
+    assert_eq!(ConstRational::ZERO.checked_recip(), None);
+    // Промежуточный результат не помещается в i32, а сокращённый помещается
+    assert_eq!(max.checked_mul(r(2, i32::MAX)), Some(r(2, 1)));
+}
+
+#[test]
+#[should_panic]
+fn operator_overflow() {
+    let _ = ConstRational::from(i32::MAX) + ConstRational::ONE;
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(i32::try_from(r(6, 3)), Ok(2));
+    assert_eq!(i32::try_from(r(1, 3)), Err(RationalError::NotAnInteger));
+    assert_eq!(f64::from(r(-3, 4)), -0.75);
+    assert_eq!(ConstRational::try_from(0.375), Ok(r(3, 8)));
+    assert_eq!(ConstRational::try_from(-2.5), Ok(r(-5, 2)));
+    assert_eq!(ConstRational::try_from(1. / 3.), Ok(r(1, 3)));
+    assert_eq!(
+        ConstRational::try_from(f64::NAN),
+        Err(RationalError::NotFinite)
+    );
+    assert_eq!(ConstRational::try_from(1e10), Err(RationalError::Overflow));
+    assert_eq!(
+        ConstRational::try_from(i32::MIN as f64),
+        Ok(ConstRational::from_int(i32::MIN))
+    );
+}
+
+#[test]
+fn parsing() {
+    assert_eq!("3/4".parse(), Ok(r(3, 4)));
+    assert_eq!(" 6 / -8 ".parse(), Ok(r(-3, 4)));
+    assert_eq!("-12".parse(), Ok(r(-12, 1)));
+    assert_eq!("4294967296/4294967296".parse(), Ok(ConstRational::ONE));
+    assert_eq!(
+        "1/0".parse::<ConstRational>(),
+        Err(RationalError::ZeroDenominator)
+    );
+    assert_eq!(
+        "4294967296".parse::<ConstRational>(),
+        Err(RationalError::Overflow)
+    );
+    assert!(matches!(
+        "1/2/3".parse::<ConstRational>(),
+        Err(RationalError::Parse(_))
+    ));
+    assert!(matches!(
+        "half".parse::<ConstRational>(),
+        Err(RationalError::Parse(_))
+    ));
+    assert_eq!(r(-3, 4).to_string(), "-3/4");
+    assert_eq!(r(8, 4).to_string(), "2");
+}
+
+proptest! {
+    #[test]
+    fn display_parses_back(a in any_rational()) {
+        prop_assert_eq!(a.to_string().parse::<ConstRational>(), Ok(a));
+    }
+
+    #[test]
+    fn always_normalized(a in any_rational()) {
+        prop_assert!(a.denominator() > 0);
+        let gcd = (1..=a.denominator().min(1000))
+            .rev()
+            .find(|d| a.numerator() % d == 0 && a.denominator() % d == 0);
+        prop_assert!(gcd.is_none() || gcd == Some(1));
+    }
+
+    #[test]
+    fn field_laws(a in small(), b in small(), c in small()) {
+        prop_assert_eq!(a + b, b + a);
+        prop_assert_eq!(a * b, b * a);
+        prop_assert_eq!((a + b) + c, a + (b + c));
+        prop_assert_eq!((a * b) * c, a * (b * c));
+        prop_assert_eq!(a * (b + c), a * b + a * c);
+        prop_assert_eq!(a - b, a + -b);
+        prop_assert_eq!(a - a, ConstRational::ZERO);
+        if b != ConstRational::ZERO {
+            prop_assert_eq!(a / b * b, a);
+            prop_assert_eq!(b * b.recip(), ConstRational::ONE);
+        }
+        let mut d = a;
+        d += b;
+        d *= c;
+        d -= a;
+        prop_assert_eq!(d, (a + b) * c - a);
+    }
+
+    #[test]
+    fn order_matches_floats(a in small(), b in small()) {
+        prop_assert_eq!(a.cmp(&b), a.to_f64().total_cmp(&b.to_f64()));
+        prop_assert_eq!(a == b, hash(a) == hash(b));
+        prop_assert_eq!(a.max(b) >= a.min(b), true);
+    }
+
+    #[test]
+    fn rounding_matches_floats(a in any_rational()) {
+        let value = a.to_f64();
+        prop_assert_eq!(a.floor() as f64, value.floor());
+        prop_assert_eq!(a.ceil() as f64, value.ceil());
+        prop_assert_eq!(a.round() as f64, value.round());
+        prop_assert_eq!(a.trunc() as f64, value.trunc());
+        prop_assert!(ConstRational::from(a.floor()) <= a);
+        prop_assert!(ConstRational::from(a.ceil()) >= a);
+    }
+
+    #[test]
+    fn checked_ops_match_wide_arithmetic(a in any_rational(), b in any_rational()) {
+        // Точный результат в i128 помещается в дробь i32 тогда и только тогда,
+        // когда checked-операция вернула Some
+        let (an, ad, bn, bd) = (
+            a.numerator() as i128,
+            a.denominator() as i128,
+            b.numerator() as i128,
+            b.denominator() as i128,
+        );
+        let fits = |n: i128, d: i128| ConstRational::reduce(n, d);
+        prop_assert_eq!(a.checked_add(b), fits(an * bd + bn * ad, ad * bd));
+        prop_assert_eq!(a.checked_mul(b), fits(an * bn, ad * bd));
+        if let Some(sum) = a.checked_add(b) {
+            prop_assert_eq!(sum.checked_sub(b), Some(a));
+        }
+    }
+
+    #[test]
+    fn floats_convert_back(a in small(), b in any_rational()) {
+        prop_assert_eq!(ConstRational::try_from(a.to_f64()), Ok(a));
+        // Несколько дробей с большими знаменателями дают одно и то же f64,
+        // поэтому обратно получается ближайшая из них
+        let back = ConstRational::try_from(b.to_f64()).unwrap();
+        prop_assert_eq!(back.to_f64(), b.to_f64());
+    }
+}
@@ -0,0 +1,164 @@
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
+
+use crate::worldgen::chunk_seed;
+
+/// Природная зона мира. Определяет рельеф, тайлы поверхности, постройки
+/// и то, какие существа встречаются в этом месте.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Biome {
+    /// Облучённая пустошь
+    Wasteland,
+    /// Развалины города
+    RuinedCity,
+    Forest,
+    /// Воронка от взрыва
+    Crater,
+    /// Ядовитое болото
+    ToxicSwamp,
+}
+
+pub const BIOMES: [Biome; 5] = [
+    Biome::Wasteland,
+    Biome::RuinedCity,
+    Biome::Forest,
+    Biome::Crater,
+    Biome::ToxicSwamp,
+];
+
+/// Параметры рельефа биома. Между соседними биомами они плавно смешиваются.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiomeTerrain {
+    /// Средняя высота поверхности
+    pub base_height: f64,
+    /// Насколько холмы поднимаются над средней высотой и долины опускаются под неё
+    pub hills_height: f64,
+}
+
+impl BiomeTerrain {
+    fn scale(self, weight: f64) -> Self {
+        Self {
+            base_height: self.base_height * weight,
+            hills_height: self.hills_height * weight,
+        }
+    }
+    fn add(self, other: Self) -> Self {
+        Self {
+            base_height: self.base_height + other.base_height,
+            hills_height: self.hills_height + other.hills_height,
+        }
+    }
+}
+
+impl Biome {
+    pub fn terrain(self) -> BiomeTerrain {
+        let (base_height, hills_height) = match self {
+            Biome::Wasteland => (0., 12.),
+            Biome::RuinedCity => (0., 3.),
+            Biome::Forest => (4., 20.),
+            Biome::Crater => (-18., 4.),
+            Biome::ToxicSwamp => (-3., 2.),
+        };
+        BiomeTerrain {
+            base_height,
+            hills_height,
+        }
+    }
+
+    /// Имя тайла, из которого состоит верхний слой земли
+    pub fn surface_tile(self) -> &'static str {
+        match self {
+            Biome::Wasteland | Biome::Crater => "wall",
+            Biome::RuinedCity => "cobblestone",
+            Biome::Forest => "grass",
+            Biome::ToxicSwamp => "toxic_sludge",
+        }
+    }
+
+    /// Шаблоны сущностей, которые появляются в биоме, с их относительными весами
+    pub fn spawn_table(self) -> &'static [(&'static str, u32)] {
+        match self {
+            Biome::Wasteland => &[("old_nettle", 1)],
+            Biome::RuinedCity => &[],
+            Biome::Forest => &[("nettle", 4), ("old_nettle", 1)],
+            Biome::Crater => &[],
+            Biome::ToxicSwamp => &[("nettle", 1)],
+        }
+    }
+}
+
+/// Карта биомов, которая целиком определяется зерном мира.
+/// Биом выбирается по нескольким медленно меняющимся шумам, а граница между
+/// биомами дополнительно искажается шумом, чтобы не быть гладкой.
+pub struct BiomeMap {
+    moisture: Perlin,
+    ruins: Perlin,
+    craters: Perlin,
+    warp_x: Perlin,
+    warp_y: Perlin,
+}
+
+impl BiomeMap {
+    const SCALE: f64 = 1. / 512.;
+    const WARP_SCALE: f64 = 1. / 32.;
+    /// На сколько тайлов может сдвинуться граница биомов
+    const WARP_DISTANCE: f64 = 12.;
+    /// Шаг сетки, по которой смешивается рельеф соседних биомов
+    pub const BLEND_STEP: i32 = 16;
+
+    pub fn new(seed: u64) -> Self {
+        let layer_seed = |layer: i32| chunk_seed(seed, layer, 1, 0) as u32;
+        Self {
+            moisture: Perlin::new(layer_seed(0)),
+            ruins: Perlin::new(layer_seed(1)),
+            craters: Perlin::new(layer_seed(2)),
+            warp_x: Perlin::new(layer_seed(3)),
+            warp_y: Perlin::new(layer_seed(4)),
+        }
+    }
+
+    /// Биом в точке мира. Биомы не зависят от высоты.
+    pub fn biome_at(&self, x: i32, y: i32) -> Biome {
+        let warp = [x as f64 * Self::WARP_SCALE, y as f64 * Self::WARP_SCALE];
+        let x = x as f64 + self.warp_x.get(warp) * Self::WARP_DISTANCE;
+        let y = y as f64 + self.warp_y.get(warp) * Self::WARP_DISTANCE;
+        self.climate_biome(x, y)
+    }
+
+    fn climate_biome(&self, x: f64, y: f64) -> Biome {
+        let point = [x * Self::SCALE, y * Self::SCALE];
+        // Воронки мельче остальных биомов
+        let craters = self.craters.get(point.map(|c| c * 4.));
+        if craters > 0.65 {
+            return Biome::Crater;
+        }
+        if self.ruins.get(point) > 0.25 {
+            return Biome::RuinedCity;
+        }
+        match self.moisture.get(point) {
+            moisture if moisture > 0.3 => Biome::ToxicSwamp,
+            moisture if moisture > -0.1 => Biome::Forest,
+            _ => Biome::Wasteland,
+        }
+    }
+
+    /// Параметры рельефа в точке, плавно смешанные между биомами соседних узлов
+    /// сетки с шагом BLEND_STEP. Рельеф поэтому непрерывен и на границах чанков.
+    pub fn terrain_at(&self, x: i32, y: i32) -> BiomeTerrain {
+        let step = Self::BLEND_STEP;
+        let (cell_x, cell_y) = (x.div_euclid(step) * step, y.div_euclid(step) * step);
+        let smooth = |t: f64| t * t * (3. - 2. * t);
+        let tx = smooth((x - cell_x) as f64 / step as f64);
+        let ty = smooth((y - cell_y) as f64 / step as f64);
+        let corner = |dx: i32, dy: i32| {
+            self.climate_biome((cell_x + dx * step) as f64, (cell_y + dy * step) as f64)
+                .terrain()
+        };
+        corner(0, 0)
+            .scale((1. - tx) * (1. - ty))
+            .add(corner(1, 0).scale(tx * (1. - ty)))
+            .add(corner(0, 1).scale((1. - tx) * ty))
+            .add(corner(1, 1).scale(tx * ty))
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::JoinHandle,
+};
+
+use vek::Vec3;
+
+use crate::{hasher, map::Chunk, tiles::TileRegistry, worldgen::ChunkGenerator, GameHasher};
+
+/// Очередь чанков, ждущих генерации
+struct Requests {
+    chunks: VecDeque<(i32, i32, i32)>,
+    stopped: bool,
+}
+
+/// Генерирует чанки в фоновых потоках, чтобы игра не замирала, когда персонаж
+/// переходит в новый чанк. Чанки генерируются в порядке запросов, а готовые
+/// чанки забираются через receive.
+pub struct ChunkLoader {
+    requests: Arc<(Mutex<Requests>, Condvar)>,
+    /// Receiver не Sync, а карта - компонент, поэтому он под мьютексом
+    generated: Mutex<mpsc::Receiver<((i32, i32, i32), Chunk)>>,
+    /// Запрошенные, но ещё не полученные чанки
+    pending: HashSet<(i32, i32, i32), GameHasher>,
+    workers: Vec<JoinHandle<()>>,
+    /// Последняя позиция персонажа и направление, в котором он двигался
+    last_position: Option<Vec3<i32>>,
+    heading: Vec3<i32>,
+}
+
+impl ChunkLoader {
+    pub fn new(
+        seed: u64,
+        generator: Arc<dyn ChunkGenerator>,
+        tiles: Arc<TileRegistry>,
+        workers: usize,
+    ) -> Self {
+        let requests = Arc::new((
+            Mutex::new(Requests {
+                chunks: VecDeque::new(),
+                stopped: false,
+            }),
+            Condvar::new(),
+        ));
+        let (sender, generated) = mpsc::channel();
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let requests = requests.clone();
+                let sender = sender.clone();
+                let generator = generator.clone();
+                let tiles = tiles.clone();
+                std::thread::spawn(move || {
+                    while let Some((x, y, z)) = next_request(&requests) {
+                        let chunk = generator.generate(seed, &tiles, x, y, z);
+                        if sender.send(((x, y, z), chunk)).is_err() {
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+        ChunkLoader {
+            requests,
+            generated: Mutex::new(generated),
+            pending: HashSet::with_hasher(hasher()),
+            workers,
+            last_position: None,
+            heading: Vec3::zero(),
+        }
+    }
+
+    /// Ставит чанк в очередь, если он ещё не запрошен
+    pub fn request(&mut self, chunk: (i32, i32, i32)) {
+        if self.pending.insert(chunk) {
+            let (requests, condvar) = &*self.requests;
+            requests.lock().unwrap().chunks.push_back(chunk);
+            condvar.notify_one();
+        }
+    }
+
+    /// Сколько запрошенных чанков ещё не получено
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Забирает уже сгенерированные чанки, не дожидаясь остальных
+    pub fn receive(&mut self) -> Vec<((i32, i32, i32), Chunk)> {
+        let chunks = self
+            .generated
+            .get_mut()
+            .unwrap()
+            .try_iter()
+            .collect::<Vec<_>>();
+        for (position, _) in chunks.iter() {
+            self.pending.remove(position);
+        }
+        chunks
+    }
+
+    /// Направление, в котором персонаж сдвинулся с прошлого вызова, по каждой
+    /// оси от -1 до 1. Если персонаж стоит на месте, остаётся прошлое направление.
+    pub fn heading(&mut self, position: Vec3<i32>) -> Vec3<i32> {
+        if let Some(last) = self.last_position {
+            let step = (position - last).map(i32::signum);
+            if step != Vec3::zero() {
+                self.heading = step;
+            }
+        }
+        self.last_position = Some(position);
+        self.heading
+    }
+}
+
+/// Ждёт следующий запрос. None означает, что генератор остановлен.
+fn next_request(requests: &(Mutex<Requests>, Condvar)) -> Option<(i32, i32, i32)> {
+    let (requests, condvar) = requests;
+    let mut requests = requests.lock().unwrap();
+    loop {
+        if requests.stopped {
+            return None;
+        }
+        if let Some(chunk) = requests.chunks.pop_front() {
+            return Some(chunk);
+        }
+        requests = condvar.wait(requests).unwrap();
+    }
+}
+
+impl Drop for ChunkLoader {
+    fn drop(&mut self) {
+        let (requests, condvar) = &*self.requests;
+        requests.lock().unwrap().stopped = true;
+        condvar.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -1,11 +1,58 @@
-use std::sync::Arc;
+use std::{fmt::Display, str::FromStr, sync::Arc};
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use vek::Vec3;
 
+use crate::registry::ComponentRegistry;
+
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Position>("position");
+    registry.register::<Name>("name");
+}
+
 /// Компонент, который должен быть у сущностей, которые будут иметь позицию на
 /// игровой карте. Это может быть, например, лежащий на земле предмет, игрок или неигровой персонаж.
-#[derive(Eq, PartialEq, Clone, Copy, Hash)]
+#[derive(Eq, PartialEq, Clone, Copy, Hash, Debug)]
 pub struct Position(pub Vec3<i32>);
 
+/// В файлах данных позиция записывается строкой вида XxYxZ, например `10x10x0`.
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected position in XxYxZ format, found {s:?}");
+        let nums = s
+            .split('x')
+            .map(|x| x.parse::<i32>())
+            .collect::<Result<Vec<i32>, _>>()
+            .map_err(|_| err())?;
+        let [x, y, z] = nums[..] else {
+            return Err(err());
+        };
+        Ok(Position(Vec3::new(x, y, z)))
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}x{}", self.0.x, self.0.y, self.0.z)
+    }
+}
+
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
 /// Компонент, имя какой-либо сущности.
+#[derive(Serialize, Deserialize)]
 pub struct Name(pub Arc<str>);
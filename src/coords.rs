@@ -0,0 +1,62 @@
+use crate::map::CHUNK_SIZE;
+
+// Перевод координат между тайлами мира и чанками. Чанк k по каждой оси покрывает
+// глобальные координаты от 64k - 32 до 64k + 31, так что тайл (0, 0, 0) лежит
+// в середине чанка (0, 0, 0). Внутри чанка тайлы хранятся линейным массивом:
+// индекс тайла - x + y * 64 + z * 64², где x, y, z отсчитываются от угла чанка
+// с наименьшими координатами.
+
+const SIZE: i32 = CHUNK_SIZE as i32;
+const HALF: i32 = SIZE / 2;
+
+/// Чанк, в который попадает глобальная координата по одной оси
+#[inline]
+pub const fn chunk_coord(global: i32) -> i32 {
+    // Без сложения global + HALF, чтобы не переполниться у границ i32
+    global.div_euclid(SIZE) + (global.rem_euclid(SIZE) >= HALF) as i32
+}
+
+/// Координата тайла внутри его чанка по одной оси, от 0 до CHUNK_SIZE - 1
+#[inline]
+pub const fn local_coord(global: i32) -> usize {
+    ((global.rem_euclid(SIZE) + HALF) % SIZE) as usize
+}
+
+/// Глобальная координата по координате чанка и координате тайла внутри него
+#[inline]
+pub const fn global_coord(chunk: i32, local: usize) -> i32 {
+    (chunk as i64 * SIZE as i64 - HALF as i64 + local as i64) as i32
+}
+
+/// Чанк, в котором лежит тайл
+#[inline]
+pub const fn chunk_of(x: i32, y: i32, z: i32) -> (i32, i32, i32) {
+    (chunk_coord(x), chunk_coord(y), chunk_coord(z))
+}
+
+/// Индекс тайла в массиве тайлов его чанка
+#[inline]
+pub const fn index_of(x: i32, y: i32, z: i32) -> usize {
+    local_coord(x) + local_coord(y) * CHUNK_SIZE + local_coord(z) * CHUNK_SIZE * CHUNK_SIZE
+}
+
+/// Координаты тайла внутри чанка по его индексу
+#[inline]
+pub const fn local_of_index(index: usize) -> (usize, usize, usize) {
+    (
+        index % CHUNK_SIZE,
+        index / CHUNK_SIZE % CHUNK_SIZE,
+        index / (CHUNK_SIZE * CHUNK_SIZE),
+    )
+}
+
+/// Глобальные координаты тайла по чанку и индексу тайла в нём
+#[inline]
+pub const fn global_of((ch_x, ch_y, ch_z): (i32, i32, i32), index: usize) -> (i32, i32, i32) {
+    let (x, y, z) = local_of_index(index);
+    (
+        global_coord(ch_x, x),
+        global_coord(ch_y, y),
+        global_coord(ch_z, z),
+    )
+}
@@ -2,18 +2,22 @@
 /// если она находится на карте, или же она должна находиться в чьём-нибудь инвентаре.
 use std::{collections::HashMap, sync::Arc};
 
-
+use serde::{Deserialize, Serialize};
 use vek::Vec3;
 
-
 use crate::{
     components::{Name, Position},
     hasher,
+    registry::ComponentRegistry,
     systems::render::Renderable,
     GameHasher, Property,
 };
 
-#[derive(Clone)]
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Item>("item");
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Item {
     pub name: String,
     pub sprite_name: String,
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use hecs::Entity;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    coords::{chunk_of, local_coord},
+    hasher,
+    map::CHUNK_SIZE,
+    systems::fov_compute::SightKey,
+    GameHasher,
+};
+
+// Освещение. Свет каждого источника расходится так же, как взгляд: его
+// загораживают непрозрачные тайлы, поэтому освещённые источником тайлы
+// считаются тем же расчётом поля зрения из места источника. Свет от разных
+// источников складывается по каналам. Количество света хранится целыми
+// долями 1/255 полной яркости канала, чтобы вклад источника можно было
+// вычесть из суммы, когда он сдвинулся или погас, без накопления ошибок.
+// Кроме источников светит небо: его свет одинаков во всех тайлах, над которыми
+// в столбце нет непрозрачных тайлов.
+
+/// Сколько света нужно в тайле, чтобы его было видно: самый яркий канал
+/// должен быть не меньше этого числа долей 1/255
+pub const MIN_VISIBLE_LIGHT: u32 = 26;
+
+/// Свет неба днём по каналам
+pub const DAYLIGHT: [u16; 3] = [255, 255, 255];
+
+/// Свет, который испускает тайл или сущность с LightSource
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Light {
+    /// Дальше этого расстояния в тайлах свет не доходит
+    pub radius: u32,
+    /// Цвет света вида 0xRRGGBB
+    pub color: u32,
+    /// Яркость рядом с источником, 1 - полная
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn is_valid(&self) -> bool {
+        self.intensity.is_finite() && self.intensity > 0. && self.color <= 0xFFFFFF
+    }
+
+    /// Свет источника по каналам в тайле со смещением offset от источника.
+    /// Яркость линейно убывает с расстоянием и пропадает сразу за радиусом.
+    pub fn at(&self, (x, y, z): (i32, i32, i32)) -> [u16; 3] {
+        let distance = ((x * x + y * y + z * z) as f32).sqrt();
+        let level = self.intensity * (1. - distance / (self.radius + 1) as f32).max(0.);
+        [16, 8, 0].map(|shift| {
+            let channel = (self.color >> shift & 0xFF) as f32;
+            (channel * level).round().min(u16::MAX as f32) as u16
+        })
+    }
+}
+
+/// Источник света: сущность или светящийся тайл
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LightSourceId {
+    Entity(Entity),
+    Tile((i32, i32, i32)),
+}
+
+/// Освещённые источником тайлы с количеством света в каждом из них
+#[derive(Debug)]
+pub struct LitArea {
+    /// Для чего посчитаны тайлы. None, если свет упёрся в незагруженный чанк,
+    /// и его нужно посчитать заново.
+    pub key: Option<SightKey>,
+    pub light: Light,
+    pub tiles: Vec<((i32, i32, i32), [u16; 3])>,
+}
+
+/// Открытость небу столбца чанков
+#[derive(Debug)]
+pub struct SkyColumn {
+    /// Загруженные чанки столбца снизу вверх с номерами их последних изменений.
+    /// Пока они те же, открытость не пересчитывается.
+    pub key: Vec<(i32, u64)>,
+    /// Для каждого столбца тайлов высота самого верхнего тайла, до которого
+    /// не доходит небо, по индексу x + y * CHUNK_SIZE внутри чанка
+    pub floors: Box<[i32]>,
+}
+
+/// Свет всех источников в загруженной части мира. Его пересчитывает система
+/// освещения, а поле зрения, память и отрисовка только читают.
+#[derive(Debug)]
+pub struct LightMap {
+    /// Свет неба по каналам в открытых небу тайлах. В тёмном мире или ночью
+    /// он нулевой.
+    pub sky: [u16; 3],
+    /// Сумма света всех источников по каналам. Неосвещённых тайлов здесь нет.
+    tiles: HashMap<(i32, i32, i32), [u32; 3], GameHasher>,
+    sources: HashMap<LightSourceId, LitArea, GameHasher>,
+    /// Открытость небу загруженных столбцов чанков по координатам (x, y) чанка
+    sky_columns: HashMap<(i32, i32), SkyColumn, GameHasher>,
+}
+
+impl LightMap {
+    pub fn new() -> Self {
+        LightMap {
+            sky: DAYLIGHT,
+            tiles: HashMap::with_hasher(hasher()),
+            sources: HashMap::with_hasher(hasher()),
+            sky_columns: HashMap::with_hasher(hasher()),
+        }
+    }
+
+    /// Сумма света источников и неба в тайле в долях 1/255
+    fn amount(&self, pos: (i32, i32, i32)) -> [u32; 3] {
+        let mut light = self.tiles.get(&pos).copied().unwrap_or([0; 3]);
+        if self.is_open_sky(pos) {
+            for (channel, sky) in light.iter_mut().zip(self.sky) {
+                *channel += sky as u32;
+            }
+        }
+        light
+    }
+
+    /// Свет в тайле по каналам, 1 - полная яркость. Где светят несколько
+    /// источников, свет может быть ярче 1.
+    pub fn get(&self, pos: (i32, i32, i32)) -> [f32; 3] {
+        self.amount(pos).map(|channel| channel as f32 / 255.)
+    }
+
+    /// Достаточно ли тайл освещён, чтобы его было видно
+    pub fn is_lit(&self, pos: (i32, i32, i32)) -> bool {
+        self.amount(pos)
+            .iter()
+            .any(|&channel| channel >= MIN_VISIBLE_LIGHT)
+    }
+
+    /// Освещает ли тайл небо: над ним нет непрозрачных тайлов, а сам он может
+    /// быть непрозрачным, как земля. Тайлы незагруженных чанков небу не открыты.
+    pub fn is_open_sky(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        let (ch_x, ch_y, _) = chunk_of(x, y, z);
+        self.sky_columns
+            .get(&(ch_x, ch_y))
+            .is_some_and(|column| z > column.floors[local_coord(x) + local_coord(y) * CHUNK_SIZE])
+    }
+
+    pub fn sky_column(&self, column: (i32, i32)) -> Option<&SkyColumn> {
+        self.sky_columns.get(&column)
+    }
+
+    pub fn set_sky_column(&mut self, column: (i32, i32), sky: SkyColumn) {
+        self.sky_columns.insert(column, sky);
+    }
+
+    /// Забывает открытость столбцов чанков, для которых keep возвращает false
+    pub fn retain_sky_columns(&mut self, keep: impl Fn((i32, i32)) -> bool) {
+        self.sky_columns.retain(|column, _| keep(*column));
+    }
+
+    pub fn source(&self, id: LightSourceId) -> Option<&LitArea> {
+        self.sources.get(&id)
+    }
+
+    /// Заменяет свет источника новым
+    pub fn set_source(&mut self, id: LightSourceId, area: LitArea) {
+        self.remove_source(id);
+        for &(pos, light) in area.tiles.iter() {
+            let sum = self.tiles.entry(pos).or_insert([0; 3]);
+            for (sum, channel) in sum.iter_mut().zip(light) {
+                *sum += channel as u32;
+            }
+        }
+        self.sources.insert(id, area);
+    }
+
+    /// Убирает свет источника, который погас или исчез
+    pub fn remove_source(&mut self, id: LightSourceId) {
+        let Some(area) = self.sources.remove(&id) else {
+            return;
+        };
+        for (pos, light) in area.tiles {
+            let sum = self
+                .tiles
+                .get_mut(&pos)
+                .unwrap_or_else(|| panic!("Свет источника {id:?} потерялся в тайле {pos:?}"));
+            for (sum, channel) in sum.iter_mut().zip(light) {
+                *sum -= channel as u32;
+            }
+            if *sum == [0; 3] {
+                self.tiles.remove(&pos);
+            }
+        }
+    }
+
+    /// Убирает свет всех источников, для которых keep возвращает false
+    pub fn retain_sources(&mut self, keep: impl Fn(LightSourceId) -> bool) {
+        let removed = self
+            .sources
+            .keys()
+            .copied()
+            .filter(|id| !keep(*id))
+            .collect::<Vec<_>>();
+        for id in removed {
+            self.remove_source(id);
+        }
+    }
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
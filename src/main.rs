@@ -1,33 +1,58 @@
+mod biome;
+mod chunk_loader;
 mod components;
+mod coords;
 mod items;
+mod light;
 mod map;
 mod mob;
+mod planner;
 mod player;
+mod prefabs;
+mod region;
+mod registry;
 mod resources;
+mod save;
+mod storage;
 mod systems;
 mod tests;
+mod tiles;
 mod ui;
+mod worldgen;
 use components::Position;
 
 use hecs::{CommandBuffer, World};
 use items::Item;
 use macroquad::{
+    input::{is_quit_requested, prevent_quit},
     prelude::Color,
     window::{clear_background, next_frame, Conf},
 };
 use map::WorldMap;
 use mob::{Inventory, Log, Mob};
-use player::{get_player_items, new_player, Player};
+use player::{get_player_items, is_player_dead, new_player, write_player_log, Player};
 use resources::Resources;
-use std::{collections::HashMap, env, sync::Mutex, time::Duration};
+use save::{GameMode, SaveError, SaveSlot};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use systems::{
-    health::{Body, BodyPart, BodyPartPart, Organ, Wound},
+    health::Wound,
+    memory::MapMemory,
     movement::{dir_to_vec3, WantsMove},
     render::run_render_system,
+    terrain::{TerrainAction, WantsBuild, WantsDig, DIGGING_PROPERTY},
     GameSystem, WorldSystem,
 };
 
 use ui::{set_skin, UIConfig, UIState};
+use vek::Vec3;
+use worldgen::{generator_by_name, ChunkGenerator, NoiseGenerator, PrefabGenerator};
 
 use crate::systems::health::WantsAttack;
 
@@ -42,7 +67,7 @@ pub fn hasher() -> GameHasher {
     fxhash::FxBuildHasher::default()
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Property {
     Int(i32),
     String(String),
@@ -62,6 +87,10 @@ pub struct Game {
     is_needed_redraw: Mutex<bool>,
     scale: f32,
     statistics: Mutex<Statistics>,
+    turn: u64,
+    save: SaveSlot,
+    /// Персонаж умер, и ходы больше не выполняются
+    is_over: bool,
 }
 
 #[derive(Clone)]
@@ -111,6 +140,14 @@ pub enum PlayerAction {
     Nothing,
     Zoom,
     Unzoom,
+    Save,
+    /// Начать выбор направления, чтобы копать
+    Dig,
+    /// Начать выбор направления, чтобы строить
+    Build,
+    /// Направление, выбранное для копания или строительства
+    Direction(Direction),
+    CancelDirection,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -129,51 +166,84 @@ enum Action {
 }
 
 impl Game {
-    async fn new() -> anyhow::Result<Game> {
+    async fn new(
+        save: SaveSlot,
+        seed: u64,
+        generator: Arc<dyn ChunkGenerator>,
+    ) -> anyhow::Result<Game> {
         set_skin().await;
-        let exe_path = env::current_exe().expect("Ты ебанутый? Ты что там делаешь?");
-        let data_path = exe_path
-            .parent()
-            .and_then(|p| p.parent())
-            .map(|p| p.join("data"))
-            .filter(|p| p.exists())
-            .unwrap_or(env::current_dir().expect("Ты как сюда залез?").join("data"));
-        let mut resources = Resources::load(&data_path).await;
+        let resources = Resources::load(&data_path()).await?;
+        let mut world = World::new();
+        let generator = Arc::new(PrefabGenerator {
+            base: generator,
+            prefabs: resources.prefabs.clone(),
+        });
+        let mut map = WorldMap::new(seed, generator, resources.tiles.clone());
+        map.store = Some(save::new_cache(&save.cache_dir())?);
+        map.start_loader(loader_workers());
+        let mut surface = |x, y| Vec3::new(x, y, map.surface_z(x, y));
+        let player_pos = surface(1, 1);
+        let items_pos = [surface(2, 2), surface(2, 3), surface(2, 4)];
+        let nettle_pos = surface(10, 10);
+        let shovel_pos = surface(3, 2);
+        world.spawn((map,));
+        let mut player = new_player();
+        player.add(Position(player_pos));
+        let body = resources
+            .body_templates
+            .get("human")
+            .ok_or(anyhow::anyhow!("Body template human is not found"))?;
+        player.add(body.clone());
+        world.spawn(player.build());
+        for (n, pos) in items_pos.into_iter().enumerate() {
+            let mut item = Item::new(format!("thing{}", n + 1), "item".into());
+            item.add_props(&[("huy".into(), Property::Marker)]);
+            world.spawn(item.to_map_entity(pos.x, pos.y, pos.z));
+        }
+
+        let mut shovel = Item::new("shovel".into(), "item".into());
+        shovel.add_props(&[(DIGGING_PROPERTY.into(), Property::Int(5))]);
+        world.spawn(shovel.to_map_entity(shovel_pos.x, shovel_pos.y, shovel_pos.z));
+
+        resources.spawn(&mut world, "nettle", Position(nettle_pos))?;
+        resources.spawn_prefab_entities(&mut world)?;
+
+        Ok(Game::with_world(world, resources, 0, save))
+    }
+
+    /// Загружает игру, сохранённую в каталоге save_path через Game::save
+    async fn load(save_path: &Path) -> anyhow::Result<Game> {
+        set_skin().await;
+        let resources = Resources::load(&data_path()).await?;
+        let (save, mut world, turn) = SaveSlot::load(
+            save_path.to_owned(),
+            &resources.components,
+            &resources.template_context(),
+            &resources.tiles,
+        )?;
+        if let Some((_, (map,))) = world.query_mut::<(&mut WorldMap,)>().into_iter().next() {
+            map.generator = Arc::new(PrefabGenerator {
+                base: map.generator.clone(),
+                prefabs: resources.prefabs.clone(),
+            });
+            map.start_loader(loader_workers());
+        }
+        Ok(Game::with_world(world, resources, turn, save))
+    }
+
+    fn with_world(world: World, resources: Resources, turn: u64, save: SaveSlot) -> Game {
         let game_systems: GameSystems = vec![GameSystem::InputSystem];
         let world_systems: WorldSystems = vec![
             WorldSystem::Move,
+            WorldSystem::Terrain,
             WorldSystem::FovCompute,
+            WorldSystem::Lighting,
             WorldSystem::Memory,
             WorldSystem::Pathfinding,
             WorldSystem::Attack,
+            WorldSystem::ChunkStreaming,
         ];
-        let mut world = World::new();
-        let map = WorldMap::new();
-        world.spawn((map,));
-        let mut player = new_player();
-        let body = Body::new().with_part(
-            "head".into(),
-            BodyPart::new().with_part(
-                "head".into(),
-                BodyPartPart::new().with_organ("eyes".into(), Organ::new()),
-            ),
-        );
-        player.add(body);
-        world.spawn(player.build());
-        let mut item = Item::new("thing1".into(), "item".into());
-        item.add_props(&[("huy".into(), Property::Marker)]);
-        world.spawn(item.to_map_entity(2, 2, 0));
-        let mut item = Item::new("thing2".into(), "item".into());
-        item.add_props(&[("huy".into(), Property::Marker)]);
-        world.spawn(item.to_map_entity(2, 3, 0));
-        let mut item = Item::new("thing3".into(), "item".into());
-        item.add_props(&[("huy".into(), Property::Marker)]);
-        world.spawn(item.to_map_entity(2, 4, 0));
-
-        let builder = resources.entity_templates.get_mut("nettle").unwrap();
-        world.spawn(builder.build());
-
-        Ok(Game {
+        Game {
             world,
             resources,
             game_systems,
@@ -185,7 +255,22 @@ impl Game {
             is_needed_redraw: Mutex::new(true),
             scale: 1.,
             statistics: Mutex::new(Statistics::new()),
-        })
+            turn,
+            save,
+            is_over: false,
+        }
+    }
+
+    /// Сохраняет игру по желанию игрока
+    fn save(&self) -> Result<(), SaveError> {
+        self.save
+            .save(&self.world, self.turn, &self.resources.components)
+    }
+
+    /// Вызывается при закрытии окна
+    fn exit(&self) -> Result<(), SaveError> {
+        self.save
+            .save_on_exit(&self.world, self.turn, &self.resources.components)
     }
 
     async fn draw(&self) -> anyhow::Result<()> {
@@ -208,13 +293,66 @@ impl Game {
             UIState::No => {}
             UIState::Inventory { ref items } => ui::inventory(items),
             UIState::Log { ref text } => ui::log(text),
-            UIState::Debug => ui::debug(&self.statistics.lock().unwrap().to_owned()),
+            UIState::ChooseDirection(action) => ui::choose_direction(action),
+            UIState::Debug => {
+                let mut info = String::new();
+                let mut maps = self.world.query::<(&WorldMap,)>();
+                if let Some((_, (map,))) = maps.iter().next() {
+                    info.push_str(format!("world seed: {}\n", map.seed).as_str());
+                    info.push_str(
+                        format!(
+                            "chunks: {}, {} KiB\n",
+                            map.chunks.len(),
+                            map.memory_size() / 1024
+                        )
+                        .as_str(),
+                    );
+                    if let Some(loader) = &map.loader {
+                        info.push_str(format!("chunks queued: {}\n", loader.pending()).as_str());
+                    }
+                    let mut players = self
+                        .world
+                        .query::<(&Player, &Position, Option<&MapMemory>)>();
+                    if let Some((_, (_, Position(pos), memory))) = players.iter().next() {
+                        let biome = map.biome_at(pos.x, pos.y, pos.z);
+                        info.push_str(format!("biome: {biome:?}\n").as_str());
+                        if let Some(memory) = memory {
+                            let size = memory.memory_size() / 1024;
+                            info.push_str(format!("map memory: {size} KiB\n").as_str());
+                        }
+                    }
+                }
+                ui::debug(&self.statistics.lock().unwrap().to_owned(), &info)
+            }
+        }
+        Ok(())
+    }
+
+    /// Забирает чанки, сгенерированные в фоне, пока игра ждёт хода игрока.
+    /// Если они появились, поле зрения и свет пересчитываются, чтобы они стали видны.
+    fn receive_chunks(&mut self) -> anyhow::Result<()> {
+        let received = match self
+            .world
+            .query_mut::<(&mut WorldMap,)>()
+            .into_iter()
+            .next()
+        {
+            Some((_, (map,))) => map.receive_chunks(),
+            None => 0,
+        };
+        if received > 0 {
+            self.resources.spawn_prefab_entities(&mut self.world)?;
+            WorldSystem::FovCompute.run(&mut self.world)?;
+            WorldSystem::Lighting.run(&mut self.world)?;
+            WorldSystem::Memory.run(&mut self.world)?;
+            *self.is_needed_redraw.lock().unwrap() = true;
         }
         Ok(())
     }
 
     async fn update(&mut self) -> anyhow::Result<()> {
         if self.is_paused {
+            self.receive_chunks()?;
             let mut is_needed_redraw = self.is_needed_redraw.lock().unwrap();
             match self.next_action {
                 PlayerAction::Move(dir) => {
@@ -280,21 +418,52 @@ impl Game {
                         text: log.0.clone(),
                     }
                 }
-                PlayerAction::CloseLog | PlayerAction::CloseInventory => {
+                PlayerAction::CloseLog
+                | PlayerAction::CloseInventory
+                | PlayerAction::CancelDirection => {
                     self.ui = UIState::No;
                 }
+                PlayerAction::Dig => self.ui = UIState::ChooseDirection(TerrainAction::Dig),
+                PlayerAction::Build => self.ui = UIState::ChooseDirection(TerrainAction::Build),
+                PlayerAction::Direction(dir) => {
+                    if let UIState::ChooseDirection(action) = self.ui {
+                        let mut bind = self.world.query::<(&Player,)>();
+                        let (e, _) = bind
+                            .into_iter()
+                            .next()
+                            .expect("Персонаж потерялся. Как так?");
+                        drop(bind);
+                        match action {
+                            TerrainAction::Dig => self.world.insert_one(e, WantsDig(dir))?,
+                            TerrainAction::Build => self.world.insert_one(e, WantsBuild(dir))?,
+                        }
+                        self.ui = UIState::No;
+                        self.is_paused = false;
+                        *is_needed_redraw = true;
+                    }
+                }
                 PlayerAction::Zoom => {
                     self.scale += 0.1;
                 }
                 PlayerAction::Unzoom => {
                     self.scale -= 0.1;
                 }
+                PlayerAction::Save => match self.save() {
+                    Ok(()) => write_player_log(&mut self.world, "Game saved"),
+                    Err(SaveError::SavingDisabled) => write_player_log(
+                        &mut self.world,
+                        "Game is saved only on exit in responsible mode",
+                    ),
+                    Err(err) => return Err(err.into()),
+                },
                 _ => {}
             }
             drop(is_needed_redraw);
             for system in self.game_systems.clone().iter() {
                 system.run(self)?
             }
+        } else if self.is_over {
+            self.is_paused = true;
         } else {
             for system in self.world_systems.iter() {
                 let now = std::time::Instant::now();
@@ -303,8 +472,15 @@ impl Game {
                 let mut stats = self.statistics.lock().unwrap();
                 stats.update_stat(elapsed, format!("{system:?}"));
             }
+            self.resources.spawn_prefab_entities(&mut self.world)?;
 
+            self.turn += 1;
             self.is_paused = true;
+            if is_player_dead(&self.world) {
+                self.is_over = true;
+                self.save.on_player_death()?;
+                write_player_log(&mut self.world, "You died");
+            }
         }
         Ok(())
     }
@@ -323,10 +499,96 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() -> anyhow::Result<()> {
-    let mut game = Game::new().await?;
+/// Сколько потоков генерируют чанки: все ядра, кроме занятого самой игрой
+fn loader_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1))
+}
+
+/// Каталог с файлами данных игры: рядом с исполняемым файлом или в текущем каталоге.
+fn data_path() -> PathBuf {
+    let exe_path = env::current_exe().expect("Ты ебанутый? Ты что там делаешь?");
+    exe_path
+        .parent()
+        .and_then(|p| p.parent())
+        .map(|p| p.join("data"))
+        .filter(|p| p.exists())
+        .unwrap_or(env::current_dir().expect("Ты как сюда залез?").join("data"))
+}
+
+/// Каталог, в котором хранится сохранённая игра: рядом с каталогом data
+fn save_path() -> PathBuf {
+    data_path().with_file_name("save")
+}
+
+/// Проверка файлов данных без открытия окна: `game123 --check-data [путь к data]`.
+/// Выводит все найденные ошибки и завершается с ошибкой, если они есть.
+fn check_data(data_path: &Path) -> anyhow::Result<()> {
+    let errors = resources::check_data(data_path);
+    for err in errors.iter() {
+        eprintln!("{err}");
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{} errors found in {}", errors.len(), data_path.display());
+    }
+    println!("{}: no errors found", data_path.display());
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let mut mode = GameMode::Normal;
+    let mut seed = None;
+    let mut generator: Arc<dyn ChunkGenerator> = Arc::new(NoiseGenerator);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check-data" => {
+                let data_path = args.next().map(PathBuf::from).unwrap_or_else(data_path);
+                return check_data(&data_path);
+            }
+            "--responsible" => mode = GameMode::Responsible,
+            "--seed" => {
+                let value = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("Argument --seed requires a number"))?;
+                seed = Some(value.parse()?);
+            }
+            "--generator" => {
+                let name = args.next().ok_or(anyhow::anyhow!(
+                    "Argument --generator requires a generator name"
+                ))?;
+                generator = generator_by_name(&name)
+                    .ok_or(anyhow::anyhow!("Unknown world generator {name}"))?;
+            }
+            arg => anyhow::bail!("Unknown argument {arg}"),
+        }
+    }
+    macroquad::Window::from_config(window_conf(), async move {
+        if let Err(err) = run(mode, seed, generator).await {
+            panic!("{err:?}");
+        }
+    });
+    Ok(())
+}
+
+/// mode, seed и generator используются только для новой игры, у сохранённой
+/// игры они свои. Если зерно не задано, оно выбирается случайно.
+async fn run(
+    mode: GameMode,
+    seed: Option<u64>,
+    generator: Arc<dyn ChunkGenerator>,
+) -> anyhow::Result<()> {
+    let save_path = save_path();
+    let mut game = if save::save_exists(&save_path) {
+        Game::load(&save_path).await?
+    } else {
+        let seed = seed.unwrap_or_else(rand::random);
+        Game::new(SaveSlot::new(save_path, mode), seed, generator).await?
+    };
+    prevent_quit();
     loop {
+        if is_quit_requested() {
+            return Ok(game.exit()?);
+        }
         let now = std::time::Instant::now();
         game.update().await?;
         game.draw().await?;
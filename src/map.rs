@@ -1,73 +1,189 @@
-use std::collections::HashMap;
-
-use rand::Rng;
+use std::collections::{HashMap, HashSet};
 
+use serde::{Deserialize, Serialize};
+use vek::Vec3;
 
 use std::sync::{Arc, Mutex};
 
-use crate::{hasher, GameHasher};
+use crate::{
+    biome::Biome,
+    chunk_loader::ChunkLoader,
+    coords, hasher,
+    light::LightMap,
+    planner::WorldPlan,
+    prefabs::PrefabSpawn,
+    region::{RegionError, RegionStore},
+    storage::{ChunkBits, TileStorage, CHUNK_TILES},
+    tiles::{Tile, TileId, TileRegistry},
+    worldgen::ChunkGenerator,
+    GameHasher,
+};
 
 pub const CHUNK_SIZE: usize = 64;
 
-/// Структура, содержащая информацию о тайле. Пока что она имеет только
-/// такие параметры, как имя тайла и название спрайтов, которыми нужно
-/// её отображать. Это будет изменено в ближайшее время.
-#[derive(Clone, Debug)]
-pub struct Tile {
-    pub name: &'static str,
-    /// имя спрайта, которым нужно отображать этот тайл
-    pub full_sprite: &'static str,
-    /// имя спрайта, который рисуется под full_sprite
-    pub fallback_sprite: Option<&'static str>,
-}
-
-impl Tile {
-    pub const fn new(name: &'static str, sprite_name: &'static str) -> Self {
-        Tile {
-            name,
-            full_sprite: sprite_name,
-            fallback_sprite: None,
-        }
-    }
-}
-
 // Карта - это объект, в котором хранится какое-то количество загруженных чанков
 // У каждого чанка есть свои декартовы координаты, и чанк собой являет линейный массив тайлов
 // У тайла есть декартовы координаты, но тайл можно получить только по индексу.
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Chunk {
-    pub tiles: [Arc<Tile>; CHUNK_SIZE.pow(3)], //15x15x15
-    pub obstacles: [bool; CHUNK_SIZE.pow(3)],
+    /// Номера тайлов в реестре тайлов
+    pub tiles: TileStorage,
+    /// Копия blocks_movement тайлов, чтобы не обращаться к реестру при поиске пути
+    pub obstacles: ChunkBits,
+    /// Копия blocks_sight тайлов для поля зрения. Сквозь окно видно, но пройти
+    /// нельзя, поэтому непрозрачность хранится отдельно от препятствий.
+    pub opaque: ChunkBits,
+    /// Индексы тайлов, которые светятся, чтобы не искать источники света
+    /// среди всех тайлов чанка
+    pub lights: Vec<u32>,
 }
 
-impl<'a> Chunk {
-    pub fn get_tile(&'a self, x: i32, y: i32, z: i32) -> &'a Arc<Tile> {
-        let idx = const_xy_index_chunk(x, y, z);
-        unsafe { self.tiles.get_unchecked(idx) }
+impl Chunk {
+    /// Создаёт чанк, тайлы которого задаются функцией от координат тайла внутри
+    /// чанка. Координаты отсчитываются от угла чанка с наименьшими координатами.
+    pub fn from_fn(
+        registry: &TileRegistry,
+        mut tile_at: impl FnMut(usize, usize, usize) -> TileId,
+    ) -> Self {
+        let tiles = (0..CHUNK_TILES).map(|idx| {
+            let (x, y, z) = coords::local_of_index(idx);
+            tile_at(x, y, z)
+        });
+        Self::from_tiles(registry, TileStorage::from_tiles(tiles))
+    }
+    fn from_tiles(registry: &TileRegistry, tiles: TileStorage) -> Self {
+        let bits = |property: fn(&Tile) -> bool| match &tiles {
+            TileStorage::Uniform(tile) => ChunkBits::Uniform(property(registry.get(*tile))),
+            TileStorage::Paletted { .. } => {
+                ChunkBits::from_bits(tiles.iter().map(|tile| property(registry.get(tile))))
+            }
+        };
+        let obstacles = bits(|tile| tile.blocks_movement);
+        let opaque = bits(|tile| tile.blocks_sight);
+        let emits = |tile: TileId| registry.get(tile).light.is_some();
+        let lights = match &tiles {
+            TileStorage::Paletted { palette, .. } if palette.iter().copied().any(emits) => tiles
+                .iter()
+                .enumerate()
+                .filter(|(_, tile)| emits(*tile))
+                .map(|(index, _)| index as u32)
+                .collect(),
+            // Чанк из одного светящегося тайла освещает разве что сам себя,
+            // и сотни тысяч источников в нём не стоят того
+            _ => Vec::new(),
+        };
+        Chunk {
+            tiles,
+            obstacles,
+            opaque,
+            lights,
+        }
     }
+    #[inline]
+    pub fn get_tile(&self, x: i32, y: i32, z: i32) -> TileId {
+        self.tiles.get(coords::index_of(x, y, z))
+    }
+    #[inline]
     pub fn get_obstacle(&self, x: i32, y: i32, z: i32) -> bool {
-        let idx = const_xy_index_chunk(x, y, z);
-        unsafe { *self.obstacles.get_unchecked(idx) }
+        self.obstacles.get(coords::index_of(x, y, z))
+    }
+    /// Заменяет тайл вместе с препятствием, непрозрачностью и светом в нём.
+    /// Возвращает false, если тайл уже был таким.
+    pub fn set_tile(
+        &mut self,
+        registry: &TileRegistry,
+        x: i32,
+        y: i32,
+        z: i32,
+        tile: TileId,
+    ) -> bool {
+        let index = coords::index_of(x, y, z);
+        if self.tiles.get(index) == tile {
+            return false;
+        }
+        self.tiles.set(index, tile);
+        let tile = registry.get(tile);
+        self.obstacles.set(index, tile.blocks_movement);
+        self.opaque.set(index, tile.blocks_sight);
+        self.lights.retain(|&light| light != index as u32);
+        if tile.light.is_some() {
+            self.lights.push(index as u32);
+        }
+        true
     }
-}
 
-const fn const_xy_chunk(x: i32, y: i32, z: i32) -> (i32, i32, i32) {
-    (
-        ((x % CHUNK_SIZE as i32) / (CHUNK_SIZE as i32 / 2) + x / CHUNK_SIZE as i32),
-        ((y % CHUNK_SIZE as i32) / (CHUNK_SIZE as i32 / 2) + y / CHUNK_SIZE as i32),
-        ((z % CHUNK_SIZE as i32) / (CHUNK_SIZE as i32 / 2) + z / CHUNK_SIZE as i32),
-    )
+    /// Сколько байт занимает чанк вместе с данными в куче
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.tiles.heap_size()
+            + self.obstacles.heap_size()
+            + self.opaque.heap_size()
+            + self.lights.capacity() * std::mem::size_of::<u32>()
+    }
+
+    /// Тайлы чанка в том виде, в котором они записываются в сохранение
+    pub fn to_runs(&self, registry: &TileRegistry) -> ChunkRuns {
+        let mut palette: Vec<TileId> = Vec::new();
+        let mut tiles: Vec<(usize, u32)> = Vec::new();
+        for tile in self.tiles.iter() {
+            let index = match palette.iter().position(|id| *id == tile) {
+                Some(index) => index,
+                None => {
+                    palette.push(tile);
+                    palette.len() - 1
+                }
+            };
+            match tiles.last_mut() {
+                Some((last, count)) if *last == index => *count += 1,
+                _ => tiles.push((index, 1)),
+            }
+        }
+        ChunkRuns {
+            palette: palette
+                .into_iter()
+                .map(|id| registry.get(id).name.to_string())
+                .collect(),
+            tiles,
+        }
+    }
+
+    /// Восстанавливает чанк из сохранения. Тайлы ищутся в реестре по именам,
+    /// а препятствия и непрозрачность берутся из текущего описания тайлов.
+    pub fn from_runs(runs: ChunkRuns, registry: &TileRegistry) -> Result<Self, String> {
+        let palette = runs
+            .palette
+            .iter()
+            .map(|name| {
+                registry
+                    .id(name)
+                    .ok_or_else(|| format!("unknown tile {name}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut tiles = Vec::with_capacity(CHUNK_TILES);
+        for (index, count) in runs.tiles {
+            let tile = palette
+                .get(index)
+                .ok_or_else(|| format!("tile #{index} is not in palette"))?;
+            tiles.extend(std::iter::repeat(*tile).take(count as usize));
+        }
+        if tiles.len() != CHUNK_TILES {
+            return Err(format!(
+                "chunk must contain {CHUNK_TILES} tiles, found {}",
+                tiles.len()
+            ));
+        }
+        Ok(Self::from_tiles(registry, TileStorage::from_tiles(tiles)))
+    }
 }
 
-const fn const_xy_index_chunk(x: i32, y: i32, z: i32) -> usize {
-    let ch_x = x.rem_euclid(CHUNK_SIZE as i32);
-    let ch_y = y.rem_euclid(CHUNK_SIZE as i32);
-    let ch_z = z.rem_euclid(CHUNK_SIZE as i32);
-    let index = (ch_x + CHUNK_SIZE as i32 / 2) % CHUNK_SIZE as i32
-        + (ch_y + CHUNK_SIZE as i32 / 2) % CHUNK_SIZE as i32 * CHUNK_SIZE as i32
-        + (ch_z + CHUNK_SIZE as i32 / 2) % CHUNK_SIZE as i32 * CHUNK_SIZE.pow(2) as i32;
-    index as usize
+/// В сохранении тайлы чанка записываются сериями одинаковых тайлов: номер тайла
+/// в palette и длина серии. До версии сохранений 5 рядом записывались и
+/// препятствия, теперь они берутся из описания тайлов.
+#[derive(Serialize, Deserialize)]
+pub struct ChunkRuns {
+    palette: Vec<String>,
+    tiles: Vec<(usize, u32)>,
 }
 
 pub trait Map {
@@ -75,11 +191,12 @@ pub trait Map {
     /// Функция, которая вычисляет координаты чанка от глобальной координаты
     #[inline]
     fn xy_chunk(x: i32, y: i32, z: i32) -> (i32, i32, i32) {
-        const_xy_chunk(x, y, z)
+        coords::chunk_of(x, y, z)
     }
+    /// Индекс тайла с глобальными координатами в массиве тайлов его чанка
     #[inline]
     fn xy_index_chunk(x: i32, y: i32, z: i32) -> usize {
-        const_xy_index_chunk(x, y, z)
+        coords::index_of(x, y, z)
     }
 
     fn get_chunk_or_create(&mut self, x: i32, y: i32, z: i32) -> &Mutex<Self::Chunk>;
@@ -87,81 +204,288 @@ pub trait Map {
 }
 
 pub struct WorldMap {
+    /// Зерно генерации мира. Содержимое каждого чанка зависит только от него
+    /// и от координат чанка, поэтому по зерну можно воспроизвести карту.
+    pub seed: u64,
+    /// Генератор, которым создаются ещё не созданные чанки
+    pub generator: Arc<dyn ChunkGenerator>,
+    /// План мира с биомами, вычисляемый по тому же зерну. Тот же, которым
+    /// пользуется генератор.
+    pub plan: Arc<WorldPlan>,
+    /// Типы тайлов, номера которых хранятся в чанках
+    pub tiles: Arc<TileRegistry>,
     pub chunks: HashMap<(i32, i32, i32), Mutex<Chunk>, GameHasher>,
+    /// Куда выгружаются чанки, далёкие от всех активных сущностей. Без него
+    /// чанки не выгружаются.
+    pub store: Option<RegionStore<ChunkRuns>>,
+    /// На скольких чанках от активной сущности чанки остаются загруженными
+    pub keep_radius: i32,
+    /// Фоновая генерация чанков. Без неё запрошенные чанки генерируются сразу.
+    pub loader: Option<ChunkLoader>,
+    /// Загруженные чанки, которые отличаются от записанных в хранилище или,
+    /// если их там нет, от сгенерированных. Только они записываются при
+    /// выгрузке и сохранении, остальные можно загрузить или сгенерировать заново.
+    pub dirty: HashSet<(i32, i32, i32), GameHasher>,
+    /// Растёт при каждом изменении тайлов и загрузке чанка из хранилища. Кэши,
+    /// построенные по карте (поле зрения, пути), устаревают, когда номер меняется.
+    pub revision: u64,
+    /// Номер изменения карты, при котором в последний раз менялся тайл чанка
+    /// или чанк загружался из хранилища. Выгруженных чанков здесь нет, как и
+    /// сгенерированных, которые с тех пор не менялись.
+    pub chunk_revisions: HashMap<(i32, i32, i32), u64, GameHasher>,
+    /// Сущности префабов и существа биомов из только что сгенерированных
+    /// чанков, которые ещё не созданы в мире
+    pub spawns: Vec<PrefabSpawn>,
+    /// Освещённость загруженной части мира
+    pub light: LightMap,
 }
 
 impl WorldMap {
-    pub fn new() -> Self {
+    pub fn new(seed: u64, generator: Arc<dyn ChunkGenerator>, tiles: Arc<TileRegistry>) -> Self {
+        let plan = generator
+            .plan(seed)
+            .unwrap_or_else(|| WorldPlan::for_seed(seed));
         WorldMap {
+            seed,
+            generator,
+            plan,
+            tiles,
             chunks: HashMap::with_hasher(hasher()),
+            store: None,
+            keep_radius: Self::DEFAULT_KEEP_RADIUS,
+            loader: None,
+            dirty: HashSet::with_hasher(hasher()),
+            revision: 0,
+            chunk_revisions: HashMap::with_hasher(hasher()),
+            spawns: Vec::new(),
+            light: LightMap::new(),
         }
     }
+    pub const DEFAULT_KEEP_RADIUS: i32 = 2;
+
+    /// Запускает фоновую генерацию чанков в workers потоках
+    pub fn start_loader(&mut self, workers: usize) {
+        self.loader = Some(ChunkLoader::new(
+            self.seed,
+            self.generator.clone(),
+            self.tiles.clone(),
+            workers,
+        ));
+    }
+    /// Просит загрузить чанк, не дожидаясь его. Выгруженный чанк загружается
+    /// из хранилища сразу, а новый генерируется в фоне и появляется в карте
+    /// после receive_chunks. Без фоновой генерации чанк генерируется сразу.
+    pub fn request_chunk(&mut self, x: i32, y: i32, z: i32) {
+        if self.chunks.contains_key(&(x, y, z)) || self.restore_stored((x, y, z)) {
+            return;
+        }
+        match &mut self.loader {
+            Some(loader) => loader.request((x, y, z)),
+            None => {
+                self.get_chunk_or_create(x, y, z);
+            }
+        }
+    }
+    /// Добавляет в карту сгенерированные в фоне чанки и возвращает их количество.
+    /// Если чанк успели создать сразу, пока он генерировался, остаётся
+    /// созданный: в нём уже могут быть изменения.
+    pub fn receive_chunks(&mut self) -> usize {
+        let Some(loader) = &mut self.loader else {
+            return 0;
+        };
+        let chunks = loader.receive();
+        let count = chunks.len();
+        for (position, chunk) in chunks {
+            if !self.chunks.contains_key(&position) {
+                self.insert_generated(position, chunk);
+            }
+        }
+        count
+    }
+    /// Добавляет в карту новый чанк и запоминает сущности, которые в нём
+    /// появляются. Такой чанк сразу помечается изменённым: если его потом
+    /// сгенерировать заново, сущности появились бы второй раз.
+    fn insert_generated(&mut self, position: (i32, i32, i32), chunk: Chunk) {
+        let (x, y, z) = position;
+        let spawns = self.generator.spawns(self.seed, &chunk, x, y, z);
+        if !spawns.is_empty() {
+            self.dirty.insert(position);
+            self.spawns.extend(spawns);
+        }
+        self.chunks.insert(position, Mutex::new(chunk));
+    }
+
+    /// Записывает в хранилище и убирает из памяти чанки, для которых keep
+    /// возвращает false. Выгруженные чанки загружаются обратно через
+    /// get_chunk_or_create вместе со всеми изменениями.
+    pub fn unload_chunks(
+        &mut self,
+        keep: impl Fn((i32, i32, i32)) -> bool,
+    ) -> Result<(), RegionError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let unloaded = self
+            .chunks
+            .keys()
+            .copied()
+            .filter(|position| !keep(*position))
+            .collect::<Vec<_>>();
+        store.store(
+            unloaded
+                .iter()
+                .filter(|position| self.dirty.contains(position))
+                .map(|position| {
+                    let chunk = self.chunks[position].lock().unwrap();
+                    (*position, chunk.to_runs(&self.tiles))
+                }),
+        )?;
+        for position in unloaded {
+            self.chunks.remove(&position);
+            self.dirty.remove(&position);
+            self.chunk_revisions.remove(&position);
+        }
+        Ok(())
+    }
+    /// Записывает в target все изменённые чанки карты: и загруженные, и выгруженные
+    pub fn save_regions(&self, target: &RegionStore<ChunkRuns>) -> Result<(), RegionError> {
+        target.clear()?;
+        if let Some(store) = &self.store {
+            store.copy_to(target)?;
+        }
+        target.store(self.dirty.iter().map(|position| {
+            let chunk = self.chunks[position].lock().unwrap();
+            (*position, chunk.to_runs(&self.tiles))
+        }))
+    }
+    /// Загружает выгруженный чанк из хранилища и возвращает, есть ли он там.
+    /// Загрузка считается изменением карты: чанк получает новый номер, которого
+    /// ещё не было, и ни один кэш, построенный до его выгрузки, с ним не совпадёт.
+    /// Если запись чанка испорчена, её регион откладывается (RegionStore::set_aside),
+    /// а чанк генерируется заново по зерну, уже без сущностей префабов,
+    /// и помечается изменённым, чтобы при выгрузке испорченная запись заменилась.
+    /// Изменения в нём теряются, а сообщение об этом попадает в журнал игрока.
+    fn restore_stored(&mut self, position: (i32, i32, i32)) -> bool {
+        let chunk = match self.load_stored(position) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return false,
+            Err(err) => {
+                if let Some(store) = &self.store {
+                    // Неудача уже записана в сообщение для журнала, а поверх
+                    // неотложенного региона выгрузка ничего не запишет
+                    let _ = store.set_aside(err, "the chunk is generated anew");
+                }
+                let (x, y, z) = position;
+                self.dirty.insert(position);
+                self.generator.generate(self.seed, &self.tiles, x, y, z)
+            }
+        };
+        self.chunks.insert(position, Mutex::new(chunk));
+        self.revision += 1;
+        self.chunk_revisions.insert(position, self.revision);
+        true
+    }
+    fn load_stored(&self, position: (i32, i32, i32)) -> Result<Option<Chunk>, RegionError> {
+        let Some(store) = &self.store else {
+            return Ok(None);
+        };
+        let Some(runs) = store.load(position)? else {
+            return Ok(None);
+        };
+        Chunk::from_runs(runs, &self.tiles)
+            .map(Some)
+            .map_err(|message| RegionError::Chunk {
+                dir: store.dir().to_owned(),
+                chunk: position,
+                message,
+            })
+    }
+    /// Препятствие в тайле без загрузки чанка: тайлы незагруженных чанков
+    /// считаются препятствиями
+    pub fn get_loaded_obstacle(&self, x: i32, y: i32, z: i32) -> bool {
+        let (ch_x, ch_y, ch_z) = Self::xy_chunk(x, y, z);
+        self.get_chunk(ch_x, ch_y, ch_z)
+            .map_or(true, |chunk| chunk.lock().unwrap().get_obstacle(x, y, z))
+    }
+    pub fn get_tile_or_create(&mut self, x: i32, y: i32, z: i32) -> TileId {
+        let (ch_x, ch_y, ch_z) = Self::xy_chunk(x, y, z);
+        let chunk = self.get_chunk_or_create(ch_x, ch_y, ch_z).lock().unwrap();
+        chunk.get_tile(x, y, z)
+    }
+    /// Заменяет тайл, загружая его чанк, если нужно. Препятствие и непрозрачность
+    /// берутся из описания нового тайла, чанк помечается изменённым,
+    /// а номер изменения карты растёт.
+    pub fn set_tile(&mut self, pos: Vec3<i32>, tile: TileId) {
+        let tiles = self.tiles.clone();
+        let position = Self::xy_chunk(pos.x, pos.y, pos.z);
+        let mut chunk = self
+            .get_chunk_or_create(position.0, position.1, position.2)
+            .lock()
+            .unwrap();
+        if !chunk.set_tile(&tiles, pos.x, pos.y, pos.z, tile) {
+            return;
+        }
+        drop(chunk);
+        self.dirty.insert(position);
+        self.revision += 1;
+        self.chunk_revisions.insert(position, self.revision);
+    }
+    /// Номер последнего изменения тайлов в прямоугольнике от min до max
+    /// включительно. Кэш, построенный по этой части карты, устаревает, только
+    /// когда этот номер меняется.
+    pub fn revision_in(&self, min: Vec3<i32>, max: Vec3<i32>) -> u64 {
+        let (min, max) = (
+            Self::xy_chunk(min.x, min.y, min.z),
+            Self::xy_chunk(max.x, max.y, max.z),
+        );
+        (min.2..=max.2)
+            .flat_map(|z| (min.1..=max.1).map(move |y| (y, z)))
+            .flat_map(|(y, z)| (min.0..=max.0).map(move |x| (x, y, z)))
+            .filter_map(|position| self.chunk_revisions.get(&position).copied())
+            .max()
+            .unwrap_or(0)
+    }
     pub fn get_obstacle_or_create(&mut self, x: i32, y: i32, z: i32) -> bool {
         let (ch_x, ch_y, ch_z) = Self::xy_chunk(x, y, z);
         let chunk = self.get_chunk_or_create(ch_x, ch_y, ch_z).lock().unwrap();
-        let idx = Self::xy_index_chunk(x, y, z);
-
-        chunk.obstacles[idx]
+        chunk.get_obstacle(x, y, z)
+    }
+    /// Сколько байт занимают все загруженные чанки
+    pub fn memory_size(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|chunk| chunk.lock().unwrap().memory_size())
+            .sum()
+    }
+    /// Биом, к которому относится тайл мира
+    pub fn biome_at(&self, x: i32, y: i32, _z: i32) -> Biome {
+        self.plan.biomes.biome_at(x, y)
+    }
+    /// Высота, на которой можно стоять в столбце (x, y): первый сверху свободный
+    /// тайл над непроходимым. Ищется в пределах нескольких чанков от нулевого уровня.
+    pub fn surface_z(&mut self, x: i32, y: i32) -> i32 {
+        let top = CHUNK_SIZE as i32 * 2;
+        let bottom = -(CHUNK_SIZE as i32) * 2;
+        (bottom..top)
+            .rev()
+            .find(|&z| {
+                !self.get_obstacle_or_create(x, y, z) && self.get_obstacle_or_create(x, y, z - 1)
+            })
+            .unwrap_or(0)
     }
 }
 
 impl Map for WorldMap {
+    /// Выгруженный чанк загружается из хранилища, а ещё не созданный генерируется
     fn get_chunk_or_create(&mut self, x: i32, y: i32, z: i32) -> &Mutex<Chunk> {
-        self.chunks
-            .entry((x, y, z))
-            .or_insert_with(|| Mutex::new(Chunk::new(x, y, z)))
+        if !self.chunks.contains_key(&(x, y, z)) && !self.restore_stored((x, y, z)) {
+            let chunk = self.generator.generate(self.seed, &self.tiles, x, y, z);
+            self.insert_generated((x, y, z), chunk);
+        }
+        &self.chunks[&(x, y, z)]
     }
     fn get_chunk(&self, x: i32, y: i32, z: i32) -> Option<&Mutex<Chunk>> {
         self.chunks.get(&(x, y, z))
     }
     type Chunk = Chunk;
 }
-
-impl Chunk {
-    pub fn new(_ch_x: i32, _ch_y: i32, ch_z: i32) -> Self {
-        let mut tiles = Vec::with_capacity(CHUNK_SIZE.pow(3));
-        let mut obstacles = Vec::with_capacity(CHUNK_SIZE.pow(3));
-        let mut rng = rand::thread_rng();
-
-        let is_sphere_in_chunk = rng.gen_bool(1. / 5.);
-        let in_sphere = {
-            let radius = 2;
-
-            let x_crd = rng.gen_range(radius..CHUNK_SIZE as i32 - radius);
-            let y_crd = rng.gen_range(radius..CHUNK_SIZE as i32 - radius);
-            let z_crd = rng.gen_range(-1..3);
-            move |x: i32, y: i32, z: i32| {
-                (((x - x_crd) * (x - x_crd) + (y - y_crd) * (y - y_crd) + (z - z_crd) * (z - z_crd))
-                    as f64)
-                    .sqrt()
-                    < radius as f64
-            }
-        };
-
-        let wall_tile = Arc::new(Tile::new("wall", "wall"));
-        let empty_tile = Arc::new(Tile::new("empty", "empty"));
-        for z in 0..CHUNK_SIZE {
-            for y in 0..CHUNK_SIZE {
-                for x in 0..CHUNK_SIZE {
-                    let is_ground = ch_z < 0 || (z < CHUNK_SIZE / 2 && ch_z == 0);
-                    let is_wall_on_ground = z == CHUNK_SIZE / 2 && rng.gen_bool(1. / 300.);
-                    let is_sphere_on_ground =
-                        is_sphere_in_chunk && ch_z == 0 && in_sphere(x as i32, y as i32, z as i32);
-                    let is_wall = is_ground || is_wall_on_ground || is_sphere_on_ground;
-                    if is_wall {
-                        tiles.push(wall_tile.clone());
-                        obstacles.push(true);
-                    } else {
-                        tiles.push(empty_tile.clone());
-                        obstacles.push(false);
-                    }
-                }
-            }
-        }
-
-        Chunk {
-            tiles: tiles.try_into().unwrap(),
-            obstacles: obstacles.try_into().unwrap(),
-        }
-    }
-}
@@ -1,17 +1,19 @@
+use serde::{Deserialize, Serialize};
 
+use crate::{items::Item, registry::ComponentRegistry};
 
-
-
-
-
-use crate::{
-    items::Item,
-};
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Mob>("mob");
+    registry.register_default::<Log>("log");
+    registry.register_default::<Inventory>("inventory");
+}
 
 /// Компонент, содержащий историю событий от лица сущности, с которой они происходили.
 /// События записаны в текстовом представлении, отделены переносом строки
+#[derive(Serialize, Deserialize, Default)]
 pub struct Log(pub String);
 
+#[derive(Serialize, Deserialize, Default)]
 pub struct Inventory(pub Vec<Item>);
 
 impl Log {
@@ -22,4 +24,5 @@ impl Log {
 
 /// Компонент, означающий, что сущность с этим компонентом - как-либо действующиее
 /// существо. Это может быть игрок или неигровой персонаж.
+#[derive(Serialize, Deserialize)]
 pub struct Mob;
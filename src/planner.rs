@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use vek::Vec2;
+
+use crate::{
+    biome::{Biome, BiomeMap},
+    hasher,
+    worldgen::chunk_seed,
+    GameHasher,
+};
+
+// План мира: поселения и дороги между ними. Он охватывает много чанков, но
+// целиком не строится: любой его кусок вычисляется по зерну из окрестных
+// областей, поэтому генератор чанка узнаёт, где проходят дороги, не заглядывая
+// в соседние чанки, и дорога одинаково продолжается в соседнем чанке, в каком
+// бы порядке чанки ни генерировались.
+
+/// Сторона квадратной области плана. В области не больше одного поселения,
+/// но стоит оно в случайном месте, поэтому поселения не выстраиваются в сетку.
+pub const PLAN_REGION: i32 = 384;
+/// Вероятность, что в области есть поселение
+const SETTLEMENT_CHANCE: f64 = 0.7;
+/// Наименьший и наибольший радиус поселения
+const SETTLEMENT_RADIUS: (i32, i32) = (16, 40);
+/// Дороги длиннее не прокладываются
+pub const MAX_ROAD: i32 = PLAN_REGION * 3 / 2;
+/// Насколько далеко от оси дороги тянется её полотно
+pub const ROAD_HALF_WIDTH: f64 = 1.5;
+/// Из скольких прямых отрезков состоит изогнутая дорога
+const ROAD_SEGMENTS: usize = 8;
+/// Слои зерна, чтобы план не совпадал с другими случайными решениями генерации
+const SETTLEMENT_LAYER: i32 = 6;
+const ROAD_LAYER: i32 = 7;
+
+/// Поселение: круг, внутри которого чаще стоят постройки
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settlement {
+    pub center: Vec2<i32>,
+    pub radius: i32,
+}
+
+impl Settlement {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let d = Vec2::new(x, y) - self.center;
+        d.x as i64 * d.x as i64 + d.y as i64 * d.y as i64 <= (self.radius as i64).pow(2)
+    }
+}
+
+/// Дорога между центрами двух поселений. Ось дороги - ломаная, которая
+/// приближает плавную кривую, чтобы дороги не были прямыми как по линейке.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Road {
+    pub from: Vec2<i32>,
+    pub to: Vec2<i32>,
+    pub points: Vec<Vec2<f64>>,
+}
+
+impl Road {
+    /// Расстояние от центра тайла до оси дороги
+    pub fn distance(&self, x: i32, y: i32) -> f64 {
+        let p = Vec2::new(x as f64, y as f64);
+        self.points
+            .windows(2)
+            .map(|segment| {
+                let (a, b) = (segment[0], segment[1]);
+                let ab = b - a;
+                let t = ((p - a).dot(ab) / ab.magnitude_squared().max(f64::EPSILON)).clamp(0., 1.);
+                (a + ab * t).distance(p)
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Лежит ли тайл на полотне дороги
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.distance(x, y) <= ROAD_HALF_WIDTH
+    }
+
+    /// Задевает ли полотно дороги прямоугольник от min до max включительно
+    pub fn intersects(&self, min: Vec2<i32>, max: Vec2<i32>) -> bool {
+        let (min, max) = (
+            min.map(|c| c as f64 - ROAD_HALF_WIDTH),
+            max.map(|c| c as f64 + ROAD_HALF_WIDTH),
+        );
+        self.points.windows(2).any(|segment| {
+            let (a, b) = (segment[0], segment[1]);
+            let (low, high) = (
+                Vec2::<f64>::partial_min(a, b),
+                Vec2::<f64>::partial_max(a, b),
+            );
+            low.x <= max.x && high.x >= min.x && low.y <= max.y && high.y >= min.y
+        })
+    }
+}
+
+/// Поселения и дороги мира, которые целиком определяются зерном мира
+pub struct WorldPlan {
+    seed: u64,
+    pub biomes: BiomeMap,
+}
+
+/// Поселения уже рассмотренных областей, чтобы не считать их заново
+type Settlements = HashMap<(i32, i32), Option<Settlement>, GameHasher>;
+
+impl WorldPlan {
+    pub fn new(seed: u64) -> Self {
+        WorldPlan {
+            seed,
+            biomes: BiomeMap::new(seed),
+        }
+    }
+
+    /// Общий для всех потоков план мира с этим зерном. Он строится заново,
+    /// только когда зерно меняется, а обычно в игре зерно одно.
+    pub fn for_seed(seed: u64) -> Arc<WorldPlan> {
+        static LAST: Mutex<Option<Arc<WorldPlan>>> = Mutex::new(None);
+        let mut last = LAST.lock().unwrap();
+        match &*last {
+            Some(plan) if plan.seed == seed => plan.clone(),
+            _ => {
+                let plan = Arc::new(WorldPlan::new(seed));
+                *last = Some(plan.clone());
+                plan
+            }
+        }
+    }
+
+    /// Поселение в области (region_x, region_y). В воронках и болотах
+    /// поселений нет.
+    pub fn settlement(&self, region_x: i32, region_y: i32) -> Option<Settlement> {
+        let mut rng =
+            StdRng::seed_from_u64(chunk_seed(self.seed, region_x, region_y, SETTLEMENT_LAYER));
+        if !rng.gen_bool(SETTLEMENT_CHANCE) {
+            return None;
+        }
+        let radius = rng.gen_range(SETTLEMENT_RADIUS.0..=SETTLEMENT_RADIUS.1);
+        // Поселение целиком лежит в своей области
+        let offset = Vec2::new(
+            rng.gen_range(radius..PLAN_REGION - radius),
+            rng.gen_range(radius..PLAN_REGION - radius),
+        );
+        let center = Vec2::new(region_x, region_y) * PLAN_REGION + offset;
+        match self.biomes.biome_at(center.x, center.y) {
+            Biome::Crater | Biome::ToxicSwamp => None,
+            _ => Some(Settlement { center, radius }),
+        }
+    }
+
+    /// Поселение, в которое попадает тайл
+    pub fn settlement_at(&self, x: i32, y: i32) -> Option<Settlement> {
+        let (region_x, region_y) = (x.div_euclid(PLAN_REGION), y.div_euclid(PLAN_REGION));
+        self.settlement(region_x, region_y)
+            .filter(|settlement| settlement.contains(x, y))
+    }
+
+    /// Поселения, области которых задевают прямоугольник от min до max включительно
+    pub fn settlements_in(&self, min: Vec2<i32>, max: Vec2<i32>) -> Vec<Settlement> {
+        let (min, max) = (region_of(min), region_of(max));
+        (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| (x, y)))
+            .filter_map(|(x, y)| self.settlement(x, y))
+            .collect()
+    }
+
+    /// Дороги, полотно которых задевает прямоугольник от min до max включительно.
+    ///
+    /// Два поселения соединяются дорогой, если между ними не дальше MAX_ROAD
+    /// и нет третьего поселения, которое ближе к каждому из них, чем они друг
+    /// к другу. Такие дороги связывают каждое поселение с ближайшим соседом
+    /// и не идут в обход через третье поселение, а решение о каждой дороге
+    /// зависит только от поселений в окрестных областях.
+    pub fn roads_in(&self, min: Vec2<i32>, max: Vec2<i32>) -> Vec<Road> {
+        // Изгиб уводит дорогу от прямой не больше чем на четверть её длины
+        let reach = MAX_ROAD + MAX_ROAD / 4 + 2;
+        let (from, to) = (region_of(min - reach), region_of(max + reach));
+        let mut settlements = Settlements::with_hasher(hasher());
+        let mut roads = Vec::new();
+        for region_y in from.y..=to.y {
+            for region_x in from.x..=to.x {
+                let Some(a) = self.cached(&mut settlements, (region_x, region_y)) else {
+                    continue;
+                };
+                let nearby = self.neighbours(&mut settlements, (region_x, region_y));
+                for &b in nearby.iter() {
+                    // Каждая дорога записывается один раз, от меньшего из концов
+                    if (a.center.x, a.center.y) >= (b.center.x, b.center.y)
+                        || !are_connected(a, b, &nearby)
+                    {
+                        continue;
+                    }
+                    let road = self.road(a.center, b.center);
+                    if road.intersects(min, max) {
+                        roads.push(road);
+                    }
+                }
+            }
+        }
+        roads
+    }
+
+    fn cached(&self, settlements: &mut Settlements, region: (i32, i32)) -> Option<Settlement> {
+        *settlements
+            .entry(region)
+            .or_insert_with(|| self.settlement(region.0, region.1))
+    }
+
+    /// Поселения в областях, из которых до области region может дойти дорога
+    fn neighbours(
+        &self,
+        settlements: &mut Settlements,
+        (region_x, region_y): (i32, i32),
+    ) -> Vec<Settlement> {
+        let reach = MAX_ROAD / PLAN_REGION + 1;
+        (-reach..=reach)
+            .flat_map(|dy| (-reach..=reach).map(move |dx| (region_x + dx, region_y + dy)))
+            .filter_map(|region| self.cached(settlements, region))
+            .collect()
+    }
+
+    /// Ось дороги между двумя точками: квадратичная кривая Безье, изогнутая
+    /// в случайную сторону. Изгиб зависит только от концов дороги.
+    fn road(&self, from: Vec2<i32>, to: Vec2<i32>) -> Road {
+        let seed = chunk_seed(self.seed, from.x, from.y, ROAD_LAYER);
+        let mut rng = StdRng::seed_from_u64(chunk_seed(seed, to.x, to.y, ROAD_LAYER));
+        let (start, end) = (from.as_::<f64>(), to.as_::<f64>());
+        let direction = end - start;
+        let normal = Vec2::new(-direction.y, direction.x);
+        let control = (start + end) / 2. + normal * rng.gen_range(-0.25..=0.25);
+        let points = (0..=ROAD_SEGMENTS)
+            .map(|step| {
+                let t = step as f64 / ROAD_SEGMENTS as f64;
+                start * (1. - t).powi(2) + control * 2. * t * (1. - t) + end * t.powi(2)
+            })
+            .collect();
+        Road { from, to, points }
+    }
+}
+
+/// Соединяются ли поселения a и b дорогой. В nearby должны быть все соседи a.
+fn are_connected(a: Settlement, b: Settlement, nearby: &[Settlement]) -> bool {
+    let length = distance_squared(a.center, b.center);
+    if length > (MAX_ROAD as i64).pow(2) {
+        return false;
+    }
+    // Третье поселение ближе к a, чем b, поэтому оно среди соседей a
+    !nearby.iter().any(|&c| {
+        c != a
+            && c != b
+            && distance_squared(a.center, c.center) < length
+            && distance_squared(b.center, c.center) < length
+    })
+}
+
+fn region_of(pos: Vec2<i32>) -> Vec2<i32> {
+    pos.map(|c| c.div_euclid(PLAN_REGION))
+}
+
+fn distance_squared(a: Vec2<i32>, b: Vec2<i32>) -> i64 {
+    let d = (b - a).as_::<i64>();
+    d.x * d.x + d.y * d.y
+}
@@ -1,30 +1,45 @@
-use std::{collections::HashSet, sync::Arc};
+use std::sync::Arc;
 
 use hecs::{EntityBuilder, World};
+use serde::{Deserialize, Serialize};
 use vek::Vec3;
 
 use crate::{
     components::Position,
-    hasher,
     items::Item,
+    light::Light,
     mob::{Inventory, Log},
     need_components,
-    systems::{fov_compute::Sight, memory::MapMemory, render::Renderable},
+    registry::ComponentRegistry,
+    systems::{
+        fov_compute::Sight, health::Dead, lighting::LightSource, memory::MapMemory,
+        render::Renderable,
+    },
     Mob,
 };
 
 /// Компонент, означающий, что сущность с этим компонентом - управляема игроком.
 /// Ожидается, что она должна встречаться только один раз в игре.
+#[derive(Serialize, Deserialize)]
 pub struct Player;
 
-/// Компонент, содержащий историю событий от лица сущности, с которой они происходили.
-/// События записаны в текстовом представлении, отделены переносом строки
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Player>("player");
+}
+
+/// Фонарь, с которым персонаж начинает игру: без него в темноте ничего не видно
+pub const PLAYER_LANTERN: Light = Light {
+    radius: 12,
+    color: 0xFFE0B0,
+    intensity: 1.,
+};
 
 pub fn new_player() -> EntityBuilder {
     let mut ebuilder = EntityBuilder::new();
     ebuilder.add_bundle((
         Position(Vec3::new(1, 1, 0)),
-        Sight(40, HashSet::with_hasher(hasher())),
+        Sight::new(40),
+        LightSource(PLAYER_LANTERN),
         Renderable(Arc::from("person")),
         Player,
         Mob,
@@ -35,6 +50,22 @@ pub fn new_player() -> EntityBuilder {
     ebuilder
 }
 
+/// Умер ли персонаж игрока. Если персонажа нет в мире, он тоже считается мёртвым.
+pub fn is_player_dead(world: &World) -> bool {
+    let mut query = world.query::<(&Player, Option<&Dead>)>();
+    match query.iter().next() {
+        Some((_, (_, dead))) => dead.is_some(),
+        None => true,
+    }
+}
+
+/// Записывает событие в журнал персонажа игрока
+pub fn write_player_log(world: &mut World, event: &str) {
+    if let Some((_, (_, log))) = world.query_mut::<(&Player, &mut Log)>().into_iter().next() {
+        log.write(event);
+    }
+}
+
 pub fn get_player_items(world: &World) -> anyhow::Result<Vec<Item>> {
     let mut binding = world.query::<(&Player, &Inventory)>();
     let (_, (_, Inventory(vec))) = binding.into_iter().next().ok_or(need_components!(
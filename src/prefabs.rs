@@ -0,0 +1,491 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use thiserror::Error;
+use vek::Vec3;
+
+use crate::{
+    biome::{Biome, BiomeMap},
+    planner::WorldPlan,
+    resources::EntityTemplate,
+    tiles::{TileId, TileRegistry},
+    worldgen::chunk_seed,
+};
+
+/// Сторона квадратной ячейки сетки размещения. В каждой ячейке стоит не больше
+/// одного префаба, и префаб целиком помещается в свою ячейку, поэтому префабы
+/// не пересекаются друг с другом.
+pub const PREFAB_CELL: i32 = 48;
+/// Вероятность, что в ячейке сетки окажется префаб
+pub const DEFAULT_CELL_CHANCE: f64 = 0.2;
+/// Вероятность префаба в ячейке, середина которой лежит в поселении
+const SETTLEMENT_CELL_CHANCE: f64 = 0.8;
+/// Символ слоя, который оставляет тайл генератора как есть. Не пробел, потому
+/// что YAML срезает пробелы в начале многострочных значений.
+const KEEP_SYMBOL: char = '_';
+
+/// Постройка из нескольких тайлов, которую генератор мира ставит на поверхность:
+/// дом, бункер, остов машины. Описывается в файле prefabs.yaml.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefab {
+    pub name: Arc<str>,
+    /// Относительная частота среди префабов, подходящих для биома
+    pub weight: u32,
+    /// Префаб можно поворачивать на четверть оборота
+    pub rotate: bool,
+    /// На сколько слоёв префаб уходит под поверхность
+    pub depth: i32,
+    /// Биомы, в которых встречается префаб. Пустой список - во всех.
+    pub biomes: Vec<Biome>,
+    /// Варианты префаба, один из которых выбирается случайно
+    pub variants: Vec<PrefabVariant>,
+}
+
+/// Один вариант префаба: тайлы по слоям и точки появления сущностей
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefabVariant {
+    /// Размер по x, y и z
+    pub size: Vec3<i32>,
+    /// Тайлы с индексом x + y * size.x + z * size.x * size.y. None оставляет
+    /// тайл, созданный генератором.
+    pub tiles: Vec<Option<TileId>>,
+    pub spawns: Vec<PrefabSpawn>,
+}
+
+/// Сущность, которая создаётся по шаблону из templates.yaml. В варианте
+/// префаба позиция отсчитывается от его угла, в размещённом - глобальная.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrefabSpawn {
+    pub template: Arc<str>,
+    pub position: Vec3<i32>,
+}
+
+/// Описание префаба в prefabs.yaml. Имя префаба записывается ключом.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrefabConfig {
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    rotate: bool,
+    #[serde(default)]
+    depth: u32,
+    #[serde(default)]
+    biomes: Vec<Biome>,
+    /// Какой тайл означает символ в слоях
+    legend: BTreeMap<char, String>,
+    variants: Vec<VariantConfig>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VariantConfig {
+    /// Слои снизу вверх. Строка слоя - ряд тайлов вдоль x, ряды идут вдоль y.
+    layers: Vec<String>,
+    #[serde(default)]
+    spawns: Vec<SpawnConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpawnConfig {
+    template: String,
+    at: (i32, i32, i32),
+}
+
+#[derive(Error, Debug)]
+pub enum PrefabError {
+    #[error("{}: can't read file: {source}", file.display())]
+    Io {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: file is corrupted: {source}", file.display())]
+    Yaml {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: prefab names must be strings, found {name:?}", file.display())]
+    Name { file: PathBuf, name: Value },
+    #[error("{}: prefab {prefab}: {source}", file.display())]
+    Prefab {
+        file: PathBuf,
+        prefab: String,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: prefab {prefab} must have at least one variant", file.display())]
+    NoVariants { file: PathBuf, prefab: String },
+    #[error("{}: prefab {prefab} uses unknown tile {tile}", file.display())]
+    UnknownTile {
+        file: PathBuf,
+        prefab: String,
+        tile: String,
+    },
+    #[error("{}: prefab {prefab}, variant #{variant}: {message}", file.display())]
+    Layers {
+        file: PathBuf,
+        prefab: String,
+        variant: usize,
+        message: String,
+    },
+    #[error("{}: prefab {prefab}, variant #{variant}: symbol {symbol:?} is not in the legend", file.display())]
+    UnknownSymbol {
+        file: PathBuf,
+        prefab: String,
+        variant: usize,
+        symbol: char,
+    },
+    #[error("{}: prefab {prefab} spawns unknown entity template {template}", file.display())]
+    UnknownTemplate {
+        file: PathBuf,
+        prefab: String,
+        template: String,
+    },
+    #[error("{}: prefab {prefab}, variant #{variant}: spawn point {at:?} is outside the prefab", file.display())]
+    SpawnOutside {
+        file: PathBuf,
+        prefab: String,
+        variant: usize,
+        at: (i32, i32, i32),
+    },
+}
+
+/// Все префабы игры в порядке описания в prefabs.yaml. От порядка зависит,
+/// какой префаб выберется в ячейке, поэтому мир с тем же зерном совпадает,
+/// только пока файл не меняется.
+#[derive(Debug)]
+pub struct PrefabRegistry {
+    pub prefabs: Vec<Prefab>,
+    /// Вероятность, что в ячейке сетки окажется префаб
+    pub cell_chance: f64,
+}
+
+impl Default for PrefabRegistry {
+    fn default() -> Self {
+        PrefabRegistry {
+            prefabs: Vec::new(),
+            cell_chance: DEFAULT_CELL_CHANCE,
+        }
+    }
+}
+
+/// Префаб, размещённый в ячейке сетки
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrefabPlacement {
+    /// Номер префаба в реестре
+    pub prefab: usize,
+    pub variant: usize,
+    /// Поворот по часовой стрелке в четвертях оборота, от 0 до 3
+    pub rotation: u8,
+    /// Угол префаба с наименьшими координатами
+    pub origin: Vec3<i32>,
+    /// Размер уже повёрнутого префаба
+    pub size: Vec3<i32>,
+}
+
+impl PrefabRegistry {
+    /// Разбирает содержимое prefabs.yaml. Тайлы легенды ищутся в tiles,
+    /// а шаблоны точек появления - среди templates. Путь к файлу используется
+    /// только в сообщениях об ошибках.
+    pub fn parse(
+        file: &Path,
+        source: &str,
+        tiles: &TileRegistry,
+        templates: &BTreeMap<Arc<str>, EntityTemplate>,
+    ) -> Result<Self, PrefabError> {
+        let configs: Mapping =
+            serde_yaml::from_str(source).map_err(|source| PrefabError::Yaml {
+                file: file.to_owned(),
+                source,
+            })?;
+        let mut registry = PrefabRegistry::default();
+        for (name, config) in configs {
+            let Value::String(name) = name else {
+                return Err(PrefabError::Name {
+                    file: file.to_owned(),
+                    name,
+                });
+            };
+            let config: PrefabConfig =
+                serde_yaml::from_value(config).map_err(|source| PrefabError::Prefab {
+                    file: file.to_owned(),
+                    prefab: name.clone(),
+                    source,
+                })?;
+            let prefab = parse_prefab(file, &name, config, tiles, templates)?;
+            registry.prefabs.push(prefab);
+        }
+        Ok(registry)
+    }
+
+    /// Префаб в ячейке сетки (cell_x, cell_y) или None, если ячейка пуста.
+    /// Размещение зависит только от зерна мира и ячейки, поэтому все чанки,
+    /// которые задевает префаб, получают одно и то же размещение.
+    /// ground_level - высота, на которой можно стоять в столбце (x, y).
+    /// Если у мира есть план, в поселениях префабы стоят чаще, а на дорогах
+    /// их нет совсем.
+    pub fn placement(
+        &self,
+        seed: u64,
+        biomes: &BiomeMap,
+        plan: Option<&WorldPlan>,
+        cell_x: i32,
+        cell_y: i32,
+        ground_level: impl Fn(i32, i32) -> Option<i32>,
+    ) -> Option<PrefabPlacement> {
+        const PREFAB_LAYER: i32 = 5;
+        let mut rng = StdRng::seed_from_u64(chunk_seed(seed, cell_x, cell_y, PREFAB_LAYER));
+        let corner = Vec3::new(cell_x, cell_y, 0) * PREFAB_CELL;
+        let center = corner.xy() + PREFAB_CELL / 2;
+        let chance = match plan.and_then(|plan| plan.settlement_at(center.x, center.y)) {
+            Some(_) => self.cell_chance.max(SETTLEMENT_CELL_CHANCE),
+            None => self.cell_chance,
+        };
+        if !rng.gen_bool(chance) {
+            return None;
+        }
+        let biome = biomes.biome_at(center.x, center.y);
+        let weights = self.prefabs.iter().map(|prefab| {
+            if prefab.biomes.is_empty() || prefab.biomes.contains(&biome) {
+                prefab.weight
+            } else {
+                0
+            }
+        });
+        // Ошибка означает, что для биома нет ни одного префаба
+        let index = WeightedIndex::new(weights).ok()?.sample(&mut rng);
+        let prefab = &self.prefabs[index];
+        let variant = rng.gen_range(0..prefab.variants.len());
+        let rotation = if prefab.rotate {
+            rng.gen_range(0..4)
+        } else {
+            0
+        };
+        let size = rotated_size(prefab.variants[variant].size, rotation);
+        let mut origin = Vec3::new(
+            corner.x + rng.gen_range(0..=PREFAB_CELL - size.x),
+            corner.y + rng.gen_range(0..=PREFAB_CELL - size.y),
+            0,
+        );
+        if let Some(plan) = plan {
+            let (min, max) = (origin.xy(), origin.xy() + size.xy() - 1);
+            let crosses_road = plan
+                .roads_in(min, max)
+                .iter()
+                .any(|road| (min.y..=max.y).any(|y| (min.x..=max.x).any(|x| road.contains(x, y))));
+            if crosses_road {
+                return None;
+            }
+        }
+        origin.z = ground_level(origin.x + size.x / 2, origin.y + size.y / 2)? - prefab.depth;
+        Some(PrefabPlacement {
+            prefab: index,
+            variant,
+            rotation,
+            origin,
+            size,
+        })
+    }
+
+    /// Тайлы размещённого префаба с глобальными координатами. Тайлы, которые
+    /// остаются от генератора, пропускаются.
+    pub fn placed_tiles<'a>(
+        &'a self,
+        placement: &'a PrefabPlacement,
+    ) -> impl Iterator<Item = (Vec3<i32>, TileId)> + 'a {
+        let variant = &self.prefabs[placement.prefab].variants[placement.variant];
+        variant
+            .tiles
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, tile)| {
+                let index = index as i32;
+                let (width, area) = (variant.size.x, variant.size.x * variant.size.y);
+                let offset = Vec3::new(index % width, index % area / width, index / area);
+                tile.map(|tile| (placement.world_position(variant.size, offset), tile))
+            })
+    }
+
+    /// Точки появления сущностей размещённого префаба с глобальными координатами
+    pub fn placed_spawns<'a>(
+        &'a self,
+        placement: &'a PrefabPlacement,
+    ) -> impl Iterator<Item = PrefabSpawn> + 'a {
+        let variant = &self.prefabs[placement.prefab].variants[placement.variant];
+        variant.spawns.iter().map(move |spawn| PrefabSpawn {
+            template: spawn.template.clone(),
+            position: placement.world_position(variant.size, spawn.position),
+        })
+    }
+}
+
+impl PrefabPlacement {
+    /// Глобальная позиция тайла с координатами offset внутри неповёрнутого
+    /// варианта размера size
+    fn world_position(&self, size: Vec3<i32>, offset: Vec3<i32>) -> Vec3<i32> {
+        let (x, y) = (offset.x, offset.y);
+        let (rx, ry) = match self.rotation {
+            0 => (x, y),
+            1 => (size.y - 1 - y, x),
+            2 => (size.x - 1 - x, size.y - 1 - y),
+            _ => (y, size.x - 1 - x),
+        };
+        self.origin + Vec3::new(rx, ry, offset.z)
+    }
+
+    /// Задевает ли префаб параллелепипед от min до max включительно
+    pub fn intersects(&self, min: Vec3<i32>, max: Vec3<i32>) -> bool {
+        let end = self.origin + self.size - 1;
+        (0..3).all(|axis| self.origin[axis] <= max[axis] && end[axis] >= min[axis])
+    }
+}
+
+fn rotated_size(size: Vec3<i32>, rotation: u8) -> Vec3<i32> {
+    if rotation % 2 == 1 {
+        Vec3::new(size.y, size.x, size.z)
+    } else {
+        size
+    }
+}
+
+fn parse_prefab(
+    file: &Path,
+    name: &str,
+    config: PrefabConfig,
+    tiles: &TileRegistry,
+    templates: &BTreeMap<Arc<str>, EntityTemplate>,
+) -> Result<Prefab, PrefabError> {
+    if config.variants.is_empty() {
+        return Err(PrefabError::NoVariants {
+            file: file.to_owned(),
+            prefab: name.to_owned(),
+        });
+    }
+    let mut legend = BTreeMap::new();
+    for (symbol, tile) in config.legend {
+        let id = tiles.id(&tile).ok_or_else(|| PrefabError::UnknownTile {
+            file: file.to_owned(),
+            prefab: name.to_owned(),
+            tile,
+        })?;
+        legend.insert(symbol, id);
+    }
+    let variants = config
+        .variants
+        .into_iter()
+        .enumerate()
+        .map(|(index, variant)| parse_variant(file, name, index, variant, &legend, templates))
+        .collect::<Result<_, _>>()?;
+    Ok(Prefab {
+        name: name.into(),
+        weight: config.weight,
+        rotate: config.rotate,
+        depth: config.depth as i32,
+        biomes: config.biomes,
+        variants,
+    })
+}
+
+fn parse_variant(
+    file: &Path,
+    prefab: &str,
+    variant: usize,
+    config: VariantConfig,
+    legend: &BTreeMap<char, TileId>,
+    templates: &BTreeMap<Arc<str>, EntityTemplate>,
+) -> Result<PrefabVariant, PrefabError> {
+    let layers_error = |message: String| PrefabError::Layers {
+        file: file.to_owned(),
+        prefab: prefab.to_owned(),
+        variant,
+        message,
+    };
+    let layers = config
+        .layers
+        .iter()
+        .map(|layer| layer.lines().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let height = layers.first().map_or(0, Vec::len);
+    let width = layers
+        .iter()
+        .flatten()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    if height == 0 || width == 0 {
+        return Err(layers_error("layers must not be empty".to_owned()));
+    }
+    if width > PREFAB_CELL as usize || height > PREFAB_CELL as usize {
+        return Err(layers_error(format!(
+            "prefab must fit into {PREFAB_CELL}x{PREFAB_CELL} tiles"
+        )));
+    }
+    let mut tiles = Vec::with_capacity(width * height * layers.len());
+    for (z, layer) in layers.iter().enumerate() {
+        if layer.len() != height {
+            return Err(layers_error(format!(
+                "layer #{z} has {} rows instead of {height}",
+                layer.len()
+            )));
+        }
+        for row in layer {
+            // Недописанный конец ряда тоже оставляет тайлы генератора
+            let symbols = row.chars().chain(std::iter::repeat(KEEP_SYMBOL));
+            for symbol in symbols.take(width) {
+                if symbol == KEEP_SYMBOL {
+                    tiles.push(None);
+                    continue;
+                }
+                let tile = legend.get(&symbol).ok_or(PrefabError::UnknownSymbol {
+                    file: file.to_owned(),
+                    prefab: prefab.to_owned(),
+                    variant,
+                    symbol,
+                })?;
+                tiles.push(Some(*tile));
+            }
+        }
+    }
+    let size = Vec3::new(width as i32, height as i32, layers.len() as i32);
+    let spawns = config
+        .spawns
+        .into_iter()
+        .map(|spawn| {
+            if !templates.contains_key(spawn.template.as_str()) {
+                return Err(PrefabError::UnknownTemplate {
+                    file: file.to_owned(),
+                    prefab: prefab.to_owned(),
+                    template: spawn.template,
+                });
+            }
+            let position = Vec3::from(spawn.at);
+            if (0..3).any(|axis| position[axis] < 0 || position[axis] >= size[axis]) {
+                return Err(PrefabError::SpawnOutside {
+                    file: file.to_owned(),
+                    prefab: prefab.to_owned(),
+                    variant,
+                    at: spawn.at,
+                });
+            }
+            Ok(PrefabSpawn {
+                template: spawn.template.into(),
+                position,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(PrefabVariant {
+        size,
+        tiles,
+        spawns,
+    })
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+/// Сколько чанков по каждой оси попадает в один файл региона
+pub const REGION_SIZE: i32 = 2;
+
+/// Регион, в который попадает чанк
+pub fn region_of((x, y, z): (i32, i32, i32)) -> (i32, i32, i32) {
+    (
+        x.div_euclid(REGION_SIZE),
+        y.div_euclid(REGION_SIZE),
+        z.div_euclid(REGION_SIZE),
+    )
+}
+
+#[derive(Error, Debug)]
+pub enum RegionError {
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: file is corrupted: {source}", path.display())]
+    Yaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: chunk {chunk:?}: {message}", dir.display())]
+    Chunk {
+        dir: PathBuf,
+        chunk: (i32, i32, i32),
+        message: String,
+    },
+}
+
+/// Запись о чанке в файле региона
+#[derive(Serialize, Deserialize)]
+struct RegionEntry<R> {
+    chunk: (i32, i32, i32),
+    data: R,
+}
+
+/// Чанки, выгруженные на диск. Чанки хранятся группами по REGION_SIZE³
+/// в файлах регионов `x_y_z.yaml`, чтобы не заводить файл на каждый чанк.
+/// R - то, во что превращается чанк при записи.
+pub struct RegionStore<R> {
+    dir: PathBuf,
+    /// Сообщения об испорченных записях, которые ещё не попали в журнал игрока
+    corrupted: Mutex<Vec<String>>,
+    data: PhantomData<fn() -> R>,
+}
+
+impl<R: Serialize + DeserializeOwned> RegionStore<R> {
+    pub fn new(dir: PathBuf) -> Self {
+        RegionStore {
+            dir,
+            corrupted: Mutex::new(Vec::new()),
+            data: PhantomData,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Загружает чанк, если он был записан
+    pub fn load(&self, chunk: (i32, i32, i32)) -> Result<Option<R>, RegionError> {
+        let entries = self.read_region(&self.region_path(region_of(chunk)))?;
+        Ok(entries
+            .into_iter()
+            .find(|entry| entry.chunk == chunk)
+            .map(|entry| entry.data))
+    }
+
+    /// Записывает чанки, заменяя их прошлые записи
+    pub fn store(
+        &self,
+        chunks: impl IntoIterator<Item = ((i32, i32, i32), R)>,
+    ) -> Result<(), RegionError> {
+        let mut regions: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (chunk, data) in chunks {
+            regions
+                .entry(region_of(chunk))
+                .or_default()
+                .push(RegionEntry { chunk, data });
+        }
+        if regions.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir).map_err(|source| RegionError::Io {
+            path: self.dir.clone(),
+            source,
+        })?;
+        for (region, new_entries) in regions {
+            let path = self.region_path(region);
+            // Испорченный регион не прочитать: он откладывается и записывается
+            // заново без прежних записей
+            let mut entries = match self.read_region(&path) {
+                Err(err @ RegionError::Yaml { .. }) => {
+                    self.set_aside(err, "the region is written anew")?;
+                    Vec::new()
+                }
+                result => result?,
+            };
+            entries.retain(|entry| !new_entries.iter().any(|new| new.chunk == entry.chunk));
+            entries.extend(new_entries);
+            let source = serde_yaml::to_string(&entries).map_err(|source| RegionError::Yaml {
+                path: path.clone(),
+                source,
+            })?;
+            fs::write(&path, source).map_err(|source| RegionError::Io { path, source })?;
+        }
+        Ok(())
+    }
+
+    /// Сохраняет файл региона с испорченной записью рядом под именем
+    /// `x_y_z.yaml.corrupt`, чтобы её можно было разобрать, и запоминает
+    /// сообщение об ошибке и о том, что с чанком стало (consequence), для журнала
+    /// игрока. Регион, который не читается целиком, убирается из хранилища,
+    /// а из региона с одной испорченной записью сохраняется копия, и остальные
+    /// записи в нём остаются. Если файл отложить не удалось, это тоже попадает
+    /// в сообщение, а возвращается ошибка: файл остался на месте, и записывать
+    /// поверх него нельзя.
+    pub fn set_aside(&self, err: RegionError, consequence: &str) -> Result<(), RegionError> {
+        let (path, keep_original) = match &err {
+            RegionError::Yaml { path, .. } => (path.clone(), false),
+            RegionError::Chunk { chunk, .. } => (self.region_path(region_of(*chunk)), true),
+            RegionError::Io { .. } => {
+                self.report(format!("{err}, {consequence}"));
+                return Ok(());
+            }
+        };
+        let mut corrupt = path.as_os_str().to_owned();
+        corrupt.push(".corrupt");
+        let corrupt = PathBuf::from(corrupt);
+        let kept = if keep_original {
+            fs::copy(&path, &corrupt).map(drop)
+        } else {
+            fs::rename(&path, &corrupt)
+        };
+        match kept {
+            Ok(()) => {
+                self.report(format!(
+                    "{err}, {consequence}, the damaged region is kept in {}",
+                    corrupt.display()
+                ));
+                Ok(())
+            }
+            Err(source) => {
+                self.report(format!(
+                    "{err}, {consequence}, the damaged region could not be kept: {source}"
+                ));
+                Err(RegionError::Io { path, source })
+            }
+        }
+    }
+
+    fn report(&self, message: String) {
+        self.corrupted.lock().unwrap().push(message);
+    }
+
+    /// Забирает сообщения об испорченных записях, найденных с прошлого раза
+    pub fn take_corrupted(&self) -> Vec<String> {
+        std::mem::take(&mut self.corrupted.lock().unwrap())
+    }
+
+    /// Загружает все записанные чанки
+    pub fn load_all(&self) -> Result<Vec<((i32, i32, i32), R)>, RegionError> {
+        let mut chunks = Vec::new();
+        for path in self.region_paths()? {
+            chunks.extend(
+                self.read_region(&path)?
+                    .into_iter()
+                    .map(|entry| (entry.chunk, entry.data)),
+            );
+        }
+        Ok(chunks)
+    }
+
+    /// Копирует все регионы в другое хранилище, заменяя его регионы с теми же именами
+    pub fn copy_to(&self, target: &RegionStore<R>) -> Result<(), RegionError> {
+        let paths = self.region_paths()?;
+        if paths.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&target.dir).map_err(|source| RegionError::Io {
+            path: target.dir.clone(),
+            source,
+        })?;
+        for path in paths {
+            let target_path = target.dir.join(path.file_name().unwrap());
+            fs::copy(&path, &target_path).map_err(|source| RegionError::Io { path, source })?;
+        }
+        Ok(())
+    }
+
+    /// Удаляет все записанные чанки
+    pub fn clear(&self) -> Result<(), RegionError> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir).map_err(|source| RegionError::Io {
+                path: self.dir.clone(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn region_path(&self, (x, y, z): (i32, i32, i32)) -> PathBuf {
+        self.dir.join(format!("{x}_{y}_{z}.yaml"))
+    }
+
+    fn region_paths(&self) -> Result<Vec<PathBuf>, RegionError> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let io_error = |source| RegionError::Io {
+            path: self.dir.clone(),
+            source,
+        };
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            if path.extension().is_some_and(|ext| ext == "yaml") {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn read_region(&self, path: &Path) -> Result<Vec<RegionEntry<R>>, RegionError> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let source = fs::read_to_string(path).map_err(|source| RegionError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        serde_yaml::from_str(&source).map_err(|source| RegionError::Yaml {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use hecs::{Component, EntityBuilder, EntityRef};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_yaml::Value;
+
+use crate::{
+    components, items, mob, player,
+    systems::{self, health::Body},
+};
+
+/// Данные, которые могут понадобиться для создания компонента из его описания.
+pub struct TemplateContext<'a> {
+    pub body_templates: &'a BTreeMap<Arc<str>, Body>,
+}
+
+/// Функция, создающая компонент из его описания и добавляющая его в сущность
+pub type LoadComponent =
+    fn(Value, &TemplateContext, &mut EntityBuilder) -> Result<(), serde_yaml::Error>;
+/// Функция, возвращающая описание компонента сущности, если он у неё есть
+pub type SaveComponent = fn(EntityRef) -> Option<Result<Value, serde_yaml::Error>>;
+
+struct ComponentEntry {
+    load: LoadComponent,
+    save: SaveComponent,
+}
+
+/// Реестр компонентов, которые можно описывать в файлах данных.
+/// Каждый модуль с компонентами регистрирует их под своими именами в функции
+/// `register_components`, после чего эти имена можно использовать в templates.yaml.
+///
+/// Сущность описывается списком компонентов. Компонент без параметров
+/// записывается просто именем (`- mob`), а компонент с параметром - отображением
+/// из одного ключа (`- sight: 40`). В том же виде компоненты выгружаются обратно.
+pub struct ComponentRegistry {
+    components: BTreeMap<&'static str, ComponentEntry>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self {
+            components: BTreeMap::new(),
+        }
+    }
+
+    /// Реестр со всеми компонентами игры
+    pub fn with_game_components() -> Self {
+        let mut registry = Self::new();
+        components::register_components(&mut registry);
+        items::register_components(&mut registry);
+        mob::register_components(&mut registry);
+        player::register_components(&mut registry);
+        systems::register_components(&mut registry);
+        registry
+    }
+
+    /// Регистрирует компонент, который создаётся и выгружается через serde.
+    pub fn register<T: Component + Serialize + DeserializeOwned>(&mut self, name: &'static str) {
+        self.register_with(name, load_component::<T>, save_component::<T>);
+    }
+
+    /// То же, что и register, но компонент, указанный без параметров,
+    /// создаётся значением по умолчанию.
+    pub fn register_default<T: Component + Serialize + DeserializeOwned + Default>(
+        &mut self,
+        name: &'static str,
+    ) {
+        self.register_with(name, load_default_component::<T>, save_component::<T>);
+    }
+
+    pub fn register_with(&mut self, name: &'static str, load: LoadComponent, save: SaveComponent) {
+        if self
+            .components
+            .insert(name, ComponentEntry { load, save })
+            .is_some()
+        {
+            panic!("Компонент {name} зарегистрирован дважды");
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.components.keys().copied()
+    }
+
+    /// Создаёт компонент из одной записи списка компонентов и добавляет его в сущность.
+    /// В сообщении об ошибке указывается, что ожидалось вместо найденного значения.
+    pub fn load(
+        &self,
+        component: Value,
+        context: &TemplateContext,
+        eb: &mut EntityBuilder,
+    ) -> Result<(), String> {
+        let (name, value) = split_component(component)?;
+        self.load_named(&name, value, context, eb)
+    }
+
+    /// То же, что и load, но имя компонента и его параметр передаются отдельно.
+    pub fn load_named(
+        &self,
+        name: &str,
+        value: Value,
+        context: &TemplateContext,
+        eb: &mut EntityBuilder,
+    ) -> Result<(), String> {
+        let entry = self.components.get(name).ok_or_else(|| {
+            let names = self.names().collect::<Vec<_>>().join(", ");
+            format!("unknown component {name}, expected one of {names}")
+        })?;
+        (entry.load)(value, context, eb).map_err(|err| format!("{name}: {err}"))
+    }
+
+    /// Выгружает все зарегистрированные компоненты сущности в виде списка
+    /// компонентов, который можно снова загрузить через load.
+    pub fn save(&self, entity: EntityRef) -> Result<Vec<Value>, serde_yaml::Error> {
+        let mut components = Vec::new();
+        for (name, entry) in self.components.iter() {
+            if let Some(value) = (entry.save)(entity) {
+                components.push(join_component(name, value?));
+            }
+        }
+        Ok(components)
+    }
+}
+
+/// Разделяет запись списка компонентов на имя компонента и его параметр
+pub fn split_component(component: Value) -> Result<(String, Value), String> {
+    match component {
+        Value::String(name) => Ok((name, Value::Null)),
+        Value::Mapping(mapping) if mapping.len() == 1 => {
+            let (name, value) = mapping.into_iter().next().unwrap();
+            match name {
+                Value::String(name) => Ok((name, value)),
+                _ => Err("component name must be a string".to_owned()),
+            }
+        }
+        _ => Err("expected a component name or a mapping with a single component".to_owned()),
+    }
+}
+
+fn join_component(name: &str, value: Value) -> Value {
+    match value {
+        Value::Null => Value::String(name.to_owned()),
+        value => {
+            let mut mapping = serde_yaml::Mapping::new();
+            mapping.insert(Value::String(name.to_owned()), value);
+            Value::Mapping(mapping)
+        }
+    }
+}
+
+pub fn load_component<T: Component + DeserializeOwned>(
+    value: Value,
+    _: &TemplateContext,
+    eb: &mut EntityBuilder,
+) -> Result<(), serde_yaml::Error> {
+    eb.add(serde_yaml::from_value::<T>(value)?);
+    Ok(())
+}
+
+pub fn load_default_component<T: Component + DeserializeOwned + Default>(
+    value: Value,
+    context: &TemplateContext,
+    eb: &mut EntityBuilder,
+) -> Result<(), serde_yaml::Error> {
+    if value.is_null() {
+        eb.add(T::default());
+        Ok(())
+    } else {
+        load_component::<T>(value, context, eb)
+    }
+}
+
+pub fn save_component<T: Component + Serialize>(
+    entity: EntityRef,
+) -> Option<Result<Value, serde_yaml::Error>> {
+    entity
+        .get::<&T>()
+        .map(|component| serde_yaml::to_value(&*component))
+}
@@ -1,28 +1,34 @@
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
     fs,
-    num::ParseIntError,
     path::{Path, PathBuf},
     rc::Rc,
     sync::Arc,
 };
 
-use hecs::EntityBuilder;
+use hecs::{Entity, EntityBuilder, World};
 use macroquad::{
     prelude::Rect,
     texture::{load_texture, Texture2D},
 };
 
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
+use thiserror::Error;
 use vek::Vec3;
 
 use crate::{
     components::Position,
     hasher,
-    mob::{Inventory, Log},
-    systems::{fov_compute::Sight, memory::MapMemory, pathfinding::Pathfinder, render::Renderable},
-    GameHasher, Mob,
+    map::WorldMap,
+    prefabs::{PrefabError, PrefabRegistry},
+    registry::{split_component, ComponentRegistry, TemplateContext},
+    systems::health::{Body, BodyPart, BodyPartPart, BoneGroup, Organ},
+    tiles::{TileRegistry, TilesError},
+    worldgen::GENERATOR_TILES,
+    GameHasher, Property,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -55,108 +61,712 @@ pub struct Assets {
 
 pub struct Resources {
     pub assets: Assets,
-    pub entity_templates: BTreeMap<Arc<str>, EntityBuilder>,
+    pub components: ComponentRegistry,
+    pub body_templates: BTreeMap<Arc<str>, Body>,
+    pub entity_templates: BTreeMap<Arc<str>, EntityTemplate>,
+    pub tiles: Arc<TileRegistry>,
+    pub prefabs: Arc<PrefabRegistry>,
+}
+
+#[derive(Error, Debug)]
+pub enum SpawnError {
+    #[error("Entity template {0} is not found")]
+    UnknownTemplate(String),
+    #[error("Can't spawn entity from template {template}: {message}")]
+    Component { template: String, message: String },
+}
+
+#[derive(Error, Debug)]
+pub enum BodyTemplateError {
+    #[error("Can't read body templates file {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Body templates file {0} is corrupted: {1}")]
+    Yaml(PathBuf, serde_yaml::Error),
+    #[error("Body template {template}: {path} must be {expected}")]
+    Malformed {
+        template: String,
+        path: String,
+        expected: &'static str,
+    },
 }
 
 impl Resources {
-    pub async fn load(data_path: &Path) -> Self {
-        let entity_templates = Self::load_templates(data_path);
-        Self {
-            assets: Assets::load(&data_path.join("gfx")).await,
+    pub async fn load(data_path: &Path) -> anyhow::Result<Self> {
+        let components = ComponentRegistry::with_game_components();
+        let body_templates = Self::load_body_templates(data_path)?;
+        let context = TemplateContext {
+            body_templates: &body_templates,
+        };
+        let entity_templates = Self::load_templates(data_path, &components, &context)?;
+        let tiles = Self::load_tiles(data_path)?;
+        let prefabs = Self::load_prefabs(data_path, &tiles, &entity_templates)?;
+        Ok(Self {
+            assets: Assets::load(&data_path.join("gfx")).await?,
+            components,
+            body_templates,
             entity_templates,
+            tiles: Arc::new(tiles),
+            prefabs: Arc::new(prefabs),
+        })
+    }
+
+    pub fn template_context(&self) -> TemplateContext<'_> {
+        TemplateContext {
+            body_templates: &self.body_templates,
+        }
+    }
+
+    /// Создаёт сущность по шаблону в указанной позиции. Позиция из шаблона,
+    /// если она там есть, заменяется.
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        template_name: &str,
+        position: Position,
+    ) -> Result<Entity, SpawnError> {
+        let mut builder = self.entity_builder(template_name)?;
+        builder.add(position);
+        Ok(world.spawn(builder.build()))
+    }
+
+    /// Создаёт count сущностей по шаблону в случайных позициях внутри
+    /// параллелепипеда от from до to включительно.
+    pub fn spawn_in_region(
+        &self,
+        world: &mut World,
+        template_name: &str,
+        count: usize,
+        from: Position,
+        to: Position,
+        rng: &mut impl Rng,
+    ) -> Result<Vec<Entity>, SpawnError> {
+        let min = Vec3::partial_min(from.0, to.0);
+        let max = Vec3::partial_max(from.0, to.0);
+        (0..count)
+            .map(|_| {
+                let position = Vec3::new(
+                    rng.gen_range(min.x..=max.x),
+                    rng.gen_range(min.y..=max.y),
+                    rng.gen_range(min.z..=max.z),
+                );
+                self.spawn(world, template_name, Position(position))
+            })
+            .collect()
+    }
+
+    /// Создаёт сущности префабов и существ биомов, которые появились вместе
+    /// с новыми чанками карты
+    pub fn spawn_prefab_entities(&self, world: &mut World) -> Result<(), SpawnError> {
+        let spawns = match world.query_mut::<(&mut WorldMap,)>().into_iter().next() {
+            Some((_, (map,))) => std::mem::take(&mut map.spawns),
+            None => return Ok(()),
+        };
+        for spawn in spawns {
+            self.spawn(world, &spawn.template, Position(spawn.position))?;
+        }
+        Ok(())
+    }
+
+    fn entity_builder(&self, template_name: &str) -> Result<EntityBuilder, SpawnError> {
+        let template = self
+            .entity_templates
+            .get(template_name)
+            .ok_or_else(|| SpawnError::UnknownTemplate(template_name.to_owned()))?;
+        template
+            .build(&self.components, &self.template_context())
+            .map_err(|message| SpawnError::Component {
+                template: template_name.to_owned(),
+                message,
+            })
+    }
+
+    /// Загружает шаблоны тел из файла body_templates.yaml. Ключи слияния YAML
+    /// (`<<: *arm`) раскрываются до разбора шаблонов.
+    pub fn load_body_templates(
+        data_path: &Path,
+    ) -> Result<BTreeMap<Arc<str>, Body>, BodyTemplateError> {
+        let path = data_path.join("body_templates.yaml");
+        let file = fs::read_to_string(&path).map_err(|e| BodyTemplateError::Io(path.clone(), e))?;
+        let mut value: Value =
+            serde_yaml::from_str(&file).map_err(|e| BodyTemplateError::Yaml(path.clone(), e))?;
+        value
+            .apply_merge()
+            .map_err(|e| BodyTemplateError::Yaml(path.clone(), e))?;
+        parse_body_templates(&value)
+    }
+
+    /// Загружает шаблоны сущностей из файла templates.yaml. Ошибки собираются
+    /// по всем шаблонам сразу, а не только до первой найденной.
+    pub fn load_templates(
+        data_path: &Path,
+        registry: &ComponentRegistry,
+        context: &TemplateContext,
+    ) -> Result<BTreeMap<Arc<str>, EntityTemplate>, TemplateErrors> {
+        let path = data_path.join("templates.yaml");
+        let file = fs::read_to_string(&path).map_err(|source| {
+            TemplateErrors(vec![TemplateError::Io {
+                file: path.clone(),
+                source,
+            }])
+        })?;
+        parse_templates(&path, &file, registry, context)
+    }
+
+    /// Загружает типы тайлов из файла tiles.yaml и проверяет, что в нём
+    /// есть все тайлы, нужные генераторам мира.
+    pub fn load_tiles(data_path: &Path) -> Result<TileRegistry, TilesError> {
+        let path = data_path.join("tiles.yaml");
+        let file = fs::read_to_string(&path).map_err(|source| TilesError::Io {
+            file: path.clone(),
+            source,
+        })?;
+        let tiles = TileRegistry::parse(&path, &file)?;
+        tiles.require(&path, &GENERATOR_TILES)?;
+        Ok(tiles)
+    }
+
+    /// Загружает префабы из файла prefabs.yaml. Тайлы и шаблоны сущностей,
+    /// на которые ссылаются префабы, должны быть уже загружены.
+    pub fn load_prefabs(
+        data_path: &Path,
+        tiles: &TileRegistry,
+        templates: &BTreeMap<Arc<str>, EntityTemplate>,
+    ) -> Result<PrefabRegistry, PrefabError> {
+        let path = data_path.join("prefabs.yaml");
+        let file = fs::read_to_string(&path).map_err(|source| PrefabError::Io {
+            file: path.clone(),
+            source,
+        })?;
+        PrefabRegistry::parse(&path, &file, tiles, templates)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("{}: can't read file: {source}", file.display())]
+    Io {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: file is corrupted: {source}", file.display())]
+    Yaml {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: template {template} must be a list of components", file.display())]
+    NotAList { file: PathBuf, template: String },
+    #[error("{}: template {template}, component #{index}: {message}", file.display())]
+    Component {
+        file: PathBuf,
+        template: String,
+        index: usize,
+        message: String,
+    },
+    #[error("{}: template {template} extends unknown template {parent}", file.display())]
+    MissingParent {
+        file: PathBuf,
+        template: String,
+        parent: String,
+    },
+    #[error("{}: templates extend each other in a cycle: {chain}", file.display())]
+    Cycle { file: PathBuf, chain: String },
+}
+
+/// Все ошибки, найденные при загрузке шаблонов сущностей.
+#[derive(Error, Debug)]
+pub struct TemplateErrors(pub Vec<TemplateError>);
+
+impl Display for TemplateErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (n, err) in self.0.iter().enumerate() {
+            if n > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{err}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Шаблон сущности с уже применённым наследованием: список компонентов
+/// в том виде, в котором они описаны в файле шаблонов.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityTemplate {
+    pub components: Vec<(String, Value)>,
+}
+
+impl EntityTemplate {
+    pub fn get(&self, component_name: &str) -> Option<&Value> {
+        self.components
+            .iter()
+            .find(|(name, _)| name == component_name)
+            .map(|(_, value)| value)
+    }
+
+    /// Создаёт новую сущность по шаблону. Шаблон при этом не расходуется.
+    pub fn build(
+        &self,
+        registry: &ComponentRegistry,
+        context: &TemplateContext,
+    ) -> Result<EntityBuilder, String> {
+        let mut eb = EntityBuilder::new();
+        for (name, value) in self.components.iter() {
+            registry.load_named(name, value.clone(), context, &mut eb)?;
+        }
+        Ok(eb)
+    }
+
+    /// Добавляет компоненты потомка к компонентам родителя. Компонент, который
+    /// есть у обоих, берётся у потомка, но остаётся на месте родительского.
+    fn extend(&self, child: &EntityTemplate) -> EntityTemplate {
+        let mut components = self.components.clone();
+        for (name, value) in child.components.iter() {
+            match components
+                .iter_mut()
+                .find(|(parent_name, _)| parent_name == name)
+            {
+                Some((_, parent_value)) => *parent_value = value.clone(),
+                None => components.push((name.clone(), value.clone())),
+            }
+        }
+        EntityTemplate { components }
+    }
+}
+
+/// Шаблон в том виде, в котором он записан в файле, до применения наследования
+struct RawTemplate {
+    extends: Option<String>,
+    template: EntityTemplate,
+}
+
+/// Разбирает содержимое templates.yaml. Путь к файлу используется только
+/// в сообщениях об ошибках.
+///
+/// Шаблон может унаследовать все компоненты другого шаблона, если среди его
+/// компонентов есть `- extends: <имя шаблона>`. Компоненты, указанные
+/// в самом шаблоне, заменяют одноимённые компоненты родителя.
+pub fn parse_templates(
+    file: &Path,
+    source: &str,
+    registry: &ComponentRegistry,
+    context: &TemplateContext,
+) -> Result<BTreeMap<Arc<str>, EntityTemplate>, TemplateErrors> {
+    let templates: BTreeMap<String, Value> = serde_yaml::from_str(source).map_err(|source| {
+        TemplateErrors(vec![TemplateError::Yaml {
+            file: file.to_owned(),
+            source,
+        }])
+    })?;
+    let mut raw_templates = BTreeMap::new();
+    let mut errors = Vec::new();
+    // Шаблоны, которые не удалось разобрать, и их потомки не загружаются
+    let mut failed = BTreeSet::new();
+    for (template_name, template) in templates {
+        let Value::Sequence(components) = template else {
+            failed.insert(template_name.clone());
+            errors.push(TemplateError::NotAList {
+                file: file.to_owned(),
+                template: template_name,
+            });
+            continue;
+        };
+        let mut raw = RawTemplate {
+            extends: None,
+            template: EntityTemplate {
+                components: Vec::new(),
+            },
+        };
+        for (index, component) in components.into_iter().enumerate() {
+            if let Err(message) = parse_component(component, &mut raw, registry, context) {
+                errors.push(TemplateError::Component {
+                    file: file.to_owned(),
+                    template: template_name.clone(),
+                    index,
+                    message,
+                });
+            }
+        }
+        raw_templates.insert(template_name, raw);
+    }
+
+    let mut entity_templates = BTreeMap::new();
+    for template_name in raw_templates.keys() {
+        let mut chain = Vec::new();
+        let result = resolve_template(
+            file,
+            template_name,
+            &raw_templates,
+            &mut entity_templates,
+            &failed,
+            &mut chain,
+        );
+        if let Err(err) = result {
+            // Потомки сломанного шаблона тоже не загружаются, но ошибка
+            // записывается только один раз
+            errors.extend(err);
+            failed.extend(chain);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(entity_templates
+            .into_iter()
+            .map(|(name, template)| (name.into(), template))
+            .collect())
+    } else {
+        Err(TemplateErrors(errors))
+    }
+}
+
+fn parse_component(
+    component: Value,
+    raw: &mut RawTemplate,
+    registry: &ComponentRegistry,
+    context: &TemplateContext,
+) -> Result<(), String> {
+    let (name, value) = split_component(component)?;
+    if name == "extends" {
+        let Value::String(parent) = value else {
+            return Err("extends: expected a template name".to_owned());
+        };
+        if raw.extends.is_some() {
+            return Err("extends: template can extend only one template".to_owned());
         }
+        raw.extends = Some(parent);
+        return Ok(());
     }
-    pub fn load_templates(data_path: &Path) -> BTreeMap<Arc<str>, EntityBuilder> {
-        let mut entity_templates = BTreeMap::new();
-        let file = fs::read_to_string(data_path.join("templates.yaml")).unwrap();
-        let templates: BTreeMap<String, Vec<Value>> = serde_yaml::from_str(&file).unwrap();
-        for (template_name, template) in templates {
-            let mut eb = EntityBuilder::new();
-            for component in template {
-                match component {
-                    Value::String(ref compo_name) => match compo_name.as_str() {
-                        "mob" => {
-                            eb.add(Mob);
-                        }
-                        "log" => {
-                            eb.add(Log("".into()));
-                        }
-                        "pathfinder" => {
-                            eb.add(Pathfinder);
-                        }
-                        "inventory" => {
-                            eb.add(Inventory(Vec::new()));
-                        }
-                        "map_memory" => {
-                            eb.add(MapMemory::new());
-                        }
-                        _ => {
-                            dbg!(component);
-                            panic!("Уберите это немедленно")
-                        }
-                    },
-                    Value::Mapping(ref mapping) => {
-                        if mapping.len() != 1 {
-                            dbg!(component);
-                            panic!("Уберите это немедленно");
-                        }
-                        if let Some((Value::String(name), val)) = mapping.iter().next() {
-                            match &(name.as_str(), val) {
-                                // ("health", Value::Number(n)) => {
-                                //     eb.add(DummyHealth(n.as_i64().unwrap() as i32));
-                                // }
-                                ("sight", Value::Number(n)) => {
-                                    eb.add(Sight(
-                                        n.as_u64().unwrap() as u32,
-                                        HashSet::with_hasher(hasher()),
-                                    ));
-                                }
-                                ("position", Value::String(pos_str)) => {
-                                    let nums = pos_str
-                                        .split('x')
-                                        .map(|x| x.parse::<i32>())
-                                        .collect::<Result<Vec<i32>, ParseIntError>>()
-                                        .expect("Координаты должны быть в таком формате: XxYxZ");
-                                    if nums.len() != 3 {
-                                        panic!("Координата позиции трёхмерная должна быть");
-                                    }
-                                    eb.add(Position(Vec3::new(nums[0], nums[1], nums[2])));
-                                }
-                                ("renderable", Value::String(str)) => {
-                                    eb.add(Renderable(str.to_owned().into()));
-                                }
-                                _ => {
-                                    dbg!(component);
-                                    panic!("Уберите это немедленно");
-                                }
-                            }
-                        }
-                    }
-                    _ => {
-                        dbg!(component);
-                        panic!("Уберите это немедленно")
-                    }
+    if raw.template.get(&name).is_some() {
+        return Err(format!("{name}: component is specified more than once"));
+    }
+    // Компонент создаётся сразу, чтобы ошибка указывала на место, где он записан
+    registry.load_named(&name, value.clone(), context, &mut EntityBuilder::new())?;
+    raw.template.components.push((name, value));
+    Ok(())
+}
+
+/// Применяет наследование к шаблону и всем его предкам. В chain записывается
+/// цепочка шаблонов, начиная с запрошенного, чтобы находить циклы.
+/// Если один из предков уже не удалось загрузить, возвращается `Err(None)`.
+fn resolve_template(
+    file: &Path,
+    template_name: &str,
+    raw_templates: &BTreeMap<String, RawTemplate>,
+    resolved: &mut BTreeMap<String, EntityTemplate>,
+    failed: &BTreeSet<String>,
+    chain: &mut Vec<String>,
+) -> Result<(), Option<TemplateError>> {
+    if resolved.contains_key(template_name) {
+        return Ok(());
+    }
+    if failed.contains(template_name) {
+        return Err(None);
+    }
+    if let Some(start) = chain.iter().position(|name| name == template_name) {
+        let cycle = chain[start..]
+            .iter()
+            .map(String::as_str)
+            .chain([template_name])
+            .collect::<Vec<_>>();
+        return Err(Some(TemplateError::Cycle {
+            file: file.to_owned(),
+            chain: cycle.join(" -> "),
+        }));
+    }
+    chain.push(template_name.to_owned());
+    let raw = &raw_templates[template_name];
+    let template = match raw.extends {
+        None => raw.template.clone(),
+        Some(ref parent) => {
+            if !raw_templates.contains_key(parent) && !failed.contains(parent) {
+                return Err(Some(TemplateError::MissingParent {
+                    file: file.to_owned(),
+                    template: template_name.to_owned(),
+                    parent: parent.clone(),
+                }));
+            }
+            resolve_template(file, parent, raw_templates, resolved, failed, chain)?;
+            resolved[parent].extend(&raw.template)
+        }
+    };
+    resolved.insert(template_name.to_owned(), template);
+    Ok(())
+}
+
+/// Разбирает содержимое body_templates.yaml (с уже раскрытыми ключами слияния).
+/// Шаблон тела - это отображение частей тела в их составные части, у каждой
+/// из которых могут быть списки групп костей (bone_groups), органов (organs)
+/// и свойств (properties).
+pub fn parse_body_templates(value: &Value) -> Result<BTreeMap<Arc<str>, Body>, BodyTemplateError> {
+    let mut body_templates = BTreeMap::new();
+    let Value::Mapping(templates) = value else {
+        return Err(malformed("", "file root", "a mapping of body templates"));
+    };
+    for (name, template) in templates {
+        let Value::String(name) = name else {
+            return Err(malformed("", "file root", "a mapping with string keys"));
+        };
+        body_templates.insert(name.as_str().into(), parse_body(name, template)?);
+    }
+    Ok(body_templates)
+}
+
+fn malformed(template: &str, path: &str, expected: &'static str) -> BodyTemplateError {
+    BodyTemplateError::Malformed {
+        template: template.to_owned(),
+        path: path.to_owned(),
+        expected,
+    }
+}
+
+/// Возвращает пары ключ-значение отображения, проверяя, что все ключи - строки.
+/// Пустое значение (`thigh:`) считается пустым отображением.
+fn string_mapping<'a>(
+    template: &str,
+    path: &str,
+    value: &'a Value,
+    expected: &'static str,
+) -> Result<Vec<(&'a str, &'a Value)>, BodyTemplateError> {
+    let mapping = match value {
+        Value::Mapping(mapping) => mapping,
+        Value::Null => return Ok(Vec::new()),
+        _ => return Err(malformed(template, path, expected)),
+    };
+    mapping
+        .iter()
+        .map(|(key, value)| match key {
+            Value::String(key) => Ok((key.as_str(), value)),
+            _ => Err(malformed(template, path, expected)),
+        })
+        .collect()
+}
+
+fn string_list<'a>(
+    template: &str,
+    path: &str,
+    value: &'a Value,
+) -> Result<Vec<&'a str>, BodyTemplateError> {
+    match value {
+        Value::Null => Ok(Vec::new()),
+        Value::Sequence(sequence) => sequence
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                item.as_str()
+                    .ok_or_else(|| malformed(template, &format!("{path}[{i}]"), "a name"))
+            })
+            .collect(),
+        _ => Err(malformed(template, path, "a list of names")),
+    }
+}
+
+fn parse_body(template: &str, value: &Value) -> Result<Body, BodyTemplateError> {
+    let mut body = Body::new();
+    let parts = string_mapping(template, template, value, "a mapping of body parts")?;
+    if parts.is_empty() {
+        return Err(malformed(
+            template,
+            template,
+            "a non-empty mapping of body parts",
+        ));
+    }
+    for (part_name, part) in parts {
+        let path = format!("{template}.{part_name}");
+        body.add_part(
+            part_name.to_owned(),
+            parse_body_part(template, &path, part)?,
+        );
+    }
+    Ok(body)
+}
+
+fn parse_body_part(
+    template: &str,
+    path: &str,
+    value: &Value,
+) -> Result<BodyPart, BodyTemplateError> {
+    let mut body_part = BodyPart::new();
+    let expected = "a non-empty mapping of body part parts";
+    let parts = string_mapping(template, path, value, expected)?;
+    if parts.is_empty() {
+        return Err(malformed(template, path, expected));
+    }
+    for (part_name, part) in parts {
+        let path = format!("{path}.{part_name}");
+        body_part.add_part(
+            part_name.to_owned(),
+            parse_body_part_part(template, &path, part)?,
+        );
+    }
+    Ok(body_part)
+}
+
+fn parse_body_part_part(
+    template: &str,
+    path: &str,
+    value: &Value,
+) -> Result<BodyPartPart, BodyTemplateError> {
+    let mut part = BodyPartPart::new();
+    let expected = "a mapping with bone_groups, organs and properties";
+    for (key, value) in string_mapping(template, path, value, expected)? {
+        let path = format!("{path}.{key}");
+        match key {
+            "bone_groups" => {
+                for name in string_list(template, &path, value)? {
+                    part.add_bone_group(name.to_owned(), BoneGroup::new());
                 }
             }
+            "organs" => {
+                for name in string_list(template, &path, value)? {
+                    part.add_organ(name.to_owned(), Organ::new());
+                }
+            }
+            "properties" => {
+                for name in string_list(template, &path, value)? {
+                    part.add_property(name.to_owned(), Property::Marker);
+                }
+            }
+            _ => {
+                return Err(malformed(
+                    template,
+                    &path,
+                    "one of bone_groups, organs, properties",
+                ))
+            }
+        }
+    }
+    Ok(part)
+}
 
-            entity_templates.insert(template_name.to_owned().into(), eb);
+#[derive(Error, Debug)]
+pub enum AssetsError {
+    #[error("{}: can't read file: {source}", file.display())]
+    Io {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: file is corrupted: {source}", file.display())]
+    Yaml {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: texture file {} not found", file.display(), texture.display())]
+    MissingTexture { file: PathBuf, texture: PathBuf },
+    #[error("{}: sprite {name} is defined more than once", file.display())]
+    DuplicateSprite { file: PathBuf, name: String },
+}
+
+impl AssetsConfig {
+    pub fn load(assets_path: &Path) -> Result<Self, AssetsError> {
+        let file = assets_path.join("assets.yaml");
+        let yaml_config = fs::read_to_string(&file).map_err(|source| AssetsError::Io {
+            file: file.clone(),
+            source,
+        })?;
+        serde_yaml::from_str(&yaml_config).map_err(|source| AssetsError::Yaml { file, source })
+    }
+
+    /// Проверяет, что все файлы текстур существуют и имена спрайтов не повторяются.
+    pub fn check(&self, assets_path: &Path) -> Vec<AssetsError> {
+        let file = assets_path.join("assets.yaml");
+        let mut errors = Vec::new();
+        let mut names = HashSet::with_hasher(hasher());
+        for texture_config in self.textures.iter() {
+            if !assets_path.join(&texture_config.source_file).is_file() {
+                errors.push(AssetsError::MissingTexture {
+                    file: file.clone(),
+                    texture: texture_config.source_file.clone(),
+                });
+            }
+            for sprite_config in texture_config.sprites.iter() {
+                if !names.insert(sprite_config.name.as_str()) {
+                    errors.push(AssetsError::DuplicateSprite {
+                        file: file.clone(),
+                        name: sprite_config.name.clone(),
+                    });
+                }
+            }
         }
-        entity_templates
+        errors
     }
+
+    /// Находит тайлы, спрайтов которых нет среди спрайтов текстур
+    pub fn check_tiles(&self, tiles_path: &Path, tiles: &TileRegistry) -> Vec<TilesError> {
+        let names = self
+            .textures
+            .iter()
+            .flat_map(|texture_config| texture_config.sprites.iter())
+            .map(|sprite_config| sprite_config.name.as_str())
+            .collect::<HashSet<_, GameHasher>>();
+        let mut errors = Vec::new();
+        for (_, tile) in tiles.iter() {
+            for sprite in [Some(&tile.sprite), tile.fallback_sprite.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if !names.contains(&**sprite) {
+                    errors.push(TilesError::UnknownSprite {
+                        file: tiles_path.to_owned(),
+                        tile: tile.name.to_string(),
+                        sprite: sprite.to_string(),
+                    });
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// Проверяет все файлы в каталоге данных, не открывая окно и не загружая текстуры.
+/// Возвращает все найденные ошибки.
+pub fn check_data(data_path: &Path) -> Vec<anyhow::Error> {
+    let mut errors: Vec<anyhow::Error> = Vec::new();
+    let body_templates = Resources::load_body_templates(data_path).unwrap_or_else(|err| {
+        errors.push(err.into());
+        BTreeMap::new()
+    });
+    let context = TemplateContext {
+        body_templates: &body_templates,
+    };
+    let registry = ComponentRegistry::with_game_components();
+    let templates = match Resources::load_templates(data_path, &registry, &context) {
+        Ok(templates) => Some(templates),
+        Err(TemplateErrors(template_errors)) => {
+            errors.extend(template_errors.into_iter().map(Into::into));
+            None
+        }
+    };
+    let tiles = Resources::load_tiles(data_path)
+        .map_err(|err| errors.push(err.into()))
+        .ok();
+    // Префабы ссылаются и на тайлы, и на шаблоны, без них проверять нечего
+    if let (Some(tiles), Some(templates)) = (&tiles, &templates) {
+        if let Err(err) = Resources::load_prefabs(data_path, tiles, templates) {
+            errors.push(err.into());
+        }
+    }
+    let assets_path = data_path.join("gfx");
+    match AssetsConfig::load(&assets_path) {
+        Ok(config) => {
+            errors.extend(config.check(&assets_path).into_iter().map(Into::into));
+            if let Some(tiles) = tiles {
+                let tiles_path = data_path.join("tiles.yaml");
+                errors.extend(
+                    config
+                        .check_tiles(&tiles_path, &tiles)
+                        .into_iter()
+                        .map(Into::into),
+                );
+            }
+        }
+        Err(err) => errors.push(err.into()),
+    }
+    errors
 }
 
 impl Assets {
-    pub async fn load(assets_path: &Path) -> Self {
-        let config_path = assets_path.join("assets.yaml");
-        let yaml_config = fs::read_to_string(config_path).unwrap_or_else(|_| {
-            panic!(
-                "File assets.yaml not found in {} directory",
-                assets_path.display()
-            )
-        });
-        let config: AssetsConfig =
-            serde_yaml::from_str(&yaml_config).expect("assets.yaml file is corrupted");
-        Self::new(&config, assets_path).await
+    pub async fn load(assets_path: &Path) -> Result<Self, AssetsError> {
+        let config = AssetsConfig::load(assets_path)?;
+        Ok(Self::new(&config, assets_path).await)
     }
     pub async fn new(config: &AssetsConfig, assets_path: &Path) -> Self {
         let mut sprites = HashMap::with_hasher(hasher());
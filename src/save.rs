@@ -0,0 +1,392 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use hecs::{EntityBuilder, World};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_yaml::Value;
+use thiserror::Error;
+
+use crate::{
+    map::{Chunk, ChunkRuns, WorldMap},
+    player::{is_player_dead, Player, PLAYER_LANTERN},
+    region::{RegionError, RegionStore},
+    registry::{ComponentRegistry, TemplateContext},
+    systems::lighting::LightSource,
+    tiles::TileRegistry,
+    worldgen::generator_by_name,
+};
+
+/// Версия формата сохранений. Её нужно увеличивать при любом изменении формата,
+/// а в load_world добавлять переход со старой версии.
+pub const SAVE_VERSION: u32 = 7;
+
+const META_FILE: &str = "meta.yaml";
+const ENTITIES_FILE: &str = "entities.yaml";
+/// До версии 6 каждый чанк лежал в этом каталоге в отдельном файле
+const CHUNKS_DIR: &str = "chunks";
+const REGIONS_DIR: &str = "regions";
+/// Каталог, куда выгружаются чанки во время игры
+const CACHE_DIR: &str = "cache";
+
+/// Режим игры, выбираемый при начале новой игры
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    /// Игру можно сохранять и загружать когда угодно
+    #[default]
+    Normal,
+    /// "Ответственный режим": игра сохраняется только при выходе, а после смерти
+    /// персонажа сохранение удаляется и приходится начинать заново
+    Responsible,
+}
+
+/// Общие сведения о сохранении, которые хранятся в meta.yaml
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SaveMeta {
+    pub version: u32,
+    pub turn: u64,
+    /// Зерно генерации мира. До версии 3 оно не сохранялось, и новые чанки
+    /// таких сохранений генерируются с зерном 0.
+    #[serde(default)]
+    pub seed: u64,
+    /// Имя генератора чанков. До версии 4 был только классический генератор.
+    #[serde(default = "classic_generator")]
+    pub generator: String,
+    /// В версии 1 режима не было, такие сохранения считаются обычными
+    #[serde(default)]
+    pub mode: GameMode,
+    /// Сохранение загружено и ещё не закрыто выходом из игры.
+    /// Используется только в ответственном режиме.
+    #[serde(default)]
+    pub opened: bool,
+}
+
+fn classic_generator() -> String {
+    "classic".to_owned()
+}
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: file is corrupted: {source}", path.display())]
+    Yaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("Save format version {found} is not supported, expected {SAVE_VERSION}")]
+    UnsupportedVersion { found: u32 },
+    #[error("{}: {file_name} is not a chunk file name", path.display())]
+    ChunkName { path: PathBuf, file_name: String },
+    #[error("{}: {message}", path.display())]
+    Chunk { path: PathBuf, message: String },
+    #[error("{}: entity #{index}: {message}", path.display())]
+    Entity {
+        path: PathBuf,
+        index: usize,
+        message: String,
+    },
+    #[error("World has no map to save")]
+    NoMap,
+    #[error("Unknown world generator {0}")]
+    UnknownGenerator(String),
+    #[error("Save {} was loaded and not closed properly, it can't be loaded again in responsible mode", path.display())]
+    NotClosed { path: PathBuf },
+    #[error("Game can be saved only on exit in responsible mode")]
+    SavingDisabled,
+    #[error(transparent)]
+    Region(#[from] RegionError),
+}
+
+/// Сохранение игры в каталоге вместе с режимом, в котором идёт игра.
+/// Следит за правилами ответственного режима.
+pub struct SaveSlot {
+    pub path: PathBuf,
+    pub mode: GameMode,
+}
+
+impl SaveSlot {
+    pub fn new(path: PathBuf, mode: GameMode) -> Self {
+        Self { path, mode }
+    }
+
+    pub fn exists(&self) -> bool {
+        save_exists(&self.path)
+    }
+
+    /// Каталог, куда во время игры выгружаются далёкие чанки
+    pub fn cache_dir(&self) -> PathBuf {
+        self.path.join(CACHE_DIR)
+    }
+
+    /// Загружает игру из каталога path. Сохранение в ответственном режиме
+    /// помечается открытым до выхода из игры, и загрузить его ещё раз,
+    /// не выйдя из игры, нельзя.
+    pub fn load(
+        path: PathBuf,
+        registry: &ComponentRegistry,
+        context: &TemplateContext,
+        tiles: &Arc<TileRegistry>,
+    ) -> Result<(SaveSlot, World, u64), SaveError> {
+        let cache = path.join(CACHE_DIR);
+        let (world, mut meta) = load_world(&path, registry, context, tiles, &cache)?;
+        if meta.mode == GameMode::Responsible {
+            if meta.opened {
+                return Err(SaveError::NotClosed { path });
+            }
+            meta.opened = true;
+            write_yaml(&path.join(META_FILE), &meta)?;
+        }
+        Ok((SaveSlot::new(path, meta.mode), world, meta.turn))
+    }
+
+    /// Сохранение по желанию игрока, в ответственном режиме запрещено
+    pub fn save(
+        &self,
+        world: &World,
+        turn: u64,
+        registry: &ComponentRegistry,
+    ) -> Result<(), SaveError> {
+        match self.mode {
+            GameMode::Normal => save_world(&self.path, world, turn, self.mode, registry),
+            GameMode::Responsible => Err(SaveError::SavingDisabled),
+        }
+    }
+
+    /// Сохранение при выходе из игры. В ответственном режиме это единственный
+    /// способ сохраниться, а если персонаж уже умер, сохранять нечего.
+    pub fn save_on_exit(
+        &self,
+        world: &World,
+        turn: u64,
+        registry: &ComponentRegistry,
+    ) -> Result<(), SaveError> {
+        match self.mode {
+            GameMode::Normal => Ok(()),
+            GameMode::Responsible if is_player_dead(world) => Ok(()),
+            GameMode::Responsible => save_world(&self.path, world, turn, self.mode, registry),
+        }
+    }
+
+    /// Вызывается после смерти персонажа. В ответственном режиме удаляет
+    /// сохранение, чтобы продолжить игру было нельзя.
+    pub fn on_player_death(&self) -> Result<(), SaveError> {
+        if self.mode == GameMode::Responsible && self.path.exists() {
+            fs::remove_dir_all(&self.path).map_err(|source| SaveError::Io {
+                path: self.path.clone(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Есть ли в каталоге dir законченное сохранение
+pub fn save_exists(dir: &Path) -> bool {
+    dir.join(META_FILE).exists()
+}
+
+/// Очищает каталог для выгрузки чанков и возвращает хранилище чанков карты в нём
+pub fn new_cache(cache: &Path) -> Result<RegionStore<ChunkRuns>, SaveError> {
+    if cache.exists() {
+        fs::remove_dir_all(cache).map_err(|source| SaveError::Io {
+            path: cache.to_owned(),
+            source,
+        })?;
+    }
+    Ok(RegionStore::new(cache.join(CHUNKS_DIR)))
+}
+
+/// Сохраняет мир в каталог dir. В каталоге создаются файл meta.yaml со сведениями
+/// о сохранении, entities.yaml со всеми сущностями и каталог regions, в котором
+/// лежат все чанки карты, и загруженные, и выгруженные.
+/// Сущности, у которых нет ни одного зарегистрированного компонента, не сохраняются.
+pub fn save_world(
+    dir: &Path,
+    world: &World,
+    turn: u64,
+    mode: GameMode,
+    registry: &ComponentRegistry,
+) -> Result<(), SaveError> {
+    let meta_path = dir.join(META_FILE);
+    if meta_path.exists() {
+        fs::remove_file(&meta_path).map_err(|source| SaveError::Io {
+            path: meta_path.clone(),
+            source,
+        })?;
+    }
+    // Чанки из сохранений старых версий
+    let chunks_dir = dir.join(CHUNKS_DIR);
+    if chunks_dir.exists() {
+        fs::remove_dir_all(&chunks_dir).map_err(|source| SaveError::Io {
+            path: chunks_dir.clone(),
+            source,
+        })?;
+    }
+    fs::create_dir_all(dir).map_err(|source| SaveError::Io {
+        path: dir.to_owned(),
+        source,
+    })?;
+
+    let mut query = world.query::<(&WorldMap,)>();
+    let (_, (map,)) = query.iter().next().ok_or(SaveError::NoMap)?;
+    map.save_regions(&RegionStore::new(dir.join(REGIONS_DIR)))?;
+    let seed = map.seed;
+    let generator = map.generator.name().to_owned();
+    drop(query);
+
+    let path = dir.join(ENTITIES_FILE);
+    let mut entities = Vec::new();
+    for entity in world.iter() {
+        let components = registry.save(entity).map_err(|source| SaveError::Yaml {
+            path: path.clone(),
+            source,
+        })?;
+        if !components.is_empty() {
+            entities.push(components);
+        }
+    }
+    write_yaml(&path, &entities)?;
+
+    // meta.yaml записывается последним, чтобы недописанное сохранение не загружалось
+    let meta = SaveMeta {
+        version: SAVE_VERSION,
+        turn,
+        seed,
+        generator,
+        mode,
+        opened: false,
+    };
+    write_yaml(&meta_path, &meta)
+}
+
+/// Загружает мир, сохранённый save_world. Возвращает мир и сведения о сохранении.
+/// Тайлы чанков ищутся по именам в реестре tiles. Чанки не загружаются сразу:
+/// они копируются в каталог cache и загружаются оттуда, когда понадобятся.
+pub fn load_world(
+    dir: &Path,
+    registry: &ComponentRegistry,
+    context: &TemplateContext,
+    tiles: &Arc<TileRegistry>,
+    cache: &Path,
+) -> Result<(World, SaveMeta), SaveError> {
+    let mut meta: SaveMeta = read_yaml(&dir.join(META_FILE))?;
+    let version = meta.version;
+    match version {
+        // Старые версии отличаются только отсутствием полей в meta.yaml,
+        // а до версии 5 в чанках ещё записывались препятствия, которые
+        // теперь просто не читаются. До версии 6 чанки лежали в отдельных файлах.
+        // До версии 7 освещения не было, и персонаж получает фонарь при загрузке.
+        1..=6 | SAVE_VERSION => meta.version = SAVE_VERSION,
+        found => return Err(SaveError::UnsupportedVersion { found }),
+    }
+
+    let generator = generator_by_name(&meta.generator)
+        .ok_or_else(|| SaveError::UnknownGenerator(meta.generator.clone()))?;
+    let mut map = WorldMap::new(meta.seed, generator, tiles.clone());
+    let store = new_cache(cache)?;
+    RegionStore::new(dir.join(REGIONS_DIR)).copy_to(&store)?;
+    map.store = Some(store);
+    // В сохранениях до версии 6 чанки лежат в отдельных файлах и загружаются сразу
+    let chunks_dir = dir.join(CHUNKS_DIR);
+    if chunks_dir.exists() {
+        let entries = fs::read_dir(&chunks_dir).map_err(|source| SaveError::Io {
+            path: chunks_dir.clone(),
+            source,
+        })?;
+        for entry in entries {
+            let path = entry
+                .map_err(|source| SaveError::Io {
+                    path: chunks_dir.clone(),
+                    source,
+                })?
+                .path();
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let position = parse_chunk_name(&file_name).ok_or_else(|| SaveError::ChunkName {
+                path: chunks_dir.clone(),
+                file_name: file_name.clone(),
+            })?;
+            let runs: ChunkRuns = read_yaml(&path)?;
+            let chunk = Chunk::from_runs(runs, tiles)
+                .map_err(|message| SaveError::Chunk { path, message })?;
+            map.chunks.insert(position, Mutex::new(chunk));
+            // Этих чанков нет в хранилище
+            map.dirty.insert(position);
+        }
+    }
+
+    let mut world = World::new();
+    world.spawn((map,));
+    let path = dir.join(ENTITIES_FILE);
+    let entities: Vec<Vec<Value>> = read_yaml(&path)?;
+    for (index, components) in entities.into_iter().enumerate() {
+        let mut eb = EntityBuilder::new();
+        for component in components {
+            registry
+                .load(component, context, &mut eb)
+                .map_err(|message| SaveError::Entity {
+                    path: path.clone(),
+                    index,
+                    message,
+                })?;
+        }
+        world.spawn(eb.build());
+    }
+    if version < 7 {
+        let players = world
+            .query::<&Player>()
+            .without::<&LightSource>()
+            .iter()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for player in players {
+            world
+                .insert_one(player, LightSource(PLAYER_LANTERN))
+                .expect("Персонаж только что найден в мире");
+        }
+    }
+    Ok((world, meta))
+}
+
+/// Разбирает имя файла чанка вида `x_y_z.yaml`
+fn parse_chunk_name(file_name: &str) -> Option<(i32, i32, i32)> {
+    let mut coords = file_name.strip_suffix(".yaml")?.split('_');
+    let x = coords.next()?.parse().ok()?;
+    let y = coords.next()?.parse().ok()?;
+    let z = coords.next()?.parse().ok()?;
+    match coords.next() {
+        None => Some((x, y, z)),
+        Some(_) => None,
+    }
+}
+
+fn write_yaml<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), SaveError> {
+    let source = serde_yaml::to_string(value).map_err(|source| SaveError::Yaml {
+        path: path.to_owned(),
+        source,
+    })?;
+    fs::write(path, source).map_err(|source| SaveError::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn read_yaml<T: DeserializeOwned>(path: &Path) -> Result<T, SaveError> {
+    let source = fs::read_to_string(path).map_err(|source| SaveError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    serde_yaml::from_str(&source).map_err(|source| SaveError::Yaml {
+        path: path.to_owned(),
+        source,
+    })
+}
@@ -0,0 +1,272 @@
+use crate::{map::CHUNK_SIZE, tiles::TileId};
+
+/// Количество тайлов в чанке
+pub const CHUNK_TILES: usize = CHUNK_SIZE.pow(3);
+
+/// Тайлы чанка. Чанк, целиком состоящий из одного тайла (воздух над землёй,
+/// камень глубоко под ней), хранит только этот тайл. Остальные чанки хранят
+/// палитру своих тайлов и для каждого тайла номер в палитре, упакованный
+/// в наименьшее подходящее число бит.
+#[derive(Clone, Debug)]
+pub enum TileStorage {
+    Uniform(TileId),
+    Paletted {
+        palette: Vec<TileId>,
+        /// Бит на номер в палитре: 1, 2, 4, 8 или 16, чтобы номера не пересекали
+        /// границы слов
+        bits: u32,
+        data: Box<[u64]>,
+    },
+}
+
+impl TileStorage {
+    pub fn from_tiles(tiles: impl IntoIterator<Item = TileId>) -> Self {
+        let mut palette: Vec<TileId> = Vec::new();
+        let mut indices = Vec::with_capacity(CHUNK_TILES);
+        for tile in tiles {
+            // Соседние тайлы обычно одинаковые, поэтому сначала проверяется последний
+            let index = match indices.last() {
+                Some(&last) if palette[last as usize] == tile => last,
+                _ => match palette.iter().position(|id| *id == tile) {
+                    Some(index) => index as u16,
+                    None => {
+                        palette.push(tile);
+                        (palette.len() - 1) as u16
+                    }
+                },
+            };
+            indices.push(index);
+        }
+        assert_eq!(
+            indices.len(),
+            CHUNK_TILES,
+            "Чанк должен быть заполнен целиком"
+        );
+        if palette.len() == 1 {
+            return TileStorage::Uniform(palette[0]);
+        }
+        let bits = bits_for(palette.len());
+        let mut data = vec![0; words(bits)].into_boxed_slice();
+        for (idx, index) in indices.into_iter().enumerate() {
+            write_packed(&mut data, bits, idx, index as u64);
+        }
+        TileStorage::Paletted {
+            palette,
+            bits,
+            data,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, idx: usize) -> TileId {
+        match self {
+            TileStorage::Uniform(tile) => *tile,
+            TileStorage::Paletted {
+                palette,
+                bits,
+                data,
+            } => palette[read_packed(data, *bits, idx) as usize],
+        }
+    }
+
+    /// Заменяет тайл. Если тайла ещё нет в палитре, он туда добавляется,
+    /// и при необходимости номера перепаковываются в большее число бит.
+    pub fn set(&mut self, idx: usize, tile: TileId) {
+        if let TileStorage::Uniform(current) = *self {
+            if current == tile {
+                return;
+            }
+            *self = TileStorage::Paletted {
+                palette: vec![current],
+                bits: 1,
+                data: vec![0; words(1)].into_boxed_slice(),
+            };
+        }
+        let TileStorage::Paletted {
+            palette,
+            bits,
+            data,
+        } = self
+        else {
+            unreachable!()
+        };
+        let index = match palette.iter().position(|id| *id == tile) {
+            Some(index) => index,
+            None => {
+                palette.push(tile);
+                let new_bits = bits_for(palette.len());
+                if new_bits != *bits {
+                    let mut new_data = vec![0; words(new_bits)].into_boxed_slice();
+                    for i in 0..CHUNK_TILES {
+                        write_packed(&mut new_data, new_bits, i, read_packed(data, *bits, i));
+                    }
+                    *bits = new_bits;
+                    *data = new_data;
+                }
+                palette.len() - 1
+            }
+        };
+        write_packed(data, *bits, idx, index as u64);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TileId> + '_ {
+        (0..CHUNK_TILES).map(|idx| self.get(idx))
+    }
+
+    /// Сколько байт занимают тайлы в куче
+    pub fn heap_size(&self) -> usize {
+        match self {
+            TileStorage::Uniform(_) => 0,
+            TileStorage::Paletted { palette, data, .. } => {
+                palette.capacity() * std::mem::size_of::<TileId>() + std::mem::size_of_val(&**data)
+            }
+        }
+    }
+}
+
+/// Одинаковые тайлы могут храниться по-разному, поэтому сравниваются сами тайлы
+impl PartialEq for TileStorage {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TileStorage::Uniform(a), TileStorage::Uniform(b)) => a == b,
+            _ => self.iter().eq(other.iter()),
+        }
+    }
+}
+
+/// Наименьшее подходящее число бит для номеров в палитре из len тайлов
+fn bits_for(len: usize) -> u32 {
+    match len {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        17..=256 => 8,
+        _ => 16,
+    }
+}
+
+fn words(bits: u32) -> usize {
+    CHUNK_TILES * bits as usize / 64
+}
+
+/// Номер слова и сдвиг в нём для номера тайла idx. Число бит - степень двойки,
+/// поэтому обходится без деления.
+#[inline]
+fn packed_position(bits: u32, idx: usize) -> (usize, u32) {
+    let bits_log = bits.trailing_zeros();
+    let per_word_log = 6 - bits_log;
+    let shift = ((idx & ((1 << per_word_log) - 1)) as u32) << bits_log;
+    (idx >> per_word_log, shift)
+}
+
+#[inline]
+fn read_packed(data: &[u64], bits: u32, idx: usize) -> u64 {
+    let (word, shift) = packed_position(bits, idx);
+    (data[word] >> shift) & ((1 << bits) - 1)
+}
+
+#[inline]
+fn write_packed(data: &mut [u64], bits: u32, idx: usize, value: u64) {
+    let (word, shift) = packed_position(bits, idx);
+    let word = &mut data[word];
+    *word = (*word & !(((1 << bits) - 1) << shift)) | (value << shift);
+}
+
+/// По одному биту на каждый тайл чанка: препятствия, запомненные тайлы.
+/// Если все биты одинаковые, память под них не выделяется.
+#[derive(Clone, Debug)]
+pub enum ChunkBits {
+    Uniform(bool),
+    Packed(Box<[u64]>),
+}
+
+impl ChunkBits {
+    pub fn from_bits(bits: impl IntoIterator<Item = bool>) -> Self {
+        let mut data = vec![0; words(1)].into_boxed_slice();
+        let mut count = 0;
+        for (idx, bit) in bits.into_iter().enumerate() {
+            data[idx / 64] |= (bit as u64) << (idx % 64);
+            count += 1;
+        }
+        assert_eq!(count, CHUNK_TILES, "Чанк должен быть заполнен целиком");
+        if data.iter().all(|word| *word == 0) {
+            ChunkBits::Uniform(false)
+        } else if data.iter().all(|word| *word == u64::MAX) {
+            ChunkBits::Uniform(true)
+        } else {
+            ChunkBits::Packed(data)
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, idx: usize) -> bool {
+        match self {
+            ChunkBits::Uniform(bit) => *bit,
+            ChunkBits::Packed(data) => data[idx / 64] >> (idx % 64) & 1 == 1,
+        }
+    }
+
+    pub fn set(&mut self, idx: usize, bit: bool) {
+        if let ChunkBits::Uniform(current) = *self {
+            if current == bit {
+                return;
+            }
+            let word = if current { u64::MAX } else { 0 };
+            *self = ChunkBits::Packed(vec![word; words(1)].into_boxed_slice());
+        }
+        if let ChunkBits::Packed(data) = self {
+            let word = &mut data[idx / 64];
+            *word = (*word & !(1 << (idx % 64))) | ((bit as u64) << (idx % 64));
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..CHUNK_TILES).map(|idx| self.get(idx))
+    }
+
+    /// Длины чередующихся серий нулевых и единичных битов, начиная с нулевых
+    pub fn runs(&self) -> Vec<u32> {
+        let mut runs = Vec::new();
+        let mut current = false;
+        let mut len = 0;
+        for bit in self.iter() {
+            if bit != current {
+                runs.push(len);
+                current = bit;
+                len = 0;
+            }
+            len += 1;
+        }
+        runs.push(len);
+        runs
+    }
+
+    pub fn from_runs(runs: &[u32]) -> Result<Self, String> {
+        let tiles_count = runs.iter().map(|run| *run as usize).sum::<usize>();
+        if tiles_count != CHUNK_TILES {
+            return Err(format!(
+                "runs must cover exactly {CHUNK_TILES} tiles, found {tiles_count}"
+            ));
+        }
+        Ok(Self::from_bits(runs.iter().enumerate().flat_map(
+            |(n, run)| std::iter::repeat(n % 2 == 1).take(*run as usize),
+        )))
+    }
+
+    /// Сколько байт занимают биты в куче
+    pub fn heap_size(&self) -> usize {
+        match self {
+            ChunkBits::Uniform(_) => 0,
+            ChunkBits::Packed(data) => std::mem::size_of_val(&**data),
+        }
+    }
+}
+
+impl PartialEq for ChunkBits {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ChunkBits::Uniform(a), ChunkBits::Uniform(b)) => a == b,
+            _ => self.iter().eq(other.iter()),
+        }
+    }
+}
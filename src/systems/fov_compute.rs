@@ -1,13 +1,23 @@
-use std::{collections::HashSet, sync::Mutex};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::HashSet,
+    hash::BuildHasher,
+};
 
 use hecs::World;
+use rationals::ConstRational;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use vek::Vec3;
 
 use crate::{
     components::Position,
-    map::{Chunk, Map, WorldMap, CHUNK_SIZE},
+    coords::{chunk_of, index_of},
+    hasher,
+    map::{Map, WorldMap, CHUNK_SIZE},
     need_components,
     player::Player,
+    registry::ComponentRegistry,
+    storage::ChunkBits,
     GameHasher,
 };
 
@@ -16,7 +26,7 @@ type Quad<T> = (T, T, T, T); //x1, y1, x2, y2
 #[derive(Clone, Debug)]
 struct Rect {
     depth: i32,
-    slope: Slope<f64>,
+    slope: Slope<Ray>,
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -27,9 +37,157 @@ struct Slope<T> {
     y2: T,
 }
 
+/// Сколько NPC за ход могут пересчитать поле зрения. Остальные до своей
+/// очереди видят то же, что и раньше, и с каждым ходом ожидания их очередь
+/// подходит ближе.
+pub const NPC_FOV_BUDGET: usize = 32;
+
 /// Компонент, означающий, что сущность с этим компонентом имеет поле зрения.
-/// Он имеет в себе радиус поля зрения и множество координат, которые сущность видит.
-pub struct Sight(pub u32, pub HashSet<(i32, i32, i32), GameHasher>);
+/// Он имеет в себе радиус поля зрения, множество координат относительно сущности,
+/// которые она видит, то, для чего это множество было посчитано, и сколько ходов
+/// устаревшее поле зрения ждёт пересчёта.
+pub struct Sight(pub u32, pub SightSet, pub Option<SightKey>, pub u32);
+
+/// То, от чего зависит поле зрения: позиция сущности, номер последнего
+/// изменения чанков в пределах её обзора и сущности с BlocksSight в них.
+/// Пока ключ не изменился, поле зрения не пересчитывается.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SightKey {
+    pub pos: Vec3<i32>,
+    pub revision: u64,
+    /// Сумма хэшей позиций загораживающих обзор сущностей, не зависящая
+    /// от их порядка
+    pub blockers: u64,
+}
+
+impl Sight {
+    pub fn new(radius: u32) -> Self {
+        Sight(radius, SightSet::new(radius), None, 0)
+    }
+}
+
+/// Видимые тайлы относительно сущности: по биту на тайл куба со стороной
+/// 2 * radius + 1 с сущностью в центре. Для радиуса 40 это 65 КиБ, намного
+/// меньше множества из сотен тысяч координат.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SightSet {
+    radius: i32,
+    bits: Box<[u64]>,
+    len: usize,
+}
+
+impl SightSet {
+    pub fn new(radius: u32) -> Self {
+        let side = 2 * radius as usize + 1;
+        SightSet {
+            radius: radius as i32,
+            bits: vec![0; side.pow(3).div_ceil(64)].into_boxed_slice(),
+            len: 0,
+        }
+    }
+
+    fn side(&self) -> i32 {
+        2 * self.radius + 1
+    }
+
+    /// Номер бита тайла или None, если тайл вне куба
+    fn index(&self, (x, y, z): (i32, i32, i32)) -> Option<usize> {
+        let r = self.radius;
+        if x.abs() > r || y.abs() > r || z.abs() > r {
+            return None;
+        }
+        let side = self.side() as usize;
+        Some(((z + r) as usize * side + (y + r) as usize) * side + (x + r) as usize)
+    }
+
+    pub fn contains(&self, pos: &(i32, i32, i32)) -> bool {
+        self.index(*pos)
+            .is_some_and(|idx| self.bits[idx / 64] & (1 << (idx % 64)) != 0)
+    }
+
+    /// Добавляет тайл и возвращает false, если он уже был виден
+    pub fn insert(&mut self, pos: (i32, i32, i32)) -> bool {
+        let idx = self
+            .index(pos)
+            .unwrap_or_else(|| panic!("Тайл {pos:?} вне радиуса обзора {}", self.radius));
+        let (word, bit) = (&mut self.bits[idx / 64], 1 << (idx % 64));
+        let added = *word & bit == 0;
+        *word |= bit;
+        self.len += added as usize;
+        added
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Видимые тайлы по порядку: x меняется быстрее всего, z медленнее всего
+    pub fn iter(&self) -> impl Iterator<Item = (i32, i32, i32)> + '_ {
+        let (r, side) = (self.radius, self.side());
+        self.bits
+            .iter()
+            .enumerate()
+            .flat_map(|(word_idx, &word)| {
+                let mut word = word;
+                std::iter::from_fn(move || {
+                    (word != 0).then(|| {
+                        let bit = word.trailing_zeros() as usize;
+                        word &= word - 1;
+                        word_idx * 64 + bit
+                    })
+                })
+            })
+            .map(move |idx| {
+                let idx = idx as i32;
+                (idx % side - r, idx / side % side - r, idx / side / side - r)
+            })
+    }
+}
+
+impl Extend<(i32, i32, i32)> for SightSet {
+    fn extend<T: IntoIterator<Item = (i32, i32, i32)>>(&mut self, iter: T) {
+        for pos in iter {
+            self.insert(pos);
+        }
+    }
+}
+
+/// Видимые тайлы вычисляются заново после загрузки, поэтому в описании
+/// компонента хранится только радиус.
+impl Serialize for Sight {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sight {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let radius = u32::deserialize(deserializer)?;
+        Ok(Sight::new(radius))
+    }
+}
+
+/// Компонент-маркер сущности, сквозь которую не видно, хотя пройти через её
+/// тайл можно: занавески, дым.
+#[derive(Serialize, Deserialize)]
+pub struct BlocksSight;
+
+/// Тайлы, которые загораживают сущности с BlocksSight
+pub type SightBlockers = HashSet<(i32, i32, i32), GameHasher>;
+
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Sight>("sight");
+    registry.register::<BlocksSight>("blocks_sight");
+}
 
 #[derive(Clone, Debug, Copy)]
 enum Direction {
@@ -41,72 +199,280 @@ enum Direction {
     Back,
 }
 
+const DIRECTIONS: [Direction; 6] = [
+    Direction::Up,
+    Direction::Left,
+    Direction::Down,
+    Direction::Right,
+    Direction::Forward,
+    Direction::Back,
+];
+
 pub fn run_fov_compute_system(world: &World) -> super::Result {
     let mut query = world.query::<(&mut WorldMap,)>();
     let (_, (map,)) = query
         .iter()
         .next()
         .ok_or(need_components!(FovSystem, WorldMap))?;
-    let mut query = world.query::<(&Player, &Position, &mut Sight)>();
-    let (_, (_, Position(cam_pos), Sight(sight_radius, sight_tiles))) = query
+    let blockers = sight_blockers(world);
+    let player_pos = run_player_sight(world, map, &blockers)?;
+    run_npc_sight(world, map, &blockers, player_pos);
+    Ok(())
+}
+
+/// Тайлы, которые сейчас загораживают сущности с BlocksSight
+pub fn sight_blockers(world: &World) -> SightBlockers {
+    world
+        .query::<(&Position, &BlocksSight)>()
         .iter()
-        .next()
-        .ok_or(need_components!(FovComputeSystem, Player, Position, Sight))?;
+        .map(|(_, (Position(pos), _))| (pos.x, pos.y, pos.z))
+        .collect()
+}
+
+/// Ключ поля зрения радиуса radius из pos по нынешней карте
+pub fn sight_key(
+    pos: Vec3<i32>,
+    radius: u32,
+    map: &WorldMap,
+    blockers: &SightBlockers,
+) -> SightKey {
+    let radius = radius as i32;
+    let (min, max) = (pos - radius, pos + radius);
+    let blockers = blockers
+        .iter()
+        .filter(|&&(x, y, z)| {
+            (min.x..=max.x).contains(&x)
+                && (min.y..=max.y).contains(&y)
+                && (min.z..=max.z).contains(&z)
+        })
+        .map(|blocker| hasher().hash_one(blocker))
+        .fold(0, u64::wrapping_add);
+    SightKey {
+        pos,
+        revision: map.revision_in(min, max),
+        blockers,
+    }
+}
+
+/// Записывает в sight новое поле зрения. Если взгляд упёрся в незагруженный
+/// чанк, поле зрения неполное, ключ не запоминается, и оно будет посчитано
+/// заново в следующий ход.
+fn store_sight(
+    sight: &mut Sight,
+    key: SightKey,
+    tiles: impl IntoIterator<Item = (i32, i32, i32)>,
+    complete: bool,
+) {
+    let Sight(sight_radius, sight_tiles, sight_key, waiting) = sight;
+    if sight_tiles.radius != *sight_radius as i32 {
+        *sight_tiles = SightSet::new(*sight_radius);
+    }
     sight_tiles.clear();
     sight_tiles.insert((0, 0, 0));
+    sight_tiles.extend(tiles);
+    *sight_key = complete.then_some(key);
+    *waiting = 0;
+}
 
-    let dirs = [
-        Direction::Up,
-        Direction::Left,
-        Direction::Down,
-        Direction::Right,
-        Direction::Forward,
-        Direction::Back,
-    ];
-    let chunks_depth = (*sight_radius / CHUNK_SIZE as u32 + 1) as i32;
+/// Чанки вокруг персонажа игрока запрашиваются каждый ход, чтобы он видел
+/// всё в радиусе обзора, а поле зрения пересчитывается, когда изменился ключ.
+fn run_player_sight(
+    world: &World,
+    map: &mut WorldMap,
+    blockers: &SightBlockers,
+) -> Result<Vec3<i32>, super::error::Error> {
+    let mut query = world.query::<(&Player, &Position, &mut Sight)>();
+    let (_, (_, Position(cam_pos), sight)) =
+        query
+            .iter()
+            .next()
+            .ok_or(need_components!(FovComputeSystem, Player, Position, Sight))?;
+    let sight_radius = &sight.0;
+
+    map.receive_chunks();
     let current_chunk = WorldMap::xy_chunk(cam_pos.x, cam_pos.y, cam_pos.z);
-    for i in -chunks_depth..=chunks_depth {
-        for j in -chunks_depth..=chunks_depth {
-            for k in -chunks_depth..=chunks_depth {
-                map.get_chunk_or_create(
-                    current_chunk.0 + i,
-                    current_chunk.1 + j,
-                    current_chunk.2 + k,
-                );
-            }
-        }
+    for (x, y, z) in sight_chunks(current_chunk, *sight_radius) {
+        map.request_chunk(x, y, z);
+    }
+    let key = sight_key(*cam_pos, *sight_radius, map, blockers);
+    if sight.2 == Some(key) {
+        return Ok(*cam_pos);
     }
-    let tmp_mutex = Mutex::new(Vec::new());
+    let (tiles, complete) = sight_tiles_parallel(*cam_pos, *sight_radius, map, blockers);
+    store_sight(sight, key, tiles, complete);
+    Ok(*cam_pos)
+}
+
+/// Поле зрения остальных сущностей с Sight. Оно пересчитывается, только когда
+/// изменился ключ, и не больше чем для NPC_FOV_BUDGET сущностей за ход: первыми
+/// те, что ждут дольше, а среди них ближайшие к персонажу игрока, поэтому
+/// дальние NPC тоже дожидаются очереди. Чанки для NPC не запрашиваются:
+/// незагруженные чанки загораживают им обзор.
+fn run_npc_sight(world: &World, map: &WorldMap, blockers: &SightBlockers, player_pos: Vec3<i32>) {
+    let mut query = world
+        .query::<(&Position, &mut Sight)>()
+        .without::<&Player>();
+    let mut stale = query
+        .iter()
+        .map(|(_, (Position(pos), sight))| (sight_key(*pos, sight.0, map, blockers), sight))
+        .filter(|(key, sight)| sight.2 != Some(*key))
+        .collect::<Vec<_>>();
+    for (_, sight) in stale.iter_mut() {
+        sight.3 += 1;
+    }
+    stale
+        .sort_by_key(|(key, sight)| (Reverse(sight.3), (key.pos - player_pos).map(i32::abs).sum()));
+    stale.truncate(NPC_FOV_BUDGET);
+    if stale.is_empty() {
+        return;
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = stale.len().div_ceil(threads);
     std::thread::scope(|s| {
-        for dir in dirs.iter() {
-            let handle = s.spawn({
-                let sight_radius = *sight_radius;
-                let cam_pos = *cam_pos;
-                let map = &*map;
-                let tmp_mutex = &tmp_mutex;
-                move || {
-                    let vec = cast(&cam_pos, dir, map, sight_radius);
-                    let mut tmp = tmp_mutex.lock().unwrap();
-                    tmp.extend(vec);
+        for part in stale.chunks_mut(per_thread) {
+            s.spawn(move || {
+                for (key, sight) in part {
+                    compute_npc_sight(*key, sight, map, blockers);
                 }
             });
-            handle.join().unwrap();
         }
     });
-    sight_tiles.extend(tmp_mutex.into_inner().unwrap());
-    Ok(())
+}
+
+/// NPC считаются параллельно друг с другом, поэтому направления обзора
+/// каждого из них считаются по очереди.
+fn compute_npc_sight(key: SightKey, sight: &mut Sight, map: &WorldMap, blockers: &SightBlockers) {
+    let (tiles, complete) = sight_tiles(key.pos, sight.0, map, blockers);
+    store_sight(sight, key, tiles, complete);
+}
+
+/// Видимые из pos тайлы по всем направлениям в порядке DIRECTIONS и то,
+/// были ли загружены все чанки, которые задел взгляд. Направления
+/// считаются по очереди.
+pub fn sight_tiles(
+    pos: Vec3<i32>,
+    radius: u32,
+    map: &WorldMap,
+    blockers: &SightBlockers,
+) -> (Vec<(i32, i32, i32)>, bool) {
+    let opacity = Opacity::new(map, pos, radius);
+    merge_casts(
+        DIRECTIONS
+            .iter()
+            .map(|dir| cast(&pos, dir, &opacity, blockers, radius)),
+    )
+}
+
+/// То же, что sight_tiles, вплоть до порядка тайлов, но каждое направление
+/// считается в своём потоке
+pub fn sight_tiles_parallel(
+    pos: Vec3<i32>,
+    radius: u32,
+    map: &WorldMap,
+    blockers: &SightBlockers,
+) -> (Vec<(i32, i32, i32)>, bool) {
+    let opacity = Opacity::new(map, pos, radius);
+    std::thread::scope(|s| {
+        let handles = DIRECTIONS.map(|dir| {
+            let opacity = &opacity;
+            s.spawn(move || cast(&pos, &dir, opacity, blockers, radius))
+        });
+        // Потоки ни с чем не делятся, а результаты собираются в порядке направлений
+        merge_casts(handles.into_iter().map(|handle| handle.join().unwrap()))
+    })
+}
+
+fn merge_casts(
+    casts: impl Iterator<Item = (Vec<(i32, i32, i32)>, bool)>,
+) -> (Vec<(i32, i32, i32)>, bool) {
+    let mut tiles = Vec::new();
+    let mut complete = true;
+    for (cast_tiles, cast_complete) in casts {
+        tiles.extend(cast_tiles);
+        complete &= cast_complete;
+    }
+    (tiles, complete)
+}
+
+/// Непрозрачность чанков в кубе обзора. Она копируется из карты один раз
+/// перед расчётом, чтобы направления обзора не ждали друг друга на блокировках
+/// чанков. Копия чанка из одинаковых тайлов не занимает памяти.
+struct Opacity {
+    /// Чанк с наименьшими координатами
+    min: Vec3<i32>,
+    /// Сколько чанков в кубе по каждой оси
+    size: Vec3<i32>,
+    /// None у незагруженных чанков
+    chunks: Vec<Option<ChunkBits>>,
+}
+
+impl Opacity {
+    fn new(map: &WorldMap, pos: Vec3<i32>, radius: u32) -> Self {
+        let (min, max) = (pos - radius as i32, pos + radius as i32);
+        let min = Vec3::from(chunk_of(min.x, min.y, min.z));
+        let max = Vec3::from(chunk_of(max.x, max.y, max.z));
+        let chunks = (min.z..=max.z)
+            .flat_map(|z| (min.y..=max.y).map(move |y| (y, z)))
+            .flat_map(|(y, z)| (min.x..=max.x).map(move |x| (x, y, z)))
+            .map(|(x, y, z)| {
+                map.get_chunk(x, y, z)
+                    .map(|chunk| chunk.lock().unwrap().opaque.clone())
+            })
+            .collect();
+        Opacity {
+            min,
+            size: max - min + 1,
+            chunks,
+        }
+    }
+
+    /// Непрозрачен ли тайл или None, если его чанк не загружен
+    fn get(&self, x: i32, y: i32, z: i32) -> Option<bool> {
+        let chunk = Vec3::<i32>::from(chunk_of(x, y, z)) - self.min;
+        let idx = (chunk.z * self.size.y + chunk.y) * self.size.x + chunk.x;
+        self.chunks[idx as usize]
+            .as_ref()
+            .map(|bits| bits.get(index_of(x, y, z)))
+    }
+}
+
+/// Чанки, которые может задеть поле зрения радиуса sight_radius из чанка
+/// current_chunk, начиная с ближайших, чтобы они и генерировались первыми
+pub fn sight_chunks(current_chunk: (i32, i32, i32), sight_radius: u32) -> Vec<(i32, i32, i32)> {
+    let chunks_depth = (sight_radius / CHUNK_SIZE as u32 + 1) as i32;
+    let mut chunks = Vec::new();
+    for i in -chunks_depth..=chunks_depth {
+        for j in -chunks_depth..=chunks_depth {
+            for k in -chunks_depth..=chunks_depth {
+                chunks.push((i, j, k));
+            }
+        }
+    }
+    chunks.sort_by_key(|(i, j, k)| i.abs().max(j.abs()).max(k.abs()));
+    chunks
+        .into_iter()
+        .map(|(i, j, k)| {
+            (
+                current_chunk.0 + i,
+                current_chunk.1 + j,
+                current_chunk.2 + k,
+            )
+        })
+        .collect()
 }
 
 impl Rect {
-    const fn new(depth: i32, slope: Slope<f64>) -> Self {
+    const fn new(depth: i32, slope: Slope<Ray>) -> Self {
         Rect { depth, slope }
     }
 
     fn tiles(&self) -> (i32, Quad<i32>) {
-        let x1 = (self.slope.x1 * self.depth as f64 + 0.5).floor() as i32;
-        let y1 = (self.slope.y1 * self.depth as f64 + 0.5).floor() as i32;
-        let x2 = (self.slope.x2 * self.depth as f64 - 0.5).ceil() as i32;
-        let y2 = (self.slope.y2 * self.depth as f64 - 0.5).ceil() as i32;
+        let depth = self.depth;
+        let x1 = self.slope.x1.first_tile(depth);
+        let y1 = self.slope.y1.first_tile(depth);
+        let x2 = self.slope.x2.last_tile(depth);
+        let y2 = self.slope.y2.last_tile(depth);
 
         (self.depth, (x1, y1, x2, y2))
     }
@@ -115,15 +481,89 @@ impl Rect {
     }
 }
 
-pub fn slope(depth: i32, col: f64) -> f64 {
-    (2. * col - 1.) / (2. * depth as f64)
+/// Наклон луча: дробь numerator / denominator с положительным знаменателем.
+/// Наклоны считаются точно, в дробях: с f64 тайлы на краю дальнего обзора
+/// видны только с одной стороны, а одна и та же граница на разной глубине
+/// округляется по-разному. В отличие от ConstRational дробь не сокращается,
+/// а сравнивается перекрёстным умножением: наклоны строятся на каждом тайле,
+/// и поиск общего делителя обходился бы дороже самого сравнения. С остальным
+/// кодом наклоны обмениваются в виде ConstRational.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    numerator: i32,
+    denominator: i32,
 }
 
+impl Ray {
+    /// Первый тайл, центр которого не левее луча на глубине depth:
+    /// floor(наклон * depth + 1/2)
+    fn first_tile(self, depth: i32) -> i32 {
+        let numerator = 2 * self.numerator as i64 * depth as i64 + self.denominator as i64;
+        numerator.div_euclid(2 * self.denominator as i64) as i32
+    }
+
+    /// Последний тайл, центр которого не правее луча на глубине depth:
+    /// ceil(наклон * depth - 1/2)
+    fn last_tile(self, depth: i32) -> i32 {
+        let numerator = 2 * self.numerator as i64 * depth as i64 - self.denominator as i64;
+        -(-numerator).div_euclid(2 * self.denominator as i64) as i32
+    }
+}
+
+impl Ord for Ray {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.numerator as i64 * other.denominator as i64)
+            .cmp(&(other.numerator as i64 * self.denominator as i64))
+    }
+}
+
+impl PartialOrd for Ray {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ray {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ray {}
+
+impl From<ConstRational> for Ray {
+    fn from(slope: ConstRational) -> Self {
+        Ray {
+            numerator: slope.numerator(),
+            denominator: slope.denominator(),
+        }
+    }
+}
+
+impl From<Ray> for ConstRational {
+    fn from(ray: Ray) -> Self {
+        ConstRational::new(ray.numerator, ray.denominator)
+    }
+}
+
+/// Наклон луча к левому краю тайла col на глубине depth
+pub const fn slope(depth: i32, col: i32) -> Ray {
+    Ray {
+        numerator: 2 * col - 1,
+        denominator: 2 * depth,
+    }
+}
+
+/// Лежит ли центр тайла между краями прямоугольника. Проверяется на каждом
+/// тайле, поэтому coord >= slope * depth сравнивается перекрёстным умножением,
+/// а не через произведение дробей.
 fn is_symmetric(rect: &Rect, x: i32, y: i32) -> bool {
-    let x_symmetric = x as f64 >= rect.depth as f64 * rect.slope.x1
-        && x as f64 <= rect.depth as f64 * rect.slope.x2;
-    let y_symmetric = y as f64 >= rect.depth as f64 * rect.slope.y1
-        && y as f64 <= rect.depth as f64 * rect.slope.y2;
+    let depth = rect.depth as i64;
+    let cmp = |coord: i32, slope: Ray| {
+        (coord as i64 * slope.denominator as i64).cmp(&(slope.numerator as i64 * depth))
+    };
+    let x_symmetric = cmp(x, rect.slope.x1).is_ge() && cmp(x, rect.slope.x2).is_le();
+    let y_symmetric = cmp(y, rect.slope.y1).is_ge() && cmp(y, rect.slope.y2).is_le();
 
     x_symmetric && y_symmetric
 }
@@ -139,117 +579,109 @@ const fn transform(direction: &Direction, x: i32, y: i32, depth: i32) -> (i32, i
     }
 }
 
+/// Видимые из cam_pos в направлении dir тайлы и то, не попался ли по пути
+/// незагруженный чанк. Взгляд загораживают непрозрачные тайлы и сущности
+/// из blockers, а не препятствия для движения.
 fn cast(
     cam_pos: &Vec3<i32>,
     dir: &Direction,
-    map: &WorldMap,
+    opacity: &Opacity,
+    blockers: &SightBlockers,
     sight_radius: u32,
-) -> Vec<(i32, i32, i32)> {
+) -> (Vec<(i32, i32, i32)>, bool) {
     let mut sight_tiles = Vec::new();
+    let mut complete = true;
     let mut rect_stack: Vec<Rect> = Vec::new();
     let init_rect = Rect::new(
         1,
         Slope {
-            x1: -1.,
-            y1: -1.,
-            x2: 1.,
-            y2: 1.,
+            x1: (-ConstRational::ONE).into(),
+            y1: (-ConstRational::ONE).into(),
+            x2: ConstRational::ONE.into(),
+            y2: ConstRational::ONE.into(),
         },
     );
     rect_stack.push(init_rect);
 
-    let mut prev_chunk_mutex: Option<(&Mutex<Chunk>, i32, i32, i32)> = None;
-
     let shift_back =
         |pos: (i32, i32, i32)| (pos.0 + cam_pos.x, pos.1 + cam_pos.y, pos.2 + cam_pos.z);
     while let Some(rect) = rect_stack.pop() {
         let (depth, (x1, y1, x2, y2)) = rect.tiles();
-        let mut is_obstacle_on_prev_row: Option<bool> = None;
-        let mut rect = rect.clone(); // Прямоугольник, который может быть продолжен или прерван препятствием
+        let mut is_opaque_on_prev_row: Option<bool> = None;
+        let mut rect = rect.clone(); // Прямоугольник, который может быть продолжен или прерван непрозрачным тайлом
         for y in y1..=y2 {
-            let mut is_obstacle_on_row = false;
-            let mut is_prev_x_obstacle: Option<bool> = None;
+            let mut is_opaque_on_row = false;
+            let mut is_prev_x_opaque: Option<bool> = None;
 
             let mut str_rect = rect.clone();
 
-            if is_obstacle_on_prev_row.is_some_and(|x| x) && !is_obstacle_on_row {
-                // Если на прошлой строке были препятствия, а на этой - нет, обрезаем сзади прямоугольник и снова тянем
-                rect.slope.y1 = slope(depth, y as f64);
+            if is_opaque_on_prev_row.is_some_and(|x| x) && !is_opaque_on_row {
+                // Если на прошлой строке были непрозрачные тайлы, а на этой - нет, обрезаем сзади прямоугольник и снова тянем
+                rect.slope.y1 = slope(depth, y);
             }
-            str_rect.slope.y1 = rect.slope.y1.max(slope(depth, y as f64 - 1.));
-            str_rect.slope.y2 = rect.slope.y2.min(slope(depth, y as f64 + 1.));
+            str_rect.slope.y1 = rect.slope.y1.max(slope(depth, y - 1));
+            str_rect.slope.y2 = rect.slope.y2.min(slope(depth, y + 1));
             for x in x1..=x2 {
-                let radio_sqr = (x.pow(2) + y.pow(2) + depth.pow(2)) as f64;
-                let in_sight_radius = radio_sqr <= 1. + sight_radius.pow(2) as f64;
+                let radio_sqr = x.pow(2) + y.pow(2) + depth.pow(2);
+                // При радиусе 0 шар радиуса обзора задевает соседние тайлы,
+                // а в SightSet помещается только куб со стороной 2 * радиус + 1
+                let in_sight_radius =
+                    depth <= sight_radius as i32 && radio_sqr <= 1 + sight_radius.pow(2) as i32;
                 if !in_sight_radius {
                     continue;
                 }
-                let slope_x = slope(depth, x as f64);
-
                 let crds = transform(dir, x, y, depth);
                 let (x_crd, y_crd, z_crd) = shift_back(crds);
-                let (ch_x, ch_y, ch_z) = WorldMap::xy_chunk(x_crd, y_crd, z_crd);
-                let chunk_mutex = match prev_chunk_mutex {
-                    Some((mutex, p_ch_x, p_ch_y, p_ch_z))
-                        if p_ch_x == ch_x && p_ch_y == ch_y && p_ch_z == ch_z =>
-                    {
-                        mutex
-                    }
-                    _ => {
-                        let new_chunk_mutex = map.get_chunk(ch_x, ch_y, ch_z).unwrap();
-                        prev_chunk_mutex = Some((new_chunk_mutex, ch_x, ch_y, ch_z));
-                        new_chunk_mutex
-                    }
-                };
-
-                let chunk = chunk_mutex.lock().unwrap();
-                let is_obstacle = chunk.get_obstacle(x_crd, y_crd, z_crd);
-                is_obstacle_on_row = is_obstacle_on_row || is_obstacle;
-
-                // Отправляем тайл в видимые, если он виден или это препятствие
-                if is_obstacle || is_symmetric(&rect, x, y) {
+                let opaque = opacity.get(x_crd, y_crd, z_crd);
+
+                // Ещё не загруженный чанк загораживает обзор, но сам не виден
+                let is_opaque = opaque.unwrap_or(true) || blockers.contains(&(x_crd, y_crd, z_crd));
+                complete &= opaque.is_some();
+                is_opaque_on_row = is_opaque_on_row || is_opaque;
+
+                // Отправляем тайл в видимые, если он виден или непрозрачен
+                if opaque.is_some() && (is_opaque || is_symmetric(&rect, x, y)) {
                     sight_tiles.push(crds);
                 }
 
-                if is_prev_x_obstacle.is_some_and(|x| !x) && is_obstacle {
-                    // Если мы после пустого тайла встречаем стену, то режем справа и пушим
+                if is_prev_x_opaque.is_some_and(|x| !x) && is_opaque {
+                    // Если мы после прозрачного тайла встречаем стену, то режем справа и пушим
                     let mut str_rect = str_rect.clone();
-                    str_rect.slope.x2 = slope_x;
+                    str_rect.slope.x2 = slope(depth, x);
                     let new_str_rect = str_rect.next();
                     rect_stack.push(new_str_rect.clone());
                 }
-                if !is_obstacle && is_prev_x_obstacle.is_some_and(|x| x) {
-                    // После препятствия выставляем начало скоса на прямоугольнике этой строки
-                    str_rect.slope.x1 = slope_x;
+                if !is_opaque && is_prev_x_opaque.is_some_and(|x| x) {
+                    // После непрозрачного тайла выставляем начало скоса на прямоугольнике этой строки
+                    str_rect.slope.x1 = slope(depth, x);
                 }
-                is_prev_x_obstacle = Some(is_obstacle);
+                is_prev_x_opaque = Some(is_opaque);
             }
 
-            if is_obstacle_on_row
-                && is_prev_x_obstacle.is_some_and(|x| !x)
-                && (rect.depth as f64) < sight_radius as f64
+            if is_opaque_on_row
+                && is_prev_x_opaque.is_some_and(|x| !x)
+                && rect.depth < sight_radius as i32
             {
                 let new_str_rect = str_rect.next();
 
                 rect_stack.push(new_str_rect);
             }
 
-            if is_obstacle_on_row
-                && is_obstacle_on_prev_row.is_some_and(|x| !x)
+            if is_opaque_on_row
+                && is_opaque_on_prev_row.is_some_and(|x| !x)
                 && rect.depth < sight_radius as i32
             {
-                // Если на этой строке нашлось препятствие, то прошлый прямоугольник обрезаем спереди и пушим
+                // Если на этой строке нашёлся непрозрачный тайл, то прошлый прямоугольник обрезаем спереди и пушим
                 let mut rect = rect.clone();
-                rect.slope.y2 = slope(depth, y as f64);
+                rect.slope.y2 = slope(depth, y);
                 let new_rect = rect.next();
                 rect_stack.push(new_rect);
             }
-            is_obstacle_on_prev_row = Some(is_obstacle_on_row);
+            is_opaque_on_prev_row = Some(is_opaque_on_row);
         }
-        if is_obstacle_on_prev_row.is_some_and(|x| !x) && (rect.depth as f64) < sight_radius as f64
-        {
+        if is_opaque_on_prev_row.is_some_and(|x| !x) && rect.depth < sight_radius as i32 {
             rect_stack.push(rect.next());
         }
     }
-    sight_tiles
+    (sight_tiles, complete)
 }
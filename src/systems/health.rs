@@ -1,9 +1,16 @@
 use std::collections::HashMap;
 
-use hecs::{Entity, World};
+use hecs::{Entity, EntityBuilder, World};
 use rand::{seq::IteratorRandom, Rng};
+use serde::{de, Deserialize, Serialize};
+use serde_yaml::Value;
 
-use crate::{hasher, mob::Log, GameHasher, Property};
+use crate::{
+    hasher,
+    mob::Log,
+    registry::{save_component, ComponentRegistry, TemplateContext},
+    GameHasher, Property,
+};
 
 /// Компонент, временно выполняющий роль здоровья у мобов
 /// Позже планируется заменить его на полноценную систему конечностей и органов
@@ -14,6 +21,44 @@ pub struct Health(pub i32);
 #[derive(Copy, Clone)]
 pub struct WantsAttack(pub Wound, pub Entity);
 
+/// Компонент-маркер мёртвой сущности
+#[derive(Serialize, Deserialize)]
+pub struct Dead;
+
+/// Органы, без которых сущность умирает
+const VITAL_ORGANS: [&str; 2] = ["brain", "heart"];
+/// Сколько ран выдерживает орган, прежде чем перестать работать
+const ORGAN_MAX_WOUNDS: usize = 3;
+
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register_with("body", load_body, save_component::<Body>);
+    registry.register::<Dead>("dead");
+}
+
+/// В шаблоне сущности тело указывается именем шаблона из body_templates.yaml,
+/// а выгружается полностью, вместе со всеми ранами.
+fn load_body(
+    value: Value,
+    context: &TemplateContext,
+    eb: &mut EntityBuilder,
+) -> Result<(), serde_yaml::Error> {
+    let body = match value {
+        Value::String(body_name) => context
+            .body_templates
+            .get(body_name.as_str())
+            .cloned()
+            .ok_or_else(|| {
+                <serde_yaml::Error as de::Error>::custom(format!(
+                    "unknown body template {body_name}"
+                ))
+            })?,
+        value => serde_yaml::from_value(value)?,
+    };
+    eb.add(body);
+    Ok(())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Body {
     parts: HashMap<String, BodyPart, GameHasher>,
 }
@@ -31,8 +76,12 @@ impl Body {
     pub fn add_part(&mut self, part_name: String, part: BodyPart) {
         self.parts.insert(part_name, part);
     }
+    pub fn part(&self, part_name: &str) -> Option<&BodyPart> {
+        self.parts.get(part_name)
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BodyPart {
     parts: HashMap<String, BodyPartPart, GameHasher>,
 }
@@ -50,8 +99,12 @@ impl BodyPart {
     pub fn add_part(&mut self, part_name: String, part: BodyPartPart) {
         self.parts.insert(part_name, part);
     }
+    pub fn part(&self, part_name: &str) -> Option<&BodyPartPart> {
+        self.parts.get(part_name)
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BodyPartPart {
     bone_groups: HashMap<String, BoneGroup, GameHasher>,
     skin: SkinPart,
@@ -91,8 +144,18 @@ impl BodyPartPart {
     pub fn add_property(&mut self, property_name: String, property: Property) {
         self.properties.insert(property_name, property);
     }
+    pub fn has_organ(&self, organ_name: &str) -> bool {
+        self.organs.contains_key(organ_name)
+    }
+    pub fn has_bone_group(&self, bone_group_name: &str) -> bool {
+        self.bone_groups.contains_key(bone_group_name)
+    }
+    pub fn has_property(&self, property_name: &str) -> bool {
+        self.properties.contains_key(property_name)
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MuscleGroup {
     wounds: Vec<Wound>,
 }
@@ -109,10 +172,12 @@ impl MuscleGroup {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SkinPart {
     wounds: Vec<Wound>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Organ {
     wounds: Vec<Wound>,
 }
@@ -121,9 +186,20 @@ impl Organ {
     pub fn new() -> Self {
         Self { wounds: Vec::new() }
     }
+    pub fn is_destroyed(&self) -> bool {
+        self.wounds.len() >= ORGAN_MAX_WOUNDS
+    }
 }
 
-#[derive(Copy, Clone)]
+impl BoneGroup {
+    pub fn new() -> Self {
+        Self {
+            fractures: Vec::new(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub enum Wound {
     Incised,
     Stabbed,
@@ -135,51 +211,61 @@ pub enum Wound {
     Surgical,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BoneGroup {
     fractures: Vec<Fracture>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Fracture {
     Open,
     Closed,
 }
 
 pub fn run_attack_system(world: &mut World) -> anyhow::Result<()> {
+    attack_with_rng(world, &mut rand::thread_rng())
+}
+
+/// Система атаки, которая выбирает части тела и органы под ударом с помощью rng,
+/// чтобы исход ударов можно было воспроизвести
+pub fn attack_with_rng(world: &mut World, rng: &mut impl Rng) -> anyhow::Result<()> {
     let mut attackers_bind = world.query::<(&WantsAttack,)>();
     let attackers: Vec<_> = attackers_bind.iter().map(|(e, (a,))| (e, *a)).collect();
     drop(attackers_bind);
     for (e, WantsAttack(damage, target)) in attackers.iter() {
         let mut log = String::new();
+        let mut is_killed = false;
         if let Ok((target_body,)) = world.query_one_mut::<(&mut Body,)>(*target) {
-            let mut rng = rand::thread_rng();
-            let target_part = target_body.parts.iter_mut().choose(&mut rng).unwrap();
+            let target_part = target_body.parts.iter_mut().choose(rng).unwrap();
             //TODO: рандомизировать урон
             //TODO: убрать полный рандом, сделать возможность прицеливаться для удара
-            let target_part_part = target_part.1.parts.iter_mut().choose(&mut rng).unwrap();
+            let target_part_part = target_part.1.parts.iter_mut().choose(rng).unwrap();
 
             let organs_count = target_part_part.1.organs.len();
-            let target_organs_count = rng.gen_range(0..organs_count / 3);
+            let target_organs_count = rng.gen_range(0..=organs_count / 3);
             let mut target_organs = target_part_part
                 .1
                 .organs
                 .iter_mut()
-                .choose_multiple(&mut rng, target_organs_count);
-            let target_bone_group = target_part_part
-                .1
-                .bone_groups
-                .iter_mut()
-                .choose(&mut rng)
-                .unwrap();
+                .choose_multiple(rng, target_organs_count);
             log.push_str("You are bruising something, you have received wounds: ");
             for organ in target_organs.iter_mut() {
                 organ.1.wounds.push(*damage);
                 log.push_str(format!("{} ", organ.0).as_str());
+                is_killed |= VITAL_ORGANS.contains(&organ.0.as_str()) && organ.1.is_destroyed();
             }
             target_part_part.1.muscles.wounds.push(*damage);
             target_part_part.1.skin.wounds.push(*damage);
-            // FIXME добавить более продвинутую обработку ран
-            target_bone_group.1.fractures.push(Fracture::Closed);
-            log.push_str(format!("and {} fracture", target_bone_group.0).as_str());
+            // У некоторых частей тела (например, у ног из шаблона) костей может не быть
+            if let Some(target_bone_group) = target_part_part.1.bone_groups.iter_mut().choose(rng) {
+                // FIXME добавить более продвинутую обработку ран
+                target_bone_group.1.fractures.push(Fracture::Closed);
+                log.push_str(format!("and {} fracture", target_bone_group.0).as_str());
+            }
+        }
+        if is_killed {
+            log.push_str(", the target is dead");
+            world.insert_one(*target, Dead)?;
         }
         if let Ok((attacker_log,)) = world.query_one_mut::<(&mut Log,)>(*e) {
             attacker_log.0.push_str(log.as_str());
@@ -37,6 +37,11 @@ pub fn run_input_system(game: &mut Game) -> InputSystemResult<()> {
                     game.next_action = val.to_owned();
                 }
             }
+            UIState::ChooseDirection(_) => {
+                if let Some(val) = get_dialog(game, "direction")?.get(&key) {
+                    game.next_action = val.to_owned();
+                }
+            }
             UIState::Log { .. } => {
                 if let Some(val) = get_dialog(game, "log")?.get(&key) {
                     game.next_action = val.to_owned();
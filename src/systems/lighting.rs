@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use hecs::World;
+use serde::{Deserialize, Serialize};
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    coords::{global_coord, global_of, index_of},
+    hasher,
+    light::{Light, LightMap, LightSourceId, LitArea, SkyColumn},
+    map::{WorldMap, CHUNK_SIZE},
+    need_components,
+    registry::ComponentRegistry,
+    GameHasher,
+};
+
+use super::fov_compute::{sight_blockers, sight_key, sight_tiles, Sight, SightBlockers, SightKey};
+
+/// Компонент сущности, которая светит: фонарь в руках, костёр, светящийся гриб
+#[derive(Serialize, Deserialize)]
+pub struct LightSource(pub Light);
+
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<LightSource>("light_source");
+}
+
+/// Замечает ли сущность из pos тайл target: он должен быть в её поле зрения
+/// и достаточно освещён
+pub fn perceives(sight: &Sight, light: &LightMap, pos: Vec3<i32>, target: Vec3<i32>) -> bool {
+    sight.1.contains(&(target - pos).into_tuple()) && light.is_lit(target.into_tuple())
+}
+
+/// Пересчитывает открытость небу столбцов, в которых изменились чанки, и свет
+/// источников, которые сдвинулись, изменились или вокруг которых изменилась
+/// карта, и убирает свет исчезнувших источников. Свет, который не достаёт
+/// до куба обзора ни одной сущности с Sight, не пересчитывается, пока к нему
+/// никто не подойдёт. Источники считаются параллельно, как поле зрения NPC.
+pub fn run_lighting_system(world: &World) -> super::Result {
+    let mut query = world.query::<(&mut WorldMap,)>();
+    let (_, (map,)) = query
+        .iter()
+        .next()
+        .ok_or(need_components!(LightingSystem, WorldMap))?;
+    update_sky(map);
+    let blockers = sight_blockers(world);
+    let viewers = world
+        .query::<(&Position, &Sight)>()
+        .iter()
+        .map(|(_, (Position(pos), Sight(radius, ..)))| (*pos, *radius as i32))
+        .collect::<Vec<_>>();
+    let seen = |pos: Vec3<i32>, light: &Light| {
+        viewers.iter().any(|&(viewer, radius)| {
+            let reach = radius + light.radius as i32;
+            (viewer - pos).map(i32::abs).reduce_max() <= reach
+        })
+    };
+    let mut sources = world
+        .query::<(&Position, &LightSource)>()
+        .iter()
+        .map(|(entity, (Position(pos), LightSource(light)))| {
+            (LightSourceId::Entity(entity), *pos, *light)
+        })
+        .collect::<Vec<_>>();
+    sources.extend(tile_lights(map));
+
+    let ids = sources
+        .iter()
+        .map(|(id, ..)| *id)
+        .collect::<HashSet<_, GameHasher>>();
+    map.light.retain_sources(|id| ids.contains(&id));
+    let stale = sources
+        .into_iter()
+        .filter(|(_, pos, light)| seen(*pos, light))
+        .map(|(id, pos, light)| (id, light, sight_key(pos, light.radius, map, &blockers)))
+        .filter(|(id, light, key)| {
+            map.light
+                .source(*id)
+                .is_none_or(|area| area.key != Some(*key) || area.light != *light)
+        })
+        .collect::<Vec<_>>();
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = stale.len().div_ceil(threads);
+    let areas = {
+        let (map, blockers) = (&*map, &blockers);
+        std::thread::scope(|s| {
+            let handles = stale
+                .chunks(per_thread)
+                .map(|part| {
+                    s.spawn(move || {
+                        part.iter()
+                            .map(|&(id, light, key)| (id, lit_area(key, light, map, blockers)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        })
+    };
+    for (id, area) in areas {
+        map.light.set_source(id, area);
+    }
+    Ok(())
+}
+
+/// Пересчитывает открытость небу столбцов чанков, в которых чанки изменились,
+/// загрузились или выгрузились
+fn update_sky(map: &mut WorldMap) {
+    let mut columns = HashMap::<_, Vec<_>, _>::with_hasher(hasher());
+    for &(x, y, z) in map.chunks.keys() {
+        let revision = map.chunk_revisions.get(&(x, y, z)).copied().unwrap_or(0);
+        columns.entry((x, y)).or_default().push((z, revision));
+    }
+    map.light
+        .retain_sky_columns(|column| columns.contains_key(&column));
+    for (column, mut key) in columns {
+        key.sort_unstable();
+        if map
+            .light
+            .sky_column(column)
+            .is_some_and(|sky| sky.key == key)
+        {
+            continue;
+        }
+        let floors = sky_floors(map, column, &key);
+        map.light.set_sky_column(column, SkyColumn { key, floors });
+    }
+}
+
+/// Высоты самых верхних тайлов, до которых не доходит небо, в столбцах чанка column
+/// с загруженными чанками chunks. Что выше загруженных чанков, неизвестно,
+/// поэтому столбец открыт небу, только если верх загруженных чанков не ниже
+/// поверхности генератора: нависающие склоны и префабы выше неё не учитываются.
+fn sky_floors(map: &WorldMap, (ch_x, ch_y): (i32, i32), chunks: &[(i32, u64)]) -> Box<[i32]> {
+    // Сверху вниз
+    let chunks = chunks
+        .iter()
+        .rev()
+        .map(|&(ch_z, _)| (ch_z, map.chunks[&(ch_x, ch_y, ch_z)].lock().unwrap()))
+        .collect::<Vec<_>>();
+    let mut floors = vec![i32::MIN; CHUNK_SIZE * CHUNK_SIZE].into_boxed_slice();
+    for (index, floor) in floors.iter_mut().enumerate() {
+        let x = global_coord(ch_x, index % CHUNK_SIZE);
+        let y = global_coord(ch_y, index / CHUNK_SIZE);
+        let mut above = None;
+        'column: for (ch_z, chunk) in chunks.iter() {
+            let top = global_coord(*ch_z, CHUNK_SIZE - 1);
+            // Над этим чанком не загруженный чанк, а он может быть под землёй
+            let buried = above != Some(ch_z + 1)
+                && !chunk.opaque.get(index_of(x, y, top))
+                && map
+                    .generator
+                    .ground_level(map.seed, x, y)
+                    .is_some_and(|ground| top < ground - 1);
+            if buried {
+                *floor = top;
+                break;
+            }
+            for z in (global_coord(*ch_z, 0)..=top).rev() {
+                if chunk.opaque.get(index_of(x, y, z)) {
+                    // Верх непрозрачного тайла небо освещает
+                    *floor = z - 1;
+                    break 'column;
+                }
+            }
+            above = Some(*ch_z);
+        }
+    }
+    floors
+}
+
+/// Светящиеся тайлы загруженных чанков
+fn tile_lights(map: &WorldMap) -> Vec<(LightSourceId, Vec3<i32>, Light)> {
+    let mut lights = Vec::new();
+    for (&position, chunk) in map.chunks.iter() {
+        let chunk = chunk.lock().unwrap();
+        for &index in chunk.lights.iter() {
+            let (x, y, z) = global_of(position, index as usize);
+            let light = map
+                .tiles
+                .get(chunk.get_tile(x, y, z))
+                .light
+                .unwrap_or_else(|| panic!("Тайл ({x}, {y}, {z}) записан светящимся, но не светит"));
+            lights.push((LightSourceId::Tile((x, y, z)), Vec3::new(x, y, z), light));
+        }
+    }
+    lights
+}
+
+/// Тайлы, до которых доходит свет источника light из key.pos. Свет
+/// расходится так же, как взгляд, и освещает и сам тайл источника.
+fn lit_area(key: SightKey, light: Light, map: &WorldMap, blockers: &SightBlockers) -> LitArea {
+    let (mut offsets, complete) = sight_tiles(key.pos, light.radius, map, blockers);
+    // Тайлы на границах направлений попадают в несколько из них
+    offsets.push((0, 0, 0));
+    offsets.sort_unstable();
+    offsets.dedup();
+    let tiles = offsets
+        .into_iter()
+        .map(|offset| {
+            let (x, y, z) = offset;
+            let pos = (key.pos.x + x, key.pos.y + y, key.pos.z + z);
+            (pos, light.at(offset))
+        })
+        .filter(|(_, amount)| *amount != [0; 3])
+        .collect();
+    LitArea {
+        key: complete.then_some(key),
+        light,
+        tiles,
+    }
+}
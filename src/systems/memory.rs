@@ -1,53 +1,184 @@
 use std::{collections::HashMap, sync::Mutex};
 
-
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
     components::Position,
     hasher,
-    map::{Map, WorldMap, CHUNK_SIZE},
+    map::{Map, WorldMap},
     need_components,
     player::Player,
+    region::{RegionError, RegionStore},
+    registry::ComponentRegistry,
+    storage::ChunkBits,
     GameHasher,
 };
 
 use super::fov_compute::Sight;
 
-/// Компонент, означающий, что сущность запоминает тайлы, которые увидела однажды
+/// Компонент, означающий, что сущность запоминает тайлы, которые увидела однажды.
+/// Запоминаются только освещённые тайлы поля зрения: в темноте ничего не видно.
 /// Хранит в себе карту, где вместо соответствующих тайлов содержатся булевы значения.
 pub struct MapMemory {
     chunks: HashMap<(i32, i32, i32), Mutex<MemoryChunk>, GameHasher>,
+    /// Куда выгружаются чанки памяти вместе с чанками карты.
+    /// Чанки памяти записываются сериями, как в описании компонента.
+    pub store: Option<RegionStore<Vec<u32>>>,
 }
 
 impl MapMemory {
     pub fn new() -> Self {
         MapMemory {
             chunks: HashMap::with_hasher(hasher()),
+            store: None,
+        }
+    }
+    /// Записывает в хранилище и убирает из памяти чанки, для которых keep
+    /// возвращает false, так же, как WorldMap::unload_chunks
+    pub fn unload_chunks(
+        &mut self,
+        keep: impl Fn((i32, i32, i32)) -> bool,
+    ) -> Result<(), RegionError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let unloaded = self
+            .chunks
+            .keys()
+            .copied()
+            .filter(|position| !keep(*position))
+            .collect::<Vec<_>>();
+        store.store(
+            unloaded
+                .iter()
+                .map(|position| (*position, self.chunks[position].lock().unwrap().runs())),
+        )?;
+        for position in unloaded {
+            self.chunks.remove(&position);
+        }
+        Ok(())
+    }
+    fn load_stored(&self, position: (i32, i32, i32)) -> Result<Option<MemoryChunk>, RegionError> {
+        let Some(store) = &self.store else {
+            return Ok(None);
+        };
+        let Some(runs) = store.load(position)? else {
+            return Ok(None);
+        };
+        MemoryChunk::from_runs(&runs)
+            .map(Some)
+            .map_err(|message| RegionError::Chunk {
+                dir: store.dir().to_owned(),
+                chunk: position,
+                message,
+            })
+    }
+    /// Сколько байт занимают все чанки памяти
+    pub fn memory_size(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|chunk| {
+                std::mem::size_of::<MemoryChunk>() + chunk.lock().unwrap().memorized.heap_size()
+            })
+            .sum()
+    }
+}
+
+impl Default for MapMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register_default::<MapMemory>("map_memory");
+}
+
+/// В описании компонента каждый чанк памяти записывается длинами чередующихся
+/// серий незапомненных и запомненных тайлов, начиная с незапомненных.
+/// Выгруженные чанки тоже записываются.
+#[derive(Serialize, Deserialize)]
+struct MemoryChunkRuns {
+    chunk: (i32, i32, i32),
+    runs: Vec<u32>,
+}
+
+impl Serialize for MapMemory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let stored = match &self.store {
+            Some(store) => store.load_all().map_err(ser::Error::custom)?,
+            None => Vec::new(),
+        };
+        let mut chunks = stored
+            .into_iter()
+            .filter(|(chunk, _)| !self.chunks.contains_key(chunk))
+            .map(|(chunk, runs)| MemoryChunkRuns { chunk, runs })
+            .chain(
+                self.chunks
+                    .iter()
+                    .map(|(chunk, memory_chunk)| MemoryChunkRuns {
+                        chunk: *chunk,
+                        runs: memory_chunk.lock().unwrap().runs(),
+                    }),
+            )
+            .collect::<Vec<_>>();
+        chunks.sort_by_key(|memory_chunk| memory_chunk.chunk);
+        chunks.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MapMemory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut map_memory = MapMemory::new();
+        for MemoryChunkRuns { chunk, runs } in Vec::<MemoryChunkRuns>::deserialize(deserializer)? {
+            let memory_chunk = MemoryChunk::from_runs(&runs).map_err(de::Error::custom)?;
+            map_memory.chunks.insert(chunk, Mutex::new(memory_chunk));
         }
+        Ok(map_memory)
     }
 }
 
 pub struct MemoryChunk {
-    pub memorized: [bool; CHUNK_SIZE.pow(3)],
+    pub memorized: ChunkBits,
 }
 
 impl MemoryChunk {
     pub const fn new() -> Self {
         MemoryChunk {
-            memorized: [false; CHUNK_SIZE.pow(3)],
+            memorized: ChunkBits::Uniform(false),
         }
     }
     pub fn is_memorized(&self, x: i32, y: i32, z: i32) -> bool {
-        let idx = MapMemory::xy_index_chunk(x, y, z);
-        unsafe { *self.memorized.get_unchecked(idx) }
+        self.memorized.get(MapMemory::xy_index_chunk(x, y, z))
+    }
+    fn runs(&self) -> Vec<u32> {
+        self.memorized.runs()
+    }
+    fn from_runs(runs: &[u32]) -> Result<Self, String> {
+        let memorized = ChunkBits::from_runs(runs).map_err(|err| format!("memory chunk {err}"))?;
+        Ok(MemoryChunk { memorized })
     }
 }
 
 impl Map for MapMemory {
+    /// Выгруженный чанк памяти загружается из хранилища. Если его там нет
+    /// или запись испорчена, чанк считается пустым: сущность забыла эти места.
+    /// Испорченный регион откладывается так же, как у WorldMap.
     fn get_chunk_or_create(&mut self, x: i32, y: i32, z: i32) -> &Mutex<MemoryChunk> {
-        self.chunks
-            .entry((x, y, z))
-            .or_insert_with(|| Mutex::new(MemoryChunk::new()))
+        if !self.chunks.contains_key(&(x, y, z)) {
+            let chunk = self
+                .load_stored((x, y, z))
+                .unwrap_or_else(|err| {
+                    if let Some(store) = &self.store {
+                        // Неудача уже записана в сообщение для журнала
+                        let _ = store.set_aside(err, "the memory chunk is forgotten");
+                    }
+                    None
+                })
+                .unwrap_or_else(MemoryChunk::new);
+            self.chunks.insert((x, y, z), Mutex::new(chunk));
+        }
+        &self.chunks[&(x, y, z)]
     }
     fn get_chunk(&self, x: i32, y: i32, z: i32) -> Option<&Mutex<MemoryChunk>> {
         self.chunks.get(&(x, y, z))
@@ -64,7 +195,7 @@ pub fn run_memory_system(world: &hecs::World) -> super::Result {
         .ok_or(need_components!(FovSystem, WorldMap))?;
 
     let mut query = world.query::<(&Player, &Position, &Sight, &mut MapMemory)>();
-    let (_, (_, Position(cam_pos), Sight(_, sight_tiles), map_memory)) =
+    let (_, (_, Position(cam_pos), Sight(_, sight_tiles, ..), map_memory)) =
         query.iter().next().ok_or(need_components!(
             MemorySystem,
             Player,
@@ -82,7 +213,10 @@ pub fn run_memory_system(world: &hecs::World) -> super::Result {
     let mut chunk_cache: [Option<(&Mutex<MemoryChunk>, i32, i32, i32)>; 15] = [None; 15];
     let mut cache_counter = 0;
     for sight_coord in sight_tiles.iter() {
-        let (x, y, z) = shift_back(*sight_coord);
+        let (x, y, z) = shift_back(sight_coord);
+        if !map.light.is_lit((x, y, z)) {
+            continue;
+        }
         let (ch_x, ch_y, ch_z) = MapMemory::xy_chunk(x, y, z);
         let chunk_mutex = match chunk_cache
             .into_iter()
@@ -100,7 +234,7 @@ pub fn run_memory_system(world: &hecs::World) -> super::Result {
         let mut chunk = chunk_mutex.lock().unwrap();
 
         let real_crd = MapMemory::xy_index_chunk(x, y, z);
-        chunk.memorized[real_crd] = true;
+        chunk.memorized.set(real_crd, true);
     }
     Ok(())
 }
@@ -1,20 +1,25 @@
 use hecs::World;
 
-use crate::Game;
+use crate::{registry::ComponentRegistry, Game};
 
 use self::{
     fov_compute::run_fov_compute_system, health::run_attack_system, input::run_input_system,
-    memory::run_memory_system, movement::run_move_system, pathfinding::run_pathfinding_system,
+    lighting::run_lighting_system, memory::run_memory_system, movement::run_move_system,
+    pathfinding::run_pathfinding_system, streaming::run_chunk_streaming_system,
+    terrain::run_terrain_system,
 };
 
 pub mod error;
 pub mod fov_compute;
 pub mod health;
 pub mod input;
+pub mod lighting;
 pub mod memory;
 pub mod movement;
 pub mod pathfinding;
 pub mod render;
+pub mod streaming;
+pub mod terrain;
 
 #[macro_export]
 macro_rules! init_systems {
@@ -25,6 +30,15 @@ macro_rules! init_systems {
 
 pub type Result = std::result::Result<(), self::error::Error>;
 
+pub fn register_components(registry: &mut ComponentRegistry) {
+    fov_compute::register_components(registry);
+    health::register_components(registry);
+    lighting::register_components(registry);
+    memory::register_components(registry);
+    pathfinding::register_components(registry);
+    render::register_components(registry);
+}
+
 #[derive(Clone, Copy)]
 pub enum GameSystem {
     InputSystem,
@@ -33,20 +47,26 @@ pub enum GameSystem {
 #[derive(Clone, Copy, Debug)]
 pub enum WorldSystem {
     FovCompute,
+    Lighting,
     Move,
     Memory,
     Attack,
     Pathfinding,
+    ChunkStreaming,
+    Terrain,
 }
 
 impl WorldSystem {
     pub fn run(&self, world: &mut World) -> anyhow::Result<()> {
         match self {
             WorldSystem::FovCompute => run_fov_compute_system(world)?,
+            WorldSystem::Lighting => run_lighting_system(world)?,
             WorldSystem::Move => run_move_system(world)?,
             WorldSystem::Memory => run_memory_system(world)?,
             WorldSystem::Attack => run_attack_system(world)?,
             WorldSystem::Pathfinding => run_pathfinding_system(world)?,
+            WorldSystem::ChunkStreaming => run_chunk_streaming_system(world)?,
+            WorldSystem::Terrain => run_terrain_system(world)?,
         }
         Ok(())
     }
@@ -59,8 +59,8 @@ pub fn run_move_system(world: &mut World) -> anyhow::Result<()> {
                     continue;
                 }
             }
-            // Иначе если на пути нет препятствия
-            if !map.get_obstacle_or_create(step.x, step.y, step.z) {
+            // Иначе если на пути нет препятствия, а чанк уже загружен
+            if !map.get_loaded_obstacle(step.x, step.y, step.z) {
                 // То двигать
                 *pos = *step;
             }
@@ -1,22 +1,32 @@
 use hecs::{CommandBuffer, World};
 use pathfinding::prelude::*;
-
+use serde::{Deserialize, Serialize};
 use vek::Vec3;
 
 use crate::{
     components::Position,
-    map::{Map, WorldMap},
+    map::WorldMap,
     need_components,
     player::Player,
+    registry::ComponentRegistry,
     Direction, Mob,
 };
 
-use super::movement::{dir_to_vec3, vec3_to_dir, WantsMove};
+use super::{
+    fov_compute::Sight,
+    lighting::perceives,
+    movement::{dir_to_vec3, vec3_to_dir, WantsMove},
+};
 
 // type Path = (Vec<Vec3<i32>>, i32);
 
+#[derive(Serialize, Deserialize)]
 pub struct Pathfinder;
 
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Pathfinder>("pathfinder");
+}
+
 const fn mhdistance(a: &Vec3<i32>, b: &Vec3<i32>) -> i32 {
     (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs()
 }
@@ -26,7 +36,7 @@ pub fn run_pathfinding_system(world: &mut World) -> anyhow::Result<()> {
     {
         // let mut mobs_bind = world.query::<(&Mob, &Position)>();
         // let mobs = mobs_bind.iter();
-        let mut movables = world.query::<(&Position, &Mob, &Pathfinder)>();
+        let mut movables = world.query::<(&Position, &Mob, &Pathfinder, Option<&Sight>)>();
         let mut binding = world.query::<(&mut WorldMap,)>();
         let (_, (map,)) = binding
             .iter()
@@ -51,13 +61,8 @@ pub fn run_pathfinding_system(world: &mut World) -> anyhow::Result<()> {
             let mut result = Vec::new();
             for (x, y, z) in dirs.iter().map(dir_to_vec3).map(|x| x.into_tuple()) {
                 let (pos_x, pos_y, pos_z) = (pos.x + x, pos.y + y, pos.z + z);
-                let (ch_x, ch_y, ch_z) = WorldMap::xy_chunk(pos_x, pos_y, pos_z);
-                let chunk_mutex = map.get_chunk(ch_x, ch_y, ch_z).unwrap();
-
-                let chunk = chunk_mutex.lock().unwrap();
-                let real = WorldMap::xy_index_chunk(pos_x, pos_y, pos_z);
-                let is_obstacle = chunk.obstacles[real];
-                if !is_obstacle {
+                // Через незагруженные чанки путь не ищется
+                if !map.get_loaded_obstacle(pos_x, pos_y, pos_z) {
                     let res_pos = Vec3::new(pos_x, pos_y, pos_z);
                     let distance = 1;
                     result.push((res_pos, distance));
@@ -68,7 +73,12 @@ pub fn run_pathfinding_system(world: &mut World) -> anyhow::Result<()> {
 
         let distance = |pos: &Vec3<i32>| mhdistance(player_pos, pos);
 
-        for (e, (Position(pos), _, _)) in movables.iter() {
+        for (e, (Position(pos), _, _, sight)) in movables.iter() {
+            // Сущность с полем зрения идёт к персонажу, только если видит его,
+            // а в темноте его не видно
+            if sight.is_some_and(|sight| !perceives(sight, &map.light, *pos, *player_pos)) {
+                continue;
+            }
             let a = astar(pos, sucsessors, distance, |x| x == player_pos);
             if let Some((path, _)) = a {
                 let next_step = path[1] - pos;
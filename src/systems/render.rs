@@ -1,12 +1,11 @@
-use std::{
-    sync::{Arc, MutexGuard},
-};
+use std::sync::{Arc, MutexGuard};
 
 use macroquad::{
     miniquad::window::screen_size,
     prelude::{Color, Vec2},
     texture::{draw_texture_ex, DrawTextureParams},
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
     components::Position,
@@ -14,6 +13,7 @@ use crate::{
     map::{Chunk, Map, WorldMap},
     need_components,
     player::Player,
+    registry::ComponentRegistry,
     resources::Sprite,
     Game, Mob,
 };
@@ -52,9 +52,13 @@ const fn idx_tile((x, y, z): (i32, i32, i32), render_radius: i32) -> usize {
 /// а так же компонентами Position и Item или Mob, будут отрисованы.
 /// Компонент содержит в себе название спрайта, который будет отрисован.
 /// По этому названию будет сделан запрос в хранилище спрайтов resources (поле Game).
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Renderable(pub Arc<str>);
 
+pub fn register_components(registry: &mut ComponentRegistry) {
+    registry.register::<Renderable>("renderable");
+}
+
 pub fn run_render_system(game: &Game) -> super::Result {
     let world = &game.world;
     let resources = &game.resources;
@@ -65,7 +69,7 @@ pub fn run_render_system(game: &Game) -> super::Result {
         .ok_or(need_components!(RenderSystem, Map))?;
 
     let mut query = world.query::<(&Player, &Position, &Sight, &MapMemory)>();
-    let (_, (_, Position(cam_pos), Sight(sight_radius, sight_positions), map_memory)) =
+    let (_, (_, Position(cam_pos), Sight(sight_radius, sight_positions, ..), map_memory)) =
         query.iter().next().ok_or(need_components!(
             RenderSystem,
             Player,
@@ -91,8 +95,14 @@ pub fn run_render_system(game: &Game) -> super::Result {
             let val = (false, xyz_tile(i as i32, render_radius), None);
             std::ptr::write_volatile(ptr, val);
         }
+        // Видны только освещённые тайлы поля зрения
         for i in sight_positions.iter() {
-            positions.get_unchecked_mut(idx_tile(*i, render_radius)).0 = true;
+            if map
+                .light
+                .is_lit((i.0 + cam_pos.x, i.1 + cam_pos.y, i.2 + cam_pos.z))
+            {
+                positions.get_unchecked_mut(idx_tile(i, render_radius)).0 = true;
+            }
         }
         for (_, (renderable, Position(pos))) in renderable_items.chain(renderable_mobs) {
             let in_radius = (pos.x - cam_pos.x).pow(2)
@@ -113,7 +123,6 @@ pub fn run_render_system(game: &Game) -> super::Result {
 
     let mut prev_chunk_mutex: Option<(MutexGuard<Chunk>, i32, i32, i32)> = None;
 
-    let base_color = Color::from_hex(0xFFFFFF);
     let shadowed_color = Color::from_hex(0x555555);
     for (is_visible, (x, y, z), renderable) in positions {
         if (x.pow(2) + y.pow(2) + z.pow(2)) > (1 + render_radius).pow(2) {
@@ -154,18 +163,15 @@ pub fn run_render_system(game: &Game) -> super::Result {
         };
 
         let memory_chunk = map_memory.get_chunk(ch_x, ch_y, ch_z);
-        let tile = chunk.get_tile(x_real, y_real, z_real);
-        let sprite = if prev_sprite
-            .as_ref()
-            .is_some_and(|x| x.0 == tile.full_sprite)
-        {
+        let tile = resources.tiles.get(chunk.get_tile(x_real, y_real, z_real));
+        let sprite = if prev_sprite.as_ref().is_some_and(|x| x.0 == &*tile.sprite) {
             &prev_sprite.as_ref().unwrap().1
         } else {
             resources
                 .assets
                 .sprites
-                .get(tile.full_sprite)
-                .unwrap_or_else(|| sprite_not_found(tile.full_sprite))
+                .get(&tile.sprite)
+                .unwrap_or_else(|| sprite_not_found(&tile.sprite))
         };
 
         let is_memorized = memory_chunk.map_or(false, |a| {
@@ -179,19 +185,28 @@ pub fn run_render_system(game: &Game) -> super::Result {
         let color = if !is_visible && is_memorized {
             shadowed_color
         } else {
-            base_color
+            lit_color(map.light.get((x_real, y_real, z_real)), shadowed_color)
         };
 
-        if tile.name != "empty" {
-            let params = DrawTextureParams {
-                source: Some(sprite.rect),
-                dest_size: Some(Vec2::new(
-                    sprite.rect.w * game.scale,
-                    sprite.rect.h * game.scale,
-                )),
-                ..Default::default()
-            };
-            draw_texture_ex(&sprite.texture, position.x, position.y, color, params);
+        if &*tile.name != "empty" {
+            let fallback_sprite = tile.fallback_sprite.as_ref().map(|name| {
+                resources
+                    .assets
+                    .sprites
+                    .get(name)
+                    .unwrap_or_else(|| sprite_not_found(name))
+            });
+            for sprite in fallback_sprite.into_iter().chain([sprite]) {
+                let params = DrawTextureParams {
+                    source: Some(sprite.rect),
+                    dest_size: Some(Vec2::new(
+                        sprite.rect.w * game.scale,
+                        sprite.rect.h * game.scale,
+                    )),
+                    ..Default::default()
+                };
+                draw_texture_ex(&sprite.texture, position.x, position.y, color, params);
+            }
         }
 
         let Some(Renderable(renderable)) = renderable else {
@@ -216,9 +231,21 @@ pub fn run_render_system(game: &Game) -> super::Result {
             &sprite.texture,
             position.x + shift_x,
             position.y + shift_y,
-            Color::from_hex(0xFFFFFF),
+            color,
             params,
         );
     }
     Ok(())
 }
+
+/// Оттенок видимого тайла под светом light. Даже едва освещённый тайл
+/// светлее запомненного shadowed, а свет ярче полного не пересвечивает.
+fn lit_color(light: [f32; 3], shadowed: Color) -> Color {
+    let [r, g, b] = light.map(|channel| channel.min(1.));
+    Color::new(
+        shadowed.r + (1. - shadowed.r) * r,
+        shadowed.g + (1. - shadowed.g) * g,
+        shadowed.b + (1. - shadowed.b) * b,
+        1.,
+    )
+}
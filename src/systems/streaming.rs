@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use hecs::{Entity, World};
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    map::{Map, WorldMap},
+    need_components,
+    player::{write_player_log, Player},
+    region::RegionStore,
+    Mob,
+};
+
+use super::{
+    fov_compute::{sight_chunks, Sight},
+    memory::MapMemory,
+};
+
+/// Каталог рядом с хранилищем карты, куда выгружается память сущности.
+/// В имени есть и поколение сущности: hecs переиспользует номера удалённых
+/// сущностей, и новая сущность не должна найти память старой.
+fn memory_store_dir(map_store: &Path, entity: Entity) -> PathBuf {
+    map_store.with_file_name(format!("memory_{}", entity.to_bits()))
+}
+
+/// Выгружает чанки карты и памяти, далёкие от всех активных сущностей (мобов
+/// и персонажа игрока). Чанк остаётся загруженным, если хотя бы одна такая
+/// сущность находится не дальше keep_radius чанков от него по каждой оси.
+/// Затем заранее запрашивает чанки, в которые персонаж скоро заглянет,
+/// если продолжит идти в ту же сторону. Испорченные записи, найденные при
+/// загрузке и выгрузке чанков, попадают в журнал игрока.
+pub fn run_chunk_streaming_system(world: &mut World) -> anyhow::Result<()> {
+    let result = stream_chunks(world);
+    report_corrupted(world);
+    result
+}
+
+fn stream_chunks(world: &mut World) -> anyhow::Result<()> {
+    let player = world
+        .query_mut::<(&Player, &Position, &Sight)>()
+        .into_iter()
+        .next()
+        .map(|(_, (_, Position(pos), Sight(radius, ..)))| (*pos, *radius));
+
+    let active = world
+        .query_mut::<(&Position, &Mob)>()
+        .into_iter()
+        .map(|(_, (Position(pos), _))| WorldMap::xy_chunk(pos.x, pos.y, pos.z))
+        .collect::<Vec<_>>();
+
+    let (_, (map,)) = world
+        .query_mut::<(&mut WorldMap,)>()
+        .into_iter()
+        .next()
+        .ok_or(need_components!(ChunkStreaming, WorldMap))?;
+    let radius = map.keep_radius;
+    let keep = |(x, y, z): (i32, i32, i32)| {
+        active.iter().any(|&(ax, ay, az)| {
+            (x - ax).abs() <= radius && (y - ay).abs() <= radius && (z - az).abs() <= radius
+        })
+    };
+    map.unload_chunks(keep)?;
+    if let Some((pos, radius)) = player {
+        prefetch_chunks(map, pos, radius);
+    }
+    let Some(map_store) = map.store.as_ref().map(|store| store.dir().to_owned()) else {
+        return Ok(());
+    };
+
+    for (entity, memory) in world.query_mut::<&mut MapMemory>() {
+        memory
+            .store
+            .get_or_insert_with(|| RegionStore::new(memory_store_dir(&map_store, entity)));
+        memory.unload_chunks(keep)?;
+    }
+    Ok(())
+}
+
+/// Переносит в журнал игрока сообщения хранилищ карты и памяти об испорченных записях
+fn report_corrupted(world: &mut World) {
+    let mut messages = Vec::new();
+    for (_, map) in world.query_mut::<&WorldMap>() {
+        messages.extend(map.store.iter().flat_map(RegionStore::take_corrupted));
+    }
+    for (_, memory) in world.query_mut::<&MapMemory>() {
+        messages.extend(memory.store.iter().flat_map(RegionStore::take_corrupted));
+    }
+    for message in messages {
+        write_player_log(world, &message);
+    }
+}
+
+/// Запрашивает чанки поля зрения, сдвинутого на чанк в ту сторону, куда идёт
+/// персонаж. Чанки самого поля зрения запрашивает система поля зрения.
+fn prefetch_chunks(map: &mut WorldMap, pos: Vec3<i32>, sight_radius: u32) {
+    let Some(loader) = &mut map.loader else {
+        return;
+    };
+    let heading = loader.heading(pos);
+    if heading == Vec3::zero() {
+        return;
+    }
+    let (x, y, z) = WorldMap::xy_chunk(pos.x, pos.y, pos.z);
+    let ahead = (x + heading.x, y + heading.y, z + heading.z);
+    for (x, y, z) in sight_chunks(ahead, sight_radius) {
+        map.request_chunk(x, y, z);
+    }
+}
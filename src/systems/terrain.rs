@@ -0,0 +1,140 @@
+use hecs::{CommandBuffer, World};
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    items::Item,
+    map::WorldMap,
+    mob::{Inventory, Log},
+    need_components,
+    tiles::Tile,
+    Direction, Mob, Property,
+};
+
+use super::movement::dir_to_vec3;
+
+/// Свойство предмета-инструмента: сила копания. Инструментом можно разрушить
+/// тайл, твёрдость которого не больше этой силы.
+pub const DIGGING_PROPERTY: &str = "digging";
+/// Свойство предмета-блока: имя тайла, который из него строится
+pub const TILE_PROPERTY: &str = "tile";
+/// Тайл, который остаётся на месте разрушенного
+const DUG_TILE: &str = "empty";
+
+/// Действие с картой, для которого игрок выбирает направление
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TerrainAction {
+    Dig,
+    Build,
+}
+
+/// Компонент, означающий, что сущность хочет разрушить соседний тайл
+pub struct WantsDig(pub Direction);
+
+/// Компонент, означающий, что сущность хочет поставить блок в соседний тайл
+pub struct WantsBuild(pub Direction);
+
+/// Предмет, который получается из разрушенного тайла и из которого его
+/// можно построить обратно
+fn block_item(tile: &Tile) -> Item {
+    let mut item = Item::new(format!("{} block", tile.name), tile.sprite.to_string());
+    item.add_props(&[(
+        TILE_PROPERTY.into(),
+        Property::String(tile.name.to_string()),
+    )]);
+    item
+}
+
+fn digging_power(item: &Item) -> Option<i32> {
+    match item.properties.get(DIGGING_PROPERTY) {
+        Some(Property::Int(power)) => Some(*power),
+        _ => None,
+    }
+}
+
+/// Разрушает непроходимый тайл, если у сущности есть подходящий инструмент,
+/// и кладёт в инвентарь блок из него. Возвращает запись для журнала.
+fn dig(map: &mut WorldMap, target: Vec3<i32>, Inventory(items): &mut Inventory) -> String {
+    let tiles = map.tiles.clone();
+    let tile = tiles.get(map.get_tile_or_create(target.x, target.y, target.z));
+    if !tile.blocks_movement {
+        return "There is nothing to dig".into();
+    }
+    match items.iter().filter_map(digging_power).max() {
+        None => return "You need a digging tool".into(),
+        Some(power) if i64::from(power) < i64::from(tile.hardness) => {
+            return format!("The {} is too hard for your tools", tile.name)
+        }
+        Some(_) => {}
+    }
+    let dug = tiles
+        .id(DUG_TILE)
+        .unwrap_or_else(|| panic!("Тайла {DUG_TILE} нет в реестре"));
+    map.set_tile(target, dug);
+    items.push(block_item(tile));
+    format!("You dig through the {}", tile.name)
+}
+
+/// Ставит блок из инвентаря в свободный тайл. Возвращает запись для журнала.
+fn build(
+    map: &mut WorldMap,
+    target: Vec3<i32>,
+    occupied: bool,
+    Inventory(items): &mut Inventory,
+) -> String {
+    let tiles = map.tiles.clone();
+    let current = tiles.get(map.get_tile_or_create(target.x, target.y, target.z));
+    if current.blocks_movement || occupied {
+        return "There is no room to build".into();
+    }
+    let block = items.iter().enumerate().find_map(|(index, item)| {
+        match item.properties.get(TILE_PROPERTY) {
+            Some(Property::String(name)) => tiles.id(name).map(|id| (index, id)),
+            _ => None,
+        }
+    });
+    let Some((index, id)) = block else {
+        return "You have no blocks to build with".into();
+    };
+    map.set_tile(target, id);
+    items.remove(index);
+    format!("You build a {}", tiles.get(id).name)
+}
+
+pub fn run_terrain_system(world: &mut World) -> anyhow::Result<()> {
+    let mobs = world
+        .query_mut::<(&Mob, &Position)>()
+        .into_iter()
+        .map(|(_, (_, Position(pos)))| *pos)
+        .collect::<Vec<_>>();
+    let mut binding = world.query::<(&mut WorldMap,)>();
+    let (_, (map,)) = binding
+        .iter()
+        .next()
+        .ok_or(need_components!(TerrainSystem, WorldMap))?;
+    let mut cmd = CommandBuffer::new();
+
+    let mut diggers = world.query::<(&Position, &WantsDig, &mut Inventory, Option<&mut Log>)>();
+    for (e, (Position(pos), WantsDig(dir), inventory, log)) in diggers.iter() {
+        cmd.remove_one::<WantsDig>(e);
+        let event = dig(map, *pos + dir_to_vec3(dir), inventory);
+        if let Some(log) = log {
+            log.write(&event);
+        }
+    }
+    drop(diggers);
+
+    let mut builders = world.query::<(&Position, &WantsBuild, &mut Inventory, Option<&mut Log>)>();
+    for (e, (Position(pos), WantsBuild(dir), inventory, log)) in builders.iter() {
+        cmd.remove_one::<WantsBuild>(e);
+        let target = *pos + dir_to_vec3(dir);
+        let event = build(map, target, mobs.contains(&target), inventory);
+        if let Some(log) = log {
+            log.write(&event);
+        }
+    }
+    drop(builders);
+    drop(binding);
+    cmd.run_on(world);
+    Ok(())
+}
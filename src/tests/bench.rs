@@ -0,0 +1,192 @@
+#![cfg(test)]
+//! Замеры памяти и времени для карты. Они долгие и имеют смысл только в release,
+//! поэтому запускаются отдельно:
+//! `cargo test --release -- --ignored --nocapture bench_`
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hecs::World;
+
+use crate::{
+    components::Position,
+    light::Light,
+    map::{Map, WorldMap},
+    player::new_player,
+    systems::{
+        fov_compute::{run_fov_compute_system, Sight},
+        lighting::{run_lighting_system, LightSource},
+        memory::{run_memory_system, MapMemory},
+    },
+    worldgen::NoiseGenerator,
+};
+
+use super::{tiles::test_tiles, with_big_stack};
+
+const SEED: u64 = 5;
+
+/// Мир с картой и персонажем, стоящим на поверхности
+fn test_world() -> World {
+    let mut world = World::new();
+    let mut map = WorldMap::new(SEED, Arc::new(NoiseGenerator), test_tiles());
+    let z = map.surface_z(0, 0);
+    world.spawn((map,));
+    let mut player = new_player();
+    player.add(Position(vek::Vec3::new(0, 0, z)));
+    world.spawn(player.build());
+    world
+}
+
+fn average(runs: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    start.elapsed() / runs
+}
+
+#[test]
+#[ignore]
+fn bench_chunk_memory() {
+    with_big_stack(|| {
+        let mut world = test_world();
+        run_fov_compute_system(&world).unwrap();
+        run_memory_system(&world).unwrap();
+        let (_, (map,)) = world
+            .query_mut::<(&WorldMap,)>()
+            .into_iter()
+            .next()
+            .unwrap();
+        let chunks = map.chunks.len();
+        let bytes = map.memory_size();
+        println!(
+            "{chunks} chunks: {} KiB, {} KiB per chunk",
+            bytes / 1024,
+            bytes / 1024 / chunks
+        );
+        let (_, (memory,)) = world
+            .query_mut::<(&MapMemory,)>()
+            .into_iter()
+            .next()
+            .unwrap();
+        println!("map memory: {} KiB", memory.memory_size() / 1024);
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_fov() {
+    with_big_stack(|| {
+        let mut world = test_world();
+        run_fov_compute_system(&world).unwrap();
+        let time = average(20, || {
+            for (_, sight) in world.query_mut::<&mut Sight>() {
+                sight.2 = None;
+            }
+            run_fov_compute_system(&world).unwrap();
+        });
+        let cached = average(20, || run_fov_compute_system(&world).unwrap());
+        println!("fov: {time:?}, {cached:?} cached");
+    });
+}
+
+/// Поле зрения персонажа и 50 NPC вокруг него: каждый ход, когда все NPC
+/// сдвинулись, и когда никто не двигался и поле зрения NPC берётся из кэша
+#[test]
+#[ignore]
+fn bench_npc_fov() {
+    with_big_stack(|| {
+        let mut world = test_world();
+        let npcs = (0..50)
+            .map(|i| {
+                let (x, y) = (i % 10 * 6 - 27, i / 10 * 6 - 12);
+                let mut query = world.query::<(&mut WorldMap,)>();
+                let (_, (map,)) = query.iter().next().unwrap();
+                let z = map.surface_z(x, y);
+                drop(query);
+                world.spawn((Position(vek::Vec3::new(x, y, z)), Sight::new(20)))
+            })
+            .collect::<Vec<_>>();
+        run_fov_compute_system(&world).unwrap();
+        let moved = average(10, || {
+            for &npc in npcs.iter() {
+                world.get::<&mut Sight>(npc).unwrap().2 = None;
+            }
+            run_fov_compute_system(&world).unwrap();
+        });
+        let cached = average(20, || run_fov_compute_system(&world).unwrap());
+        println!("fov with 50 npcs: {moved:?} moved, {cached:?} cached");
+    });
+}
+
+/// Свет фонаря персонажа и 50 фонарей NPC: каждый ход, когда все сдвинулись,
+/// и когда никто не двигался. Сначала фонари стоят вокруг персонажа, а потом
+/// разбросаны по загруженным чанкам, и до обзора персонажа достают не все.
+#[test]
+#[ignore]
+fn bench_lighting() {
+    with_big_stack(|| {
+        for (name, spacing, shift) in [("around the player", 6, (27, 12)), ("spread", 16, (72, 32))]
+        {
+            let mut world = test_world();
+            let lantern = Light {
+                radius: 12,
+                color: 0xFFE0B0,
+                intensity: 1.,
+            };
+            for i in 0..50 {
+                let (x, y) = (i % 10 * spacing - shift.0, i / 10 * spacing - shift.1);
+                let mut query = world.query::<(&mut WorldMap,)>();
+                let (_, (map,)) = query.iter().next().unwrap();
+                let z = map.surface_z(x, y);
+                drop(query);
+                world.spawn((Position(vek::Vec3::new(x, y, z)), LightSource(lantern)));
+            }
+            run_fov_compute_system(&world).unwrap();
+            run_lighting_system(&world).unwrap();
+            let mut step = 1;
+            let moved = average(10, || {
+                for (_, (Position(pos), _)) in world.query_mut::<(&mut Position, &LightSource)>() {
+                    pos.x += step;
+                }
+                step = -step;
+                run_lighting_system(&world).unwrap();
+            });
+            let cached = average(20, || run_lighting_system(&world).unwrap());
+            println!("lighting with 51 lights {name}: {moved:?} moved, {cached:?} cached");
+        }
+    });
+}
+
+/// Та часть отрисовки, которая зависит от хранения чанков: поиск тайла
+/// и его спрайта для каждой позиции вокруг персонажа
+#[test]
+#[ignore]
+fn bench_render_tiles() {
+    with_big_stack(|| {
+        let world = test_world();
+        run_fov_compute_system(&world).unwrap();
+        let mut query = world.query::<(&WorldMap,)>();
+        let (_, (map,)) = query.iter().next().unwrap();
+        let radius = 45;
+        let time = average(5, || {
+            let mut sprites = 0;
+            for z in -radius..=radius {
+                for y in -radius..=radius {
+                    for x in -radius..=radius {
+                        let (ch_x, ch_y, ch_z) = WorldMap::xy_chunk(x, y, z);
+                        let Some(chunk) = map.get_chunk(ch_x, ch_y, ch_z) else {
+                            continue;
+                        };
+                        let tile = chunk.lock().unwrap().get_tile(x, y, z);
+                        sprites += map.tiles.get(tile).sprite.len();
+                    }
+                }
+            }
+            assert!(sprites > 0);
+        });
+        println!("render tiles: {time:?}");
+    });
+}
@@ -0,0 +1,160 @@
+#![cfg(test)]
+
+use std::collections::{HashMap, HashSet};
+
+use vek::Vec2;
+
+use crate::{
+    biome::{Biome, BiomeMap, BIOMES},
+    coords::global_coord,
+    map::{Chunk, CHUNK_SIZE},
+    planner::WorldPlan,
+    tiles::TileRegistry,
+    worldgen::{ChunkGenerator, NoiseGenerator},
+};
+
+use super::{resources::test_resources, tiles::test_tiles, with_big_stack};
+
+#[test]
+fn biomes_from_seed() {
+    let a = BiomeMap::new(3);
+    let b = BiomeMap::new(3);
+    let other = BiomeMap::new(4);
+    let mut found = HashSet::new();
+    let mut differs = false;
+    for y in -100..100 {
+        for x in -100..100 {
+            let biome = a.biome_at(x * 32, y * 32);
+            assert_eq!(biome, b.biome_at(x * 32, y * 32));
+            differs |= biome != other.biome_at(x * 32, y * 32);
+            found.insert(biome);
+        }
+    }
+    assert!(differs, "Биомы не зависят от зерна");
+    assert_eq!(found, HashSet::from(BIOMES));
+}
+
+#[test]
+fn biome_terrain_is_smooth() {
+    let biomes = BiomeMap::new(1);
+    let max_step = BIOMES
+        .iter()
+        .flat_map(|a| BIOMES.iter().map(move |b| (a.terrain(), b.terrain())))
+        .map(|(a, b)| (a.base_height - b.base_height).abs())
+        .fold(0., f64::max);
+    // Перепад средней высоты между соседними тайлами не больше, чем при
+    // переходе между самыми разными биомами на одном шаге сетки смешивания
+    let limit = max_step * 1.5 / BiomeMap::BLEND_STEP as f64;
+    for y in -100..100 {
+        for x in -100..100 {
+            let here = biomes.terrain_at(x, y).base_height;
+            let right = biomes.terrain_at(x + 1, y).base_height;
+            let down = biomes.terrain_at(x, y + 1).base_height;
+            assert!(
+                (here - right).abs() <= limit,
+                "Обрыв между {x}, {y} и соседом"
+            );
+            assert!(
+                (here - down).abs() <= limit,
+                "Обрыв между {x}, {y} и соседом"
+            );
+        }
+    }
+}
+
+/// Имя самого верхнего непустого тайла в столбце чанка, если столбец
+/// не продолжается в чанке выше
+fn top_tile<'a>(chunk: &Chunk, tiles: &'a TileRegistry, x: usize, y: usize) -> Option<&'a str> {
+    let name = |z: usize| {
+        &*tiles
+            .get(chunk.tiles.get(x + y * CHUNK_SIZE + z * CHUNK_SIZE.pow(2)))
+            .name
+    };
+    if name(CHUNK_SIZE - 1) != "empty" {
+        return None;
+    }
+    (0..CHUNK_SIZE)
+        .rev()
+        .map(name)
+        .find(|name| *name != "empty")
+}
+
+#[test]
+fn generator_uses_biomes() {
+    with_big_stack(|| {
+        let seed = 1;
+        let tiles = test_tiles();
+        let biomes = BiomeMap::new(seed);
+        for (biome, expected) in [
+            (Biome::Forest, ["grass", "tree"]),
+            (Biome::RuinedCity, ["cobblestone", "cobblestone"]),
+            (Biome::ToxicSwamp, ["toxic_sludge", "toxic_sludge"]),
+        ] {
+            // Чанк, середина которого лежит в нужном биоме
+            let (ch_x, ch_y) = (-20..20)
+                .flat_map(|y| (-20..20).map(move |x| (x, y)))
+                .find(|&(x, y)| {
+                    biomes.biome_at(x * CHUNK_SIZE as i32, y * CHUNK_SIZE as i32) == biome
+                })
+                .unwrap_or_else(|| panic!("Биом {biome:?} не найден"));
+            let chunk = NoiseGenerator.generate(seed, &tiles, ch_x, ch_y, 0);
+            let corner = |local| Vec2::new(global_coord(ch_x, local), global_coord(ch_y, local));
+            let roads = WorldPlan::new(seed).roads_in(corner(0), corner(CHUNK_SIZE - 1));
+            let mut checked = 0;
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let (wx, wy) = (global_coord(ch_x, x), global_coord(ch_y, y));
+                    // На дорогах своё покрытие
+                    if biomes.biome_at(wx, wy) != biome
+                        || roads.iter().any(|road| road.contains(wx, wy))
+                    {
+                        continue;
+                    }
+                    if let Some(top) = top_tile(&chunk, &tiles, x, y) {
+                        assert!(expected.contains(&top), "{biome:?}: {top} в {wx}, {wy}");
+                        checked += 1;
+                    }
+                }
+            }
+            assert!(checked > 0);
+        }
+    });
+}
+
+#[test]
+fn biomes_populate_chunks() {
+    let resources = test_resources();
+    for biome in BIOMES {
+        for (template, _) in biome.spawn_table() {
+            assert!(
+                resources.entity_templates.contains_key(*template),
+                "В таблице биома {biome:?} неизвестный шаблон {template}"
+            );
+        }
+    }
+
+    let seed = 5;
+    let biomes = BiomeMap::new(seed);
+    let mut found = HashMap::<Biome, HashSet<String>>::new();
+    for ch_y in -16..16 {
+        for ch_x in -16..16 {
+            for ch_z in -1..=1 {
+                for (x, y, template) in NoiseGenerator::creature_columns(seed, ch_x, ch_y, ch_z) {
+                    let biome = biomes.biome_at(x, y);
+                    assert!(biome
+                        .spawn_table()
+                        .iter()
+                        .any(|(candidate, _)| *candidate == template));
+                    found.entry(biome).or_default().insert(template.to_string());
+                }
+            }
+        }
+    }
+    // В лесу растёт и молодая крапива, а в пустоши только старая
+    assert!(found[&Biome::Forest].contains("nettle"));
+    assert_eq!(
+        found[&Biome::Wasteland],
+        HashSet::from(["old_nettle".into()])
+    );
+    assert!(!found.contains_key(&Biome::RuinedCity));
+}
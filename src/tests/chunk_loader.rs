@@ -0,0 +1,116 @@
+#![cfg(test)]
+
+use std::{sync::Arc, thread, time::Duration};
+
+use hecs::{Entity, World};
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    map::{Map, WorldMap},
+    player::new_player,
+    systems::{
+        fov_compute::{run_fov_compute_system, Sight},
+        streaming::run_chunk_streaming_system,
+    },
+    worldgen::{FlatGenerator, NoiseGenerator},
+};
+
+use super::tiles::test_tiles;
+
+const SEED: u64 = 1234;
+
+/// Ждёт, пока фоновая генерация выдаст все запрошенные чанки
+fn wait_chunks(map: &mut WorldMap) {
+    while map.loader.as_ref().unwrap().pending() > 0 {
+        map.receive_chunks();
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn background_chunks_match_sync() {
+    let tiles = test_tiles();
+    let mut background = WorldMap::new(SEED, Arc::new(NoiseGenerator), tiles.clone());
+    background.start_loader(3);
+    let mut sync = WorldMap::new(SEED, Arc::new(NoiseGenerator), tiles);
+    let positions = (-1..=1)
+        .flat_map(|x| (-1..=0).flat_map(move |y| (-1..=0).map(move |z| (x, y, z))))
+        .collect::<Vec<_>>();
+    for (x, y, z) in positions.iter().copied() {
+        background.request_chunk(x, y, z);
+        // Повторный запрос не ставит чанк в очередь ещё раз
+        background.request_chunk(x, y, z);
+    }
+    assert!(background.loader.as_ref().unwrap().pending() <= positions.len());
+    wait_chunks(&mut background);
+
+    assert_eq!(background.chunks.len(), positions.len());
+    for (x, y, z) in positions {
+        let generated = background.get_chunk(x, y, z).unwrap().lock().unwrap();
+        let expected = sync.get_chunk_or_create(x, y, z).lock().unwrap();
+        assert!(*generated == *expected, "Чанк {:?} отличается", (x, y, z));
+    }
+}
+
+/// Мир с картой и персонажем, у которого уже посчитано поле зрения
+fn world_with_player(map: WorldMap, pos: Vec3<i32>) -> (World, Entity) {
+    let mut world = World::new();
+    world.spawn((map,));
+    let mut player = new_player();
+    player.add(Position(pos));
+    let player = world.spawn(player.build());
+    run_fov_compute_system(&world).unwrap();
+    (world, player)
+}
+
+#[test]
+fn fov_waits_for_chunks() {
+    let tiles = test_tiles();
+    let generator = Arc::new(FlatGenerator { ground_level: 0 });
+    let mut sync = WorldMap::new(SEED, generator.clone(), tiles.clone());
+    let pos = Vec3::new(0, 0, sync.surface_z(0, 0));
+    let mut background = WorldMap::new(SEED, generator, tiles);
+    background.start_loader(2);
+    let (sync_world, sync_player) = world_with_player(sync, pos);
+    let (world, player) = world_with_player(background, pos);
+
+    // Пока чанки не готовы, не видно ничего, кроме самого персонажа,
+    // и шагнуть в ещё не готовый чанк нельзя
+    assert!(world.get::<&Sight>(player).unwrap().1.len() <= 1);
+    {
+        let mut query = world.query::<(&mut WorldMap,)>();
+        let (_, (map,)) = query.iter().next().unwrap();
+        assert!(map.get_loaded_obstacle(pos.x, pos.y, pos.z));
+        wait_chunks(map);
+    }
+
+    run_fov_compute_system(&world).unwrap();
+    let visible = &world.get::<&Sight>(player).unwrap().1;
+    assert_eq!(*visible, sync_world.get::<&Sight>(sync_player).unwrap().1);
+}
+
+#[test]
+fn chunks_prefetched_ahead() {
+    let mut map = WorldMap::new(
+        SEED,
+        Arc::new(FlatGenerator { ground_level: 0 }),
+        test_tiles(),
+    );
+    map.start_loader(2);
+    let (mut world, player) = world_with_player(map, Vec3::zero());
+    run_chunk_streaming_system(&mut world).unwrap();
+    world.get::<&mut Position>(player).unwrap().0.x += 1;
+    run_chunk_streaming_system(&mut world).unwrap();
+
+    let (_, (map,)) = world
+        .query_mut::<(&mut WorldMap,)>()
+        .into_iter()
+        .next()
+        .unwrap();
+    wait_chunks(map);
+    // Поле зрения задевает чанки до первого, а персонаж идёт в сторону x
+    assert!(map.get_chunk(2, 0, 0).is_some());
+    assert!(map.get_chunk(2, 1, -1).is_some());
+    assert!(map.get_chunk(-2, 0, 0).is_none());
+}
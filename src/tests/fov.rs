@@ -0,0 +1,385 @@
+#![cfg(test)]
+
+use std::sync::Arc;
+
+use hecs::{Entity, World};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rationals::ConstRational;
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    coords::chunk_of,
+    hasher,
+    map::WorldMap,
+    player::{new_player, Player},
+    systems::{
+        fov_compute::{
+            run_fov_compute_system, sight_chunks, sight_tiles, sight_tiles_parallel, slope,
+            BlocksSight, Ray, Sight, SightBlockers, SightSet, NPC_FOV_BUDGET,
+        },
+        lighting::run_lighting_system,
+        movement::WantsMove,
+        pathfinding::{run_pathfinding_system, Pathfinder},
+    },
+    worldgen::{FlatGenerator, NoiseGenerator},
+    Mob,
+};
+
+use super::{tiles::test_tiles, with_big_stack};
+
+const SEED: u64 = 19;
+
+/// Стена поперёк взгляда персонажа с проёмом из тайла gap посередине.
+/// Возвращает мир, где персонаж стоит перед стеной, и поверхность земли.
+fn wall_with_gap(gap: &str) -> (World, i32) {
+    let tiles = test_tiles();
+    let mut map = WorldMap::new(
+        SEED,
+        Arc::new(FlatGenerator { ground_level: 0 }),
+        tiles.clone(),
+    );
+    let z = map.surface_z(0, 0);
+    let (wall, gap) = (tiles.id("wall").unwrap(), tiles.id(gap).unwrap());
+    for y in -6..=6 {
+        for dz in 0..6 {
+            map.set_tile(Vec3::new(3, y, z + dz), wall);
+        }
+    }
+    map.set_tile(Vec3::new(3, 0, z), gap);
+    let mut world = World::new();
+    world.spawn((map,));
+    let mut player = new_player();
+    player.add(Position(Vec3::new(0, 0, z)));
+    world.spawn(player.build());
+    (world, z)
+}
+
+/// Видит ли персонаж тайл за проёмом в стене. Проём на той же высоте,
+/// на которой стоит персонаж.
+fn sees_behind_wall(world: &World) -> bool {
+    run_fov_compute_system(world).unwrap();
+    let mut query = world.query::<(&Player, &Position, &Sight)>();
+    let (_, (_, Position(pos), Sight(_, tiles, ..))) = query.iter().next().unwrap();
+    let behind = Vec3::new(6, 0, pos.z);
+    tiles.contains(&(behind - *pos).into_tuple())
+}
+
+/// Видит ли сущность тайл со смещением offset от себя
+fn sees(world: &World, entity: Entity, offset: Vec3<i32>) -> bool {
+    let sight = world.get::<&Sight>(entity).unwrap();
+    sight.1.contains(&offset.into_tuple())
+}
+
+fn set_tile(world: &World, pos: Vec3<i32>, tile: &str) {
+    let mut query = world.query::<&mut WorldMap>();
+    let (_, map) = query.iter().next().unwrap();
+    let tile = map.tiles.id(tile).unwrap();
+    map.set_tile(pos, tile);
+}
+
+fn is_obstacle(world: &World, pos: Vec3<i32>) -> bool {
+    let mut query = world.query::<&mut WorldMap>();
+    let (_, map) = query.iter().next().unwrap();
+    map.get_obstacle_or_create(pos.x, pos.y, pos.z)
+}
+
+#[test]
+fn wall_blocks_sight() {
+    with_big_stack(|| {
+        let (world, z) = wall_with_gap("wall");
+        assert!(is_obstacle(&world, Vec3::new(3, 0, z)));
+        assert!(!sees_behind_wall(&world));
+        let (world, _) = wall_with_gap("empty");
+        assert!(sees_behind_wall(&world));
+    });
+}
+
+#[test]
+fn window_lets_sight_through() {
+    with_big_stack(|| {
+        let (world, z) = wall_with_gap("window");
+        // Сквозь окно видно, но пройти нельзя
+        assert!(is_obstacle(&world, Vec3::new(3, 0, z)));
+        assert!(sees_behind_wall(&world));
+    });
+}
+
+#[test]
+fn curtain_blocks_sight() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        world.spawn((Position(Vec3::new(3, 0, z)), BlocksSight));
+        // Через занавеску можно пройти, но сквозь неё не видно
+        assert!(!is_obstacle(&world, Vec3::new(3, 0, z)));
+        assert!(!sees_behind_wall(&world));
+    });
+}
+
+#[test]
+fn npcs_see_through_gap() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        let in_front = world.spawn((Position(Vec3::new(6, 0, z)), Sight::new(10)));
+        let aside = world.spawn((Position(Vec3::new(6, 4, z)), Sight::new(10)));
+        run_fov_compute_system(&world).unwrap();
+        assert!(sees(&world, in_front, Vec3::new(-6, 0, 0)));
+        assert!(!sees(&world, aside, Vec3::new(-6, -4, 0)));
+        assert!(world.get::<&Sight>(aside).unwrap().2.is_some());
+
+        // Заделанный проём меняет карту, и поле зрения считается заново
+        set_tile(&world, Vec3::new(3, 0, z), "wall");
+        run_fov_compute_system(&world).unwrap();
+        assert!(!sees(&world, in_front, Vec3::new(-6, 0, 0)));
+    });
+}
+
+#[test]
+fn tiny_sight_radius() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        let blind = world.spawn((Position(Vec3::new(0, 3, z)), Sight::new(0)));
+        let near = world.spawn((Position(Vec3::new(0, -3, z)), Sight::new(1)));
+        run_fov_compute_system(&world).unwrap();
+        let tiles = |entity| {
+            let sight = world.get::<&Sight>(entity).unwrap();
+            sight.1.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(tiles(blind), [(0, 0, 0)]);
+        assert!(tiles(near)
+            .iter()
+            .all(|&(x, y, z)| x.abs().max(y.abs()).max(z.abs()) <= 1));
+        assert!(sees(&world, near, Vec3::new(1, 0, 0)));
+        assert!(sees(&world, near, Vec3::new(0, 0, -1)));
+    });
+}
+
+#[test]
+fn npc_sight_budget() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        let npcs = (0..NPC_FOV_BUDGET as i32 + 3)
+            .map(|i| world.spawn((Position(Vec3::new(-i, 2, z)), Sight::new(5))))
+            .collect::<Vec<_>>();
+        let computed = |world: &World| {
+            npcs.iter()
+                .filter(|&&npc| world.get::<&Sight>(npc).unwrap().2.is_some())
+                .count()
+        };
+        run_fov_compute_system(&world).unwrap();
+        // Первыми считаются ближайшие к персонажу
+        assert_eq!(computed(&world), NPC_FOV_BUDGET);
+        assert!(world.get::<&Sight>(npcs[0]).unwrap().2.is_some());
+        assert!(world
+            .get::<&Sight>(*npcs.last().unwrap())
+            .unwrap()
+            .2
+            .is_none());
+        run_fov_compute_system(&world).unwrap();
+        assert_eq!(computed(&world), npcs.len());
+    });
+}
+
+#[test]
+fn moving_npcs_all_get_their_turn() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        let npcs = (0..NPC_FOV_BUDGET as i32 + 8)
+            .map(|i| world.spawn((Position(Vec3::new(-i, 2, z)), Sight::new(5))))
+            .collect::<Vec<_>>();
+        let mut refreshed = vec![false; npcs.len()];
+        // Все NPC двигаются каждый ход, а за два хода поле зрения пересчитывается
+        // у каждого, и у дальних тоже
+        for turn in 0..2 {
+            for &npc in npcs.iter() {
+                world.get::<&mut Position>(npc).unwrap().0.y = 2 + turn % 2;
+            }
+            run_fov_compute_system(&world).unwrap();
+            for (refreshed, &npc) in refreshed.iter_mut().zip(npcs.iter()) {
+                let Position(pos) = *world.get::<&Position>(npc).unwrap();
+                *refreshed |= world
+                    .get::<&Sight>(npc)
+                    .unwrap()
+                    .2
+                    .is_some_and(|key| key.pos == pos);
+            }
+        }
+        assert!(refreshed.iter().all(|&refreshed| refreshed));
+    });
+}
+
+#[test]
+fn pathfinder_chases_only_seen_player() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        let mob = |pos| (Position(pos), Mob, Pathfinder, Sight::new(10));
+        let in_front = world.spawn(mob(Vec3::new(6, 0, z)));
+        let aside = world.spawn(mob(Vec3::new(6, 4, z)));
+        run_fov_compute_system(&world).unwrap();
+        run_lighting_system(&world).unwrap();
+        run_pathfinding_system(&mut world).unwrap();
+        assert!(world.get::<&WantsMove>(in_front).is_ok());
+        assert!(world.get::<&WantsMove>(aside).is_err());
+    });
+}
+
+#[test]
+fn rays_compare_like_rationals() {
+    let rays = (1..=12)
+        .flat_map(|depth| (-depth..=depth + 1).map(move |col| slope(depth, col)))
+        .collect::<Vec<_>>();
+    for &a in rays.iter() {
+        let exact = ConstRational::from(a);
+        assert_eq!(Ray::from(exact), a);
+        for &b in rays.iter() {
+            assert_eq!(a.cmp(&b), exact.cmp(&ConstRational::from(b)));
+        }
+    }
+}
+
+#[test]
+fn sight_set() {
+    let mut set = SightSet::new(2);
+    assert!(set.is_empty());
+    assert!(set.insert((0, 0, 0)));
+    assert!(set.insert((-2, 1, 2)));
+    assert!(set.insert((2, -2, -2)));
+    assert!(!set.insert((0, 0, 0)));
+    assert_eq!(set.len(), 3);
+    assert!(set.contains(&(-2, 1, 2)));
+    assert!(!set.contains(&(1, 1, 2)));
+    assert!(!set.contains(&(3, 0, 0)));
+    assert_eq!(
+        set.iter().collect::<Vec<_>>(),
+        [(2, -2, -2), (0, 0, 0), (-2, 1, 2)]
+    );
+    set.clear();
+    assert_eq!(set.iter().count(), 0);
+}
+
+#[test]
+fn sight_cached_until_map_changes() {
+    with_big_stack(|| {
+        let (mut world, z) = wall_with_gap("empty");
+        run_fov_compute_system(&world).unwrap();
+        // Очищенное поле зрения остаётся пустым, пока его не пересчитают
+        let forget = |world: &World| {
+            let mut query = world.query::<(&Player, &mut Sight)>();
+            let (_, (_, sight)) = query.iter().next().unwrap();
+            sight.1.clear();
+        };
+        let recomputed = |world: &World| {
+            run_fov_compute_system(world).unwrap();
+            let mut query = world.query::<(&Player, &Sight)>();
+            let (_, (_, sight)) = query.iter().next().unwrap();
+            !sight.1.is_empty()
+        };
+
+        forget(&world);
+        assert!(!recomputed(&world));
+        // Изменение далеко за радиусом обзора не трогает кэш
+        set_tile(&world, Vec3::new(200, 0, z), "wall");
+        assert!(!recomputed(&world));
+        set_tile(&world, Vec3::new(10, 10, z), "wall");
+        assert!(recomputed(&world));
+
+        forget(&world);
+        let curtain = world.spawn((Position(Vec3::new(3, 0, z)), BlocksSight));
+        assert!(recomputed(&world));
+        forget(&world);
+        world.get::<&mut Position>(curtain).unwrap().0.y = 1;
+        assert!(recomputed(&world));
+
+        forget(&world);
+        for (_, (_, Position(pos))) in world.query_mut::<(&Player, &mut Position)>() {
+            pos.x -= 1;
+        }
+        assert!(recomputed(&world));
+    });
+}
+
+#[test]
+fn parallel_sight_matches_sequential() {
+    with_big_stack(|| {
+        let mut map = WorldMap::new(SEED, Arc::new(NoiseGenerator), test_tiles());
+        let mut blockers = SightBlockers::with_hasher(hasher());
+        for (x, y) in [(0, 0), (37, -12), (-50, 44)] {
+            let pos = Vec3::new(x, y, map.surface_z(x, y));
+            for (ch_x, ch_y, ch_z) in sight_chunks(chunk_of(pos.x, pos.y, pos.z), 40) {
+                map.request_chunk(ch_x, ch_y, ch_z);
+            }
+            blockers.insert((pos.x + 2, pos.y + 1, pos.z));
+            let sequential = sight_tiles(pos, 40, &map, &blockers);
+            assert!(sequential.1);
+            assert!(sequential.0.len() > 1000);
+            for _ in 0..3 {
+                assert_eq!(sight_tiles_parallel(pos, 40, &map, &blockers), sequential);
+            }
+        }
+    });
+}
+
+/// Случайная карта: стены, разбросанные с плотностью density над плоской землёй
+fn random_walls(seed: u64, density: f64) -> WorldMap {
+    let tiles = test_tiles();
+    let mut map = WorldMap::new(
+        SEED,
+        Arc::new(FlatGenerator { ground_level: 0 }),
+        tiles.clone(),
+    );
+    let wall = tiles.id("wall").unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    for z in 0..12 {
+        for y in -24..=24 {
+            for x in -24..=24 {
+                if rng.gen_bool(density) {
+                    map.set_tile(Vec3::new(x, y, z), wall);
+                }
+            }
+        }
+    }
+    map
+}
+
+/// Если из A виден прозрачный тайл B, то и из B виден A
+#[test]
+fn sight_is_symmetric() {
+    with_big_stack(|| {
+        let blockers = SightBlockers::with_hasher(hasher());
+        for seed in 0..6 {
+            let mut map = random_walls(seed, [0.02, 0.1, 0.25][seed as usize % 3]);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let viewers = (0..30)
+                .map(|_| {
+                    Vec3::new(
+                        rng.gen_range(-20..=20),
+                        rng.gen_range(-20..=20),
+                        rng.gen_range(0..10),
+                    )
+                })
+                .filter(|pos| !map.get_obstacle_or_create(pos.x, pos.y, pos.z))
+                .collect::<Vec<_>>();
+            let sights = viewers
+                .iter()
+                .map(|&pos| {
+                    let mut sight = SightSet::new(30);
+                    sight.extend(sight_tiles(pos, 30, &map, &blockers).0);
+                    sight
+                })
+                .collect::<Vec<_>>();
+            let mut seen = 0;
+            for (a, sight_a) in viewers.iter().zip(sights.iter()) {
+                for (b, sight_b) in viewers.iter().zip(sights.iter()) {
+                    let a_sees_b = sight_a.contains(&(b - a).into_tuple());
+                    assert_eq!(
+                        a_sees_b,
+                        sight_b.contains(&(a - b).into_tuple()),
+                        "{a:?} и {b:?} видят друг друга по-разному"
+                    );
+                    seen += a_sees_b as usize;
+                }
+            }
+            assert!(seen > viewers.len());
+        }
+    });
+}
@@ -0,0 +1,329 @@
+#![cfg(test)]
+
+use std::sync::Arc;
+
+use hecs::World;
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    light::{Light, DAYLIGHT},
+    map::{Map, WorldMap},
+    player::new_player,
+    systems::{
+        fov_compute::{run_fov_compute_system, Sight},
+        lighting::{run_lighting_system, LightSource},
+        memory::{run_memory_system, MapMemory},
+        movement::WantsMove,
+        pathfinding::{run_pathfinding_system, Pathfinder},
+    },
+    worldgen::FlatGenerator,
+    Mob,
+};
+
+use super::{tiles::test_tiles, with_big_stack};
+
+const SEED: u64 = 23;
+
+const LAMP: Light = Light {
+    radius: 5,
+    color: 0xFF8000,
+    intensity: 1.,
+};
+
+/// Мир с плоской землёй ночью, без света неба, и наблюдателем в начале
+/// координат, ради которого считается свет. Возвращает его и высоту, на которой
+/// можно стоять.
+fn flat_world() -> (World, i32) {
+    let mut map = WorldMap::new(
+        SEED,
+        Arc::new(FlatGenerator { ground_level: 0 }),
+        test_tiles(),
+    );
+    map.light.sky = [0; 3];
+    let z = map.surface_z(0, 0);
+    let mut world = World::new();
+    world.spawn((map,));
+    world.spawn((Position(Vec3::new(0, 0, z)), Sight::new(20)));
+    (world, z)
+}
+
+fn set_tile(world: &World, pos: Vec3<i32>, tile: &str) {
+    let mut query = world.query::<(&mut WorldMap,)>();
+    let (_, (map,)) = query.iter().next().unwrap();
+    let tile = map.tiles.id(tile).unwrap();
+    map.set_tile(pos, tile);
+}
+
+/// Свет в тайле в долях 1/255, как его задают источники
+fn light_at(world: &World, pos: Vec3<i32>) -> [u32; 3] {
+    let mut query = world.query::<(&WorldMap,)>();
+    let (_, (map,)) = query.iter().next().unwrap();
+    map.light
+        .get(pos.into_tuple())
+        .map(|channel| (channel * 255.).round() as u32)
+}
+
+fn is_lit(world: &World, pos: Vec3<i32>) -> bool {
+    let mut query = world.query::<(&WorldMap,)>();
+    let (_, (map,)) = query.iter().next().unwrap();
+    map.light.is_lit(pos.into_tuple())
+}
+
+fn amount(light: Light, offset: (i32, i32, i32)) -> [u32; 3] {
+    light.at(offset).map(u32::from)
+}
+
+#[test]
+fn light_fades_with_distance() {
+    assert_eq!(LAMP.at((0, 0, 0)), [255, 128, 0]);
+    // На середине пути до края радиуса остаётся половина яркости
+    assert_eq!(LAMP.at((3, 0, 0)), [128, 64, 0]);
+    assert_eq!(LAMP.at((0, -3, 0)), LAMP.at((0, 0, 3)));
+    assert_eq!(LAMP.at((6, 0, 0)), [0, 0, 0]);
+    let dim = Light {
+        intensity: 0.5,
+        ..LAMP
+    };
+    assert_eq!(dim.at((0, 0, 0)), [128, 64, 0]);
+}
+
+#[test]
+fn light_spreads_in_open_air() {
+    with_big_stack(|| {
+        let (mut world, z) = flat_world();
+        world.spawn((Position(Vec3::new(0, 0, z)), LightSource(LAMP)));
+        run_lighting_system(&world).unwrap();
+
+        assert_eq!(light_at(&world, Vec3::new(0, 0, z)), [255, 128, 0]);
+        assert_eq!(light_at(&world, Vec3::new(3, 0, z)), [128, 64, 0]);
+        assert_eq!(light_at(&world, Vec3::new(0, 0, z + 3)), [128, 64, 0]);
+        assert!(is_lit(&world, Vec3::new(0, 5, z)));
+        assert!(!is_lit(&world, Vec3::new(6, 0, z)));
+        // Освещена поверхность земли, но не то, что под ней
+        assert!(is_lit(&world, Vec3::new(2, 0, z - 1)));
+        assert!(!is_lit(&world, Vec3::new(2, 0, z - 2)));
+    });
+}
+
+#[test]
+fn walls_block_light() {
+    with_big_stack(|| {
+        let (mut world, z) = flat_world();
+        // Стена сбоку и потолок над источником
+        for a in -4..=4 {
+            for b in -4..=4 {
+                set_tile(&world, Vec3::new(a, b, z + 2), "wall");
+            }
+            for dz in 0..2 {
+                set_tile(&world, Vec3::new(2, a, z + dz), "wall");
+            }
+        }
+        world.spawn((Position(Vec3::new(0, 0, z)), LightSource(LAMP)));
+        run_lighting_system(&world).unwrap();
+
+        assert!(is_lit(&world, Vec3::new(2, 0, z)));
+        assert!(!is_lit(&world, Vec3::new(3, 0, z)));
+        assert!(is_lit(&world, Vec3::new(-3, 0, z)));
+        assert!(is_lit(&world, Vec3::new(0, 0, z + 2)));
+        assert!(!is_lit(&world, Vec3::new(0, 0, z + 3)));
+
+        // В проём в стене свет проходит
+        set_tile(&world, Vec3::new(2, 0, z), "empty");
+        run_lighting_system(&world).unwrap();
+        assert!(is_lit(&world, Vec3::new(3, 0, z)));
+        // Сквозь окно тоже, хотя пройти через него нельзя
+        set_tile(&world, Vec3::new(2, 0, z), "window");
+        run_lighting_system(&world).unwrap();
+        assert!(is_lit(&world, Vec3::new(3, 0, z)));
+    });
+}
+
+#[test]
+fn lights_add_up_and_go_out() {
+    with_big_stack(|| {
+        let (mut world, z) = flat_world();
+        let a = world.spawn((Position(Vec3::new(0, 0, z)), LightSource(LAMP)));
+        let b = world.spawn((Position(Vec3::new(2, 0, z)), LightSource(LAMP)));
+        run_lighting_system(&world).unwrap();
+        let between = Vec3::new(1, 0, z);
+        let [r, g, _] = amount(LAMP, (1, 0, 0));
+        assert_eq!(light_at(&world, between), [2 * r, 2 * g, 0]);
+
+        world.despawn(b).unwrap();
+        run_lighting_system(&world).unwrap();
+        assert_eq!(light_at(&world, between), [r, g, 0]);
+
+        // Источник сдвинулся и сменил цвет
+        world
+            .insert(
+                a,
+                (
+                    Position(Vec3::new(10, 0, z)),
+                    LightSource(Light {
+                        color: 0x0000FF,
+                        ..LAMP
+                    }),
+                ),
+            )
+            .unwrap();
+        run_lighting_system(&world).unwrap();
+        assert!(!is_lit(&world, between));
+        assert_eq!(light_at(&world, Vec3::new(10, 0, z)), [0, 0, 255]);
+
+        world.remove_one::<LightSource>(a).unwrap();
+        run_lighting_system(&world).unwrap();
+        assert_eq!(light_at(&world, Vec3::new(10, 0, z)), [0, 0, 0]);
+    });
+}
+
+#[test]
+fn lamp_tiles_shine() {
+    with_big_stack(|| {
+        let (world, z) = flat_world();
+        let tiles = test_tiles();
+        let lamp = tiles.by_name("lamp").unwrap().light.unwrap();
+        // Лампа непрозрачна, но светит вокруг себя
+        set_tile(&world, Vec3::new(0, 0, z), "lamp");
+        run_lighting_system(&world).unwrap();
+        assert_eq!(
+            light_at(&world, Vec3::new(0, 0, z)),
+            amount(lamp, (0, 0, 0))
+        );
+        assert_eq!(
+            light_at(&world, Vec3::new(0, 3, z)),
+            amount(lamp, (0, 3, 0))
+        );
+
+        set_tile(&world, Vec3::new(0, 0, z), "empty");
+        run_lighting_system(&world).unwrap();
+        assert!(!is_lit(&world, Vec3::new(0, 3, z)));
+    });
+}
+
+#[test]
+fn darkness_hides_player() {
+    with_big_stack(|| {
+        let (mut world, z) = flat_world();
+        let mut player = new_player();
+        player.add(Position(Vec3::new(0, 0, z)));
+        let player = world.spawn(player.build());
+        let npc = world.spawn((
+            Position(Vec3::new(8, 0, z)),
+            Mob,
+            Pathfinder,
+            Sight::new(10),
+        ));
+        let chases = |world: &mut World| {
+            world.remove_one::<WantsMove>(npc).ok();
+            run_fov_compute_system(world).unwrap();
+            run_lighting_system(world).unwrap();
+            run_pathfinding_system(world).unwrap();
+            world.get::<&WantsMove>(npc).is_ok()
+        };
+        // С фонарём персонажа видно издалека, а без него не видно
+        assert!(chases(&mut world));
+        world.remove_one::<LightSource>(player).unwrap();
+        assert!(!chases(&mut world));
+        // Чужой свет тоже выдаёт персонажа
+        world.spawn((Position(Vec3::new(0, 2, z)), LightSource(LAMP)));
+        assert!(chases(&mut world));
+    });
+}
+
+#[test]
+fn only_lit_tiles_are_memorized() {
+    with_big_stack(|| {
+        let (mut world, z) = flat_world();
+        let mut player = new_player();
+        player.add(Position(Vec3::new(0, 0, z)));
+        let player = world.spawn(player.build());
+        world.remove_one::<LightSource>(player).unwrap();
+        world.spawn((
+            Position(Vec3::new(6, 0, z)),
+            LightSource(Light { radius: 2, ..LAMP }),
+        ));
+        run_fov_compute_system(&world).unwrap();
+        run_lighting_system(&world).unwrap();
+        run_memory_system(&world).unwrap();
+
+        let memory = world.get::<&MapMemory>(player).unwrap();
+        let is_memorized = |pos: Vec3<i32>| {
+            let (ch_x, ch_y, ch_z) = MapMemory::xy_chunk(pos.x, pos.y, pos.z);
+            memory
+                .get_chunk(ch_x, ch_y, ch_z)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .is_memorized(pos.x, pos.y, pos.z)
+        };
+        assert!(is_memorized(Vec3::new(6, 0, z)));
+        assert!(is_memorized(Vec3::new(6, 0, z - 1)));
+        assert!(!is_memorized(Vec3::new(0, 1, z)));
+        assert!(!is_memorized(Vec3::new(0, 0, z - 1)));
+    });
+}
+
+#[test]
+fn sky_lights_open_tiles() {
+    with_big_stack(|| {
+        let (world, z) = flat_world();
+        {
+            let mut query = world.query::<(&mut WorldMap,)>();
+            let (_, (map,)) = query.iter().next().unwrap();
+            map.light.sky = DAYLIGHT;
+        }
+        // Навес над (0, 0) высоко над землёй
+        set_tile(&world, Vec3::new(0, 0, z + 10), "wall");
+        run_lighting_system(&world).unwrap();
+
+        assert_eq!(light_at(&world, Vec3::new(3, 0, z)), [255; 3]);
+        assert!(is_lit(&world, Vec3::new(3, 0, z - 1)));
+        assert!(!is_lit(&world, Vec3::new(3, 0, z - 2)));
+        assert!(is_lit(&world, Vec3::new(0, 0, z + 10)));
+        assert!(!is_lit(&world, Vec3::new(0, 0, z + 9)));
+        assert!(!is_lit(&world, Vec3::new(0, 0, z)));
+
+        // Навес убрали, и под ним снова светло
+        set_tile(&world, Vec3::new(0, 0, z + 10), "empty");
+        run_lighting_system(&world).unwrap();
+        assert!(is_lit(&world, Vec3::new(0, 0, z)));
+    });
+}
+
+#[test]
+fn sky_does_not_reach_caves() {
+    with_big_stack(|| {
+        // Земля намного выше загруженных чанков, и что над ними, неизвестно
+        let mut map = WorldMap::new(
+            SEED,
+            Arc::new(FlatGenerator { ground_level: 1000 }),
+            test_tiles(),
+        );
+        map.get_chunk_or_create(0, 0, 0);
+        let empty = map.tiles.id("empty").unwrap();
+        map.set_tile(Vec3::new(0, 0, 31), empty);
+        map.set_tile(Vec3::new(0, 0, 30), empty);
+        let mut world = World::new();
+        world.spawn((map,));
+        world.spawn((Position(Vec3::new(0, 0, 30)), Sight::new(20)));
+        run_lighting_system(&world).unwrap();
+        assert!(!is_lit(&world, Vec3::new(0, 0, 30)));
+    });
+}
+
+#[test]
+fn unseen_lights_wait_for_viewers() {
+    with_big_stack(|| {
+        let (mut world, z) = flat_world();
+        let far = Vec3::new(100, 0, z);
+        world.spawn((Position(far), LightSource(LAMP)));
+        run_lighting_system(&world).unwrap();
+        assert!(!is_lit(&world, far));
+
+        // Наблюдатель подошёл, и до него свет уже достаёт
+        world.spawn((Position(Vec3::new(80, 0, z)), Sight::new(20)));
+        run_lighting_system(&world).unwrap();
+        assert_eq!(light_at(&world, far), [255, 128, 0]);
+    });
+}
@@ -1,62 +1,185 @@
 #![cfg(test)]
 
+use std::sync::Arc;
+
+use proptest::prelude::*;
+
+use crate::{
+    coords::{chunk_coord, global_coord, global_of, local_coord, local_of_index},
+    map::{Chunk, Map, WorldMap, CHUNK_SIZE},
+    storage::CHUNK_TILES,
+    systems::memory::MapMemory,
+    worldgen::{ChunkGenerator, ClassicGenerator, PatternGenerator},
+};
+
+use super::{tiles::test_tiles, with_big_stack};
+
 #[test]
 fn map_index() {
-    //    let ch_idx = WorldMap::xy_index_chunk(0, 0);
-    //    assert_eq!(ch_idx, 112);
-    //    let ch_idx = WorldMap::xy_index_chunk(15, 15);
-    //    assert_eq!(ch_idx, 112);
-    //    let ch_idx = WorldMap::xy_index_chunk(-15, -15);
-    //    assert_eq!(ch_idx, 112);
-    //    let ch_idx = WorldMap::xy_index_chunk(-1, -1);
-    //    assert_eq!(ch_idx, 96);
+    assert_eq!(WorldMap::xy_index_chunk(-32, -32, -32), 0);
+    assert_eq!(WorldMap::xy_index_chunk(31, 31, 31), CHUNK_TILES - 1);
+    assert_eq!(
+        WorldMap::xy_index_chunk(0, 0, 0),
+        32 + 32 * 64 + 32 * 64 * 64
+    );
+    // Последний тайл по x чанка -1, первые по y и z чанков 1 и -1
+    assert_eq!(WorldMap::xy_index_chunk(-33, 32, -96), 63);
+    assert_eq!(
+        WorldMap::xy_index_chunk(-1, -1, -1),
+        31 + 31 * 64 + 31 * 64 * 64
+    );
+    assert_eq!(
+        MapMemory::xy_index_chunk(100, -100, 7),
+        WorldMap::xy_index_chunk(100, -100, 7)
+    );
 }
 
 #[test]
 fn chunk_xy() {
-    //    for i in -7..=7 {
-    //        for j in -7..=7 {
-    //            assert_eq!((0, 0), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7..=7 {
-    //        for j in -7 + 15..=7 + 15 {
-    //            assert_eq!((0, 1), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7..=7 {
-    //        for j in -7 - 15..=7 - 15 {
-    //            assert_eq!((0, -1), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7 + 15..=7 + 15 {
-    //        for j in -7..=7 {
-    //            assert_eq!((1, 0), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7 - 15..=7 - 15 {
-    //        for j in -7..=7 {
-    //            assert_eq!((-1, 0), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7 + 15..=7 + 15 {
-    //        for j in -7 + 15..=7 + 15 {
-    //            assert_eq!((1, 1), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7 - 15..=7 - 15 {
-    //        for j in -7 - 15..=7 - 15 {
-    //            assert_eq!((-1, -1), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7 + 15..=7 + 15 {
-    //        for j in -7 - 15..=7 - 15 {
-    //            assert_eq!((1, -1), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
-    //    for i in -7 - 15..=7 - 15 {
-    //        for j in -7 + 15..=7 + 15 {
-    //            assert_eq!((-1, 1), WorldMap::xy_chunk(i, j));
-    //        }
-    //    }
+    // Чанк k покрывает координаты от 64k - 32 до 64k + 31 по каждой оси
+    for k in -3..=3 {
+        let (first, last) = (64 * k - 32, 64 * k + 31);
+        for (x, chunk) in [(first, k), (last, k), (first - 1, k - 1), (last + 1, k + 1)] {
+            assert_eq!(WorldMap::xy_chunk(x, 0, 0), (chunk, 0, 0), "x = {x}");
+            assert_eq!(WorldMap::xy_chunk(0, x, 0), (0, chunk, 0), "y = {x}");
+            assert_eq!(WorldMap::xy_chunk(0, 0, x), (0, 0, chunk), "z = {x}");
+        }
+        assert_eq!(local_coord(first), 0);
+        assert_eq!(local_coord(last), CHUNK_SIZE - 1);
+        assert_eq!(global_coord(k, 0), first);
+    }
+    assert_eq!(chunk_coord(i32::MAX), i32::MAX / 64 + 1);
+    assert_eq!(chunk_coord(i32::MIN), i32::MIN / 64);
+}
+
+/// Координаты около границ чанков, в том числе отрицательные и у краёв i32
+fn any_coord() -> impl Strategy<Value = i32> {
+    prop_oneof![
+        any::<i32>(),
+        -1000..1000,
+        (-1000..1000i32, -2..2i32).prop_map(|(k, d)| 64 * k - 32 + d),
+        Just(i32::MIN),
+        Just(i32::MAX),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn global_to_chunk_and_back(x in any_coord(), y in any_coord(), z in any_coord()) {
+        let chunk = WorldMap::xy_chunk(x, y, z);
+        let index = WorldMap::xy_index_chunk(x, y, z);
+        prop_assert!(index < CHUNK_TILES);
+        prop_assert_eq!(global_of(chunk, index), (x, y, z));
+        prop_assert_eq!(
+            local_of_index(index),
+            (local_coord(x), local_coord(y), local_coord(z))
+        );
+        prop_assert_eq!(MapMemory::xy_chunk(x, y, z), chunk);
+    }
+
+    #[test]
+    fn chunk_and_local_to_global(
+        chunk in -(1 << 25) + 1..(1 << 25) - 1,
+        local in 0..CHUNK_SIZE,
+    ) {
+        let global = global_coord(chunk, local);
+        prop_assert_eq!(chunk_coord(global), chunk);
+        prop_assert_eq!(local_coord(global), local);
+    }
+
+    #[test]
+    fn neighbour_tiles(x in any_coord()) {
+        prop_assume!(x != i32::MAX);
+        // Соседний тайл лежит в том же чанке или в следующем, начиная его
+        let step = chunk_coord(x + 1) - chunk_coord(x);
+        prop_assert!(step == 0 || step == 1);
+        prop_assert_eq!(step == 1, local_coord(x + 1) == 0);
+        if step == 0 {
+            prop_assert_eq!(local_coord(x + 1), local_coord(x) + 1);
+        }
+    }
+}
+
+/// Карта, системы и генератор сходятся в том, где какой тайл, в том числе
+/// на границах чанков с отрицательными координатами
+#[test]
+fn map_agrees_with_generator() {
+    with_big_stack(|| {
+        let is_wall = |x: i32, y: i32, z: i32| (x * 31 + y * 17 + z * 7).rem_euclid(5) == 0;
+        let mut map = WorldMap::new(1, Arc::new(PatternGenerator { is_wall }), test_tiles());
+        let coords = [-33, -32, -1, 0, 31, 32];
+        for x in coords {
+            for y in coords {
+                for z in coords {
+                    assert_eq!(map.get_obstacle_or_create(x, y, z), is_wall(x, y, z));
+                    assert_eq!(map.get_loaded_obstacle(x, y, z), is_wall(x, y, z));
+                    let (ch_x, ch_y, ch_z) = WorldMap::xy_chunk(x, y, z);
+                    let chunk = map.get_chunk(ch_x, ch_y, ch_z).unwrap().lock().unwrap();
+                    let tile = map.tiles.get(chunk.get_tile(x, y, z));
+                    assert_eq!(tile.blocks_movement, is_wall(x, y, z));
+                }
+            }
+        }
+        assert_eq!(map.chunks.len(), 27);
+    });
+}
+
+/// Координаты непроходимых тайлов чанка выше уровня земли в порядке хранения
+fn walls_above_ground(chunk: &Chunk) -> Vec<(usize, usize, usize)> {
+    (CHUNK_SIZE.pow(3) / 2..CHUNK_SIZE.pow(3))
+        .filter(|&idx| chunk.obstacles.get(idx))
+        .map(|idx| {
+            (
+                idx % CHUNK_SIZE,
+                idx / CHUNK_SIZE % CHUNK_SIZE,
+                idx / CHUNK_SIZE.pow(2),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn chunk_generation_is_deterministic() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        for (x, y, z) in [(0, 0, 0), (-3, 7, 0), (2, -1, -1), (0, 0, 1)] {
+            let a = ClassicGenerator.generate(42, &tiles, x, y, z);
+            let b = ClassicGenerator.generate(42, &tiles, x, y, z);
+            assert!(a == b, "Чанк {x}, {y}, {z} генерируется по-разному");
+        }
+        let a = ClassicGenerator.generate(1, &tiles, 0, 0, 0);
+        let b = ClassicGenerator.generate(2, &tiles, 0, 0, 0);
+        assert_ne!(walls_above_ground(&a), walls_above_ground(&b));
+        let c = ClassicGenerator.generate(1, &tiles, 1, 0, 0);
+        assert_ne!(walls_above_ground(&a), walls_above_ground(&c));
+    });
+}
+
+#[test]
+fn chunk_generation_layout() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let chunk = ClassicGenerator.generate(1, &tiles, 0, 0, 0);
+        assert_eq!(
+            walls_above_ground(&chunk),
+            [
+                (25, 2, 32),
+                (46, 3, 32),
+                (49, 3, 32),
+                (21, 10, 32),
+                (26, 24, 32),
+                (44, 24, 32),
+                (38, 26, 32),
+                (37, 28, 32),
+                (12, 31, 32),
+                (15, 35, 32),
+                (38, 36, 32),
+                (42, 57, 32),
+                (41, 62, 32),
+            ]
+        );
+        let mut map = WorldMap::new(1, Arc::new(ClassicGenerator), tiles);
+        let from_map = map.get_chunk_or_create(0, 0, 0).lock().unwrap();
+        assert!(*from_map == chunk);
+    });
 }
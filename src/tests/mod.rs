@@ -1,2 +1,28 @@
+mod bench;
+mod biome;
+mod chunk_loader;
 mod error;
+mod fov;
+mod light;
 mod map;
+mod planner;
+mod prefabs;
+mod registry;
+mod resources;
+mod save;
+mod storage;
+mod streaming;
+mod terrain;
+mod tiles;
+mod worldgen;
+
+/// Чанки целиком лежат на стеке, и стека тестового потока для них не хватает
+#[cfg(test)]
+fn with_big_stack(f: fn()) {
+    std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
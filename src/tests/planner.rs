@@ -0,0 +1,148 @@
+#![cfg(test)]
+
+use std::sync::Arc;
+
+use vek::Vec2;
+
+use crate::{
+    biome::Biome,
+    coords::{chunk_coord, local_coord},
+    map::WorldMap,
+    planner::{Road, Settlement, WorldPlan, MAX_ROAD, PLAN_REGION},
+    worldgen::{ChunkGenerator, NoiseGenerator},
+};
+
+use super::{tiles::test_tiles, with_big_stack};
+
+const SEED: u64 = 5;
+
+/// Все поселения в квадрате областей от -n до n
+fn settlements(plan: &WorldPlan, n: i32) -> Vec<Settlement> {
+    (-n..=n)
+        .flat_map(|y| (-n..=n).map(move |x| (x, y)))
+        .filter_map(|(x, y)| plan.settlement(x, y))
+        .collect()
+}
+
+fn sorted(mut roads: Vec<Road>) -> Vec<Road> {
+    roads.sort_by_key(|road| (road.from.x, road.from.y, road.to.x, road.to.y));
+    roads
+}
+
+#[test]
+fn settlements_from_seed() {
+    let plan = WorldPlan::new(SEED);
+    let all = settlements(&plan, 4);
+    assert!(all.len() > 20);
+    assert_eq!(all, settlements(&WorldPlan::new(SEED), 4));
+    assert_ne!(all, settlements(&WorldPlan::new(SEED + 1), 4));
+    for settlement in all {
+        let (x, y) = (settlement.center.x, settlement.center.y);
+        assert!(!matches!(
+            plan.biomes.biome_at(x, y),
+            Biome::Crater | Biome::ToxicSwamp
+        ));
+        assert_eq!(plan.settlement_at(x, y), Some(settlement));
+        // Поселение целиком лежит в своей области
+        let radius = settlement.radius;
+        assert_eq!(plan.settlement_at(x + radius, y), Some(settlement));
+        assert_eq!(plan.settlement_at(x - radius, y), Some(settlement));
+        assert_eq!(
+            (x - radius).div_euclid(PLAN_REGION),
+            (x + radius).div_euclid(PLAN_REGION)
+        );
+        assert_ne!(plan.settlement_at(x + radius + 1, y), Some(settlement));
+    }
+}
+
+#[test]
+fn plan_is_shared() {
+    let map = WorldMap::new(SEED, Arc::new(NoiseGenerator), test_tiles());
+    // Карта и генератор пользуются одним планом, а не строят каждый свой
+    assert!(Arc::ptr_eq(&map.plan, &NoiseGenerator.plan(SEED).unwrap()));
+    assert!(Arc::ptr_eq(&map.plan, &NoiseGenerator.plan(SEED).unwrap()));
+}
+
+#[test]
+fn roads_connect_nearest_settlements() {
+    let plan = WorldPlan::new(SEED);
+    let all = settlements(&plan, 5);
+    let distance = |a: Settlement, b: Settlement| (b.center - a.center).as_::<f64>().magnitude();
+    for &a in settlements(&plan, 2).iter() {
+        let roads = plan.roads_in(a.center, a.center);
+        // Дороги начинаются и заканчиваются в поселениях
+        for road in roads.iter() {
+            assert!(all.iter().any(|s| s.center == road.from));
+            assert!(all.iter().any(|s| s.center == road.to));
+        }
+        let nearest = all
+            .iter()
+            .copied()
+            .filter(|&b| b != a)
+            .min_by(|&b, &c| distance(a, b).total_cmp(&distance(a, c)))
+            .unwrap();
+        if distance(a, nearest) <= MAX_ROAD as f64 {
+            assert!(
+                roads.iter().any(|road| {
+                    [road.from, road.to] == [a.center, nearest.center]
+                        || [road.to, road.from] == [a.center, nearest.center]
+                }),
+                "Поселение {a:?} не связано с ближайшим {nearest:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn roads_do_not_depend_on_query_area() {
+    let plan = WorldPlan::new(SEED);
+    let (min, max) = (Vec2::new(-512, -512), Vec2::new(511, 511));
+    let all = plan.roads_in(min, max);
+    assert!(!all.is_empty());
+    // Каждый чанк видит ровно те дороги большого квадрата, которые его задевают
+    for y in (min.y..max.y).step_by(256) {
+        for x in (min.x..max.x).step_by(256) {
+            let (from, to) = (Vec2::new(x, y), Vec2::new(x + 63, y + 63));
+            let expected = all
+                .iter()
+                .filter(|road| road.intersects(from, to))
+                .cloned()
+                .collect();
+            assert_eq!(sorted(plan.roads_in(from, to)), sorted(expected));
+        }
+    }
+}
+
+#[test]
+fn road_continues_across_chunks() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let plan = WorldPlan::new(SEED);
+        let roads = plan.roads_in(Vec2::new(-1024, -1024), Vec2::new(1023, 1023));
+        // Место, где ось дороги пересекает границу чанков по x
+        let (x, y) = roads
+            .iter()
+            .flat_map(|road| {
+                (-1024..1024)
+                    .flat_map(|y| (-16..16).map(move |ch_x| (ch_x * 64 + 31, y)))
+                    .filter(move |&(x, y)| road.distance(x, y) < 0.5 && road.contains(x + 1, y))
+            })
+            .next()
+            .expect("Дорога не пересекает границы чанков");
+        assert_ne!(chunk_coord(x), chunk_coord(x + 1));
+
+        // Соседние чанки генерируются отдельно, сначала тот, что дальше
+        for x in [x + 1, x] {
+            let z = NoiseGenerator.ground_level(SEED, x, y).unwrap() - 1;
+            let chunk = NoiseGenerator.generate(
+                SEED,
+                &tiles,
+                chunk_coord(x),
+                chunk_coord(y),
+                chunk_coord(z),
+            );
+            assert_eq!(&*tiles.get(chunk.get_tile(x, y, z)).name, "road");
+            assert!(!chunk.get_obstacle(x, y, z + 1) || local_coord(z) == 63);
+        }
+    });
+}
@@ -0,0 +1,251 @@
+#![cfg(test)]
+
+use std::{path::Path, sync::Arc};
+
+use hecs::World;
+use vek::Vec3;
+
+use crate::{
+    biome::BiomeMap,
+    components::Position,
+    coords::chunk_of,
+    map::{Map, WorldMap},
+    prefabs::{PrefabError, PrefabPlacement, PrefabRegistry},
+    region::RegionStore,
+    worldgen::{ChunkGenerator, FlatGenerator, PrefabGenerator},
+};
+
+use super::{resources::test_resources, save::TempDir, tiles::test_tiles, with_big_stack};
+
+const SEED: u64 = 77;
+
+/// Буква Г из стен: по ней видно, куда повёрнут префаб
+const CORNER: &str = "
+corner:
+  rotate: true
+  legend:
+    \"#\": wall
+    \".\": empty
+  variants:
+  - layers:
+    - |-
+      ###
+      #._
+    spawns:
+    - template: nettle
+      at: [1, 1, 0]
+";
+
+fn parse(source: &str) -> Result<PrefabRegistry, PrefabError> {
+    let resources = test_resources();
+    PrefabRegistry::parse(
+        Path::new("prefabs.yaml"),
+        source,
+        &resources.tiles,
+        &resources.entity_templates,
+    )
+}
+
+/// Генератор, который ставит префаб в каждую ячейку сетки на плоскую землю
+fn corner_generator() -> PrefabGenerator {
+    let mut prefabs = parse(CORNER).unwrap();
+    prefabs.cell_chance = 1.;
+    PrefabGenerator {
+        base: Arc::new(FlatGenerator { ground_level: 0 }),
+        prefabs: Arc::new(prefabs),
+    }
+}
+
+#[test]
+fn prefabs_load() {
+    let resources = test_resources();
+    let names = resources
+        .prefabs
+        .prefabs
+        .iter()
+        .map(|prefab| &*prefab.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["ruined_house", "bunker", "car_wreck"]);
+
+    let corner = parse(CORNER).unwrap();
+    let variant = &corner.prefabs[0].variants[0];
+    assert_eq!(variant.size, Vec3::new(3, 2, 1));
+    let wall = resources.tiles.id("wall");
+    let empty = resources.tiles.id("empty");
+    assert_eq!(variant.tiles, [wall, wall, wall, wall, empty, None]);
+    assert_eq!(&*variant.spawns[0].template, "nettle");
+}
+
+#[test]
+fn prefab_errors() {
+    let prefab = |legend: &str, layers: &str, spawns: &str| {
+        parse(&format!(
+            "box:\n  legend:\n{legend}\n  variants:\n  - layers:\n{layers}\n    spawns:\n{spawns}\n"
+        ))
+    };
+    let legend = "    \"#\": wall";
+    let layers = "    - \"##\\n##\"";
+    let spawn = |at: &str| format!("    - template: nettle\n      at: {at}");
+    assert!(prefab(legend, layers, &spawn("[1, 1, 0]")).is_ok());
+
+    assert!(matches!(
+        prefab("    \"#\": unobtainium", layers, ""),
+        Err(PrefabError::UnknownTile { tile, .. }) if tile == "unobtainium"
+    ));
+    assert!(matches!(
+        prefab(legend, "    - \"#%\\n##\"", ""),
+        Err(PrefabError::UnknownSymbol { symbol: '%', .. })
+    ));
+    assert!(matches!(
+        prefab(legend, "    - \"##\\n##\"\n    - \"##\"", ""),
+        Err(PrefabError::Layers { variant: 0, .. })
+    ));
+    assert!(matches!(
+        prefab(legend, &format!("    - \"{}\"", "#".repeat(49)), ""),
+        Err(PrefabError::Layers { .. })
+    ));
+    assert!(matches!(
+        prefab(legend, layers, &spawn("[2, 0, 0]")),
+        Err(PrefabError::SpawnOutside { at: (2, 0, 0), .. })
+    ));
+    assert!(matches!(
+        prefab(legend, layers, "    - template: dragon\n      at: [0, 0, 0]"),
+        Err(PrefabError::UnknownTemplate { template, .. }) if template == "dragon"
+    ));
+    assert!(matches!(
+        parse("box:\n  legend: {}\n  variants: []\n"),
+        Err(PrefabError::NoVariants { .. })
+    ));
+    assert!(matches!(
+        parse("box:\n  legend: {}\n  variants: []\n  color: red\n"),
+        Err(PrefabError::Prefab { .. })
+    ));
+}
+
+#[test]
+fn prefab_rotation() {
+    let prefabs = parse(CORNER).unwrap();
+    let wall = prefabs.prefabs[0].variants[0].tiles[0].unwrap();
+    let place = |rotation| {
+        let size = Vec3::new(3, 2, 1);
+        let placement = PrefabPlacement {
+            prefab: 0,
+            variant: 0,
+            rotation,
+            origin: Vec3::new(10, 20, 0),
+            size: if rotation % 2 == 1 {
+                Vec3::new(2, 3, 1)
+            } else {
+                size
+            },
+        };
+        let mut walls = prefabs
+            .placed_tiles(&placement)
+            .filter(|(_, tile)| *tile == wall)
+            .map(|(pos, _)| (pos.x - 10, pos.y - 20))
+            .collect::<Vec<_>>();
+        walls.sort();
+        let spawn = prefabs.placed_spawns(&placement).next().unwrap().position;
+        (walls, (spawn.x - 10, spawn.y - 20))
+    };
+    // ###    ##    _.#    #_
+    // #._    .#    ###    #.
+    //        _#           ##
+    assert_eq!(place(0), (vec![(0, 0), (0, 1), (1, 0), (2, 0)], (1, 1)));
+    assert_eq!(place(1), (vec![(0, 0), (1, 0), (1, 1), (1, 2)], (0, 1)));
+    assert_eq!(place(2), (vec![(0, 1), (1, 1), (2, 0), (2, 1)], (1, 0)));
+    assert_eq!(place(3), (vec![(0, 0), (0, 1), (0, 2), (1, 2)], (1, 1)));
+}
+
+#[test]
+fn prefabs_cross_chunk_boundaries() {
+    with_big_stack(|| {
+        let generator = corner_generator();
+        let tiles = test_tiles();
+        let biomes = BiomeMap::new(SEED);
+        let placements = (0..20)
+            .flat_map(|cell_y| (0..20).map(move |cell_x| (cell_x, cell_y)))
+            .filter_map(|(cell_x, cell_y)| {
+                generator
+                    .prefabs
+                    .placement(SEED, &biomes, None, cell_x, cell_y, |x, y| {
+                        generator.ground_level(SEED, x, y)
+                    })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(placements.len(), 400);
+        assert!(placements.iter().any(|placement| placement.rotation != 0));
+
+        // Префаб на границе чанков: каждый из чанков получает свою часть
+        let placement = placements
+            .iter()
+            .find(|placement| {
+                let end = placement.origin + placement.size - 1;
+                chunk_of(placement.origin.x, placement.origin.y, 0) != chunk_of(end.x, end.y, 0)
+            })
+            .unwrap();
+        let end = placement.origin + placement.size - 1;
+        let (first, last) = (
+            chunk_of(placement.origin.x, placement.origin.y, 0),
+            chunk_of(end.x, end.y, 0),
+        );
+        for ch_y in first.1..=last.1 {
+            for ch_x in first.0..=last.0 {
+                assert!(generator
+                    .placements(SEED, ch_x, ch_y, 0)
+                    .contains(placement));
+                let chunk = generator.generate(SEED, &tiles, ch_x, ch_y, 0);
+                assert!(chunk == generator.generate(SEED, &tiles, ch_x, ch_y, 0));
+                for (pos, tile) in generator.prefabs.placed_tiles(placement) {
+                    if chunk_of(pos.x, pos.y, pos.z) == (ch_x, ch_y, 0) {
+                        assert_eq!(chunk.get_tile(pos.x, pos.y, pos.z), tile);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn prefab_entities_spawn_once() {
+    with_big_stack(|| {
+        let dir = TempDir::new("prefab-spawns");
+        let resources = test_resources();
+        let mut map = WorldMap::new(SEED, Arc::new(corner_generator()), resources.tiles.clone());
+        map.store = Some(RegionStore::new(dir.0.join("chunks")));
+        map.get_chunk_or_create(0, 0, 0);
+        assert!(!map.spawns.is_empty());
+        assert!(map.dirty.contains(&(0, 0, 0)));
+        let spawned = map.spawns.clone();
+        // Под землёй префабов нет, и такой чанк незачем хранить
+        map.get_chunk_or_create(0, 0, -1);
+        assert!(!map.dirty.contains(&(0, 0, -1)));
+
+        let mut world = World::new();
+        world.spawn((map,));
+        resources.spawn_prefab_entities(&mut world).unwrap();
+        let mut positions = world
+            .query_mut::<&Position>()
+            .into_iter()
+            .map(|(_, Position(pos))| *pos)
+            .collect::<Vec<_>>();
+        positions.sort_by_key(|pos| (pos.x, pos.y));
+        let mut expected = spawned
+            .iter()
+            .map(|spawn| spawn.position)
+            .collect::<Vec<_>>();
+        expected.sort_by_key(|pos| (pos.x, pos.y));
+        assert_eq!(positions, expected);
+
+        // Выгруженный чанк загружается из хранилища, и сущности не появляются снова
+        let (_, (map,)) = world
+            .query_mut::<(&mut WorldMap,)>()
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(map.spawns.is_empty());
+        map.unload_chunks(|_| false).unwrap();
+        map.get_chunk_or_create(0, 0, 0);
+        assert!(map.spawns.is_empty());
+    });
+}
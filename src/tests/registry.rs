@@ -0,0 +1,62 @@
+#![cfg(test)]
+
+use std::{collections::BTreeMap, path::Path};
+
+use hecs::{EntityBuilder, World};
+
+use crate::{
+    items::Item,
+    player::new_player,
+    registry::{ComponentRegistry, TemplateContext},
+    resources::Resources,
+    Property,
+};
+
+#[test]
+fn components_round_trip() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let body_templates = Resources::load_body_templates(&data_path).unwrap();
+    let context = TemplateContext {
+        body_templates: &body_templates,
+    };
+    let registry = ComponentRegistry::with_game_components();
+
+    let mut world = World::new();
+    let mut player = new_player();
+    player.add(body_templates.get("human").unwrap().clone());
+    let player = world.spawn(player.build());
+    let mut item = Item::new("thing".into(), "item".into());
+    item.add_props(&[("huy".into(), Property::Int(3))]);
+    let item = world.spawn(item.to_map_entity(2, 2, 0));
+
+    for entity in [player, item] {
+        let saved = registry.save(world.entity(entity).unwrap()).unwrap();
+        let mut eb = EntityBuilder::new();
+        for component in saved.clone() {
+            registry.load(component, &context, &mut eb).unwrap();
+        }
+        let loaded = world.spawn(eb.build());
+        let resaved = registry.save(world.entity(loaded).unwrap()).unwrap();
+        assert_eq!(saved, resaved);
+    }
+}
+
+#[test]
+fn unknown_component() {
+    let registry = ComponentRegistry::with_game_components();
+    let body_templates = BTreeMap::new();
+    let context = TemplateContext {
+        body_templates: &body_templates,
+    };
+    let mut eb = EntityBuilder::new();
+    let err = registry.load("mbo".into(), &context, &mut eb).unwrap_err();
+    assert!(err.starts_with("unknown component mbo, expected one of "));
+    let err = registry
+        .load(
+            serde_yaml::from_str("sight: far").unwrap(),
+            &context,
+            &mut eb,
+        )
+        .unwrap_err();
+    assert!(err.starts_with("sight: "), "{err}");
+}
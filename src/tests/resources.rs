@@ -0,0 +1,264 @@
+#![cfg(test)]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
+
+use hecs::World;
+use rand::{rngs::StdRng, SeedableRng};
+use serde_yaml::Value;
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    hasher,
+    mob::Mob,
+    registry::{ComponentRegistry, TemplateContext},
+    resources::{
+        check_data, parse_body_templates, parse_templates, Assets, BodyTemplateError,
+        EntityTemplate, Resources, SpawnError, TemplateError, TemplateErrors,
+    },
+    systems::{fov_compute::Sight, health::Body},
+};
+
+#[test]
+fn body_templates_load() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let templates = Resources::load_body_templates(&data_path).unwrap();
+    let human = templates.get("human").unwrap();
+    let head = human.part("head").and_then(|p| p.part("head")).unwrap();
+    assert!(head.has_organ("brain"));
+    assert!(head.has_bone_group("skull"));
+    // Части, подключенные через `<<: *arm`, должны получить всё содержимое якоря
+    for arm in ["left_arm", "right_arm"] {
+        let hand = human.part(arm).and_then(|p| p.part("hand")).unwrap();
+        assert!(hand.has_property("can_hold"));
+        assert!(hand.has_bone_group("hand"));
+    }
+    assert!(human
+        .part("right_leg")
+        .and_then(|p| p.part("foot"))
+        .is_some());
+    assert!(human.part("arm").is_none());
+}
+
+#[test]
+fn body_templates_malformed() {
+    let value: Value = serde_yaml::from_str(
+        "
+human:
+  head:
+    head:
+      organs: brain
+",
+    )
+    .unwrap();
+    let Err(err) = parse_body_templates(&value) else {
+        panic!("Malformed body template was accepted");
+    };
+    assert!(matches!(err, BodyTemplateError::Malformed { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Body template human: human.head.head.organs must be a list of names"
+    );
+
+    let value: Value = serde_yaml::from_str(
+        "
+human:
+  head:
+    head:
+      eyes: []
+",
+    )
+    .unwrap();
+    let Err(err) = parse_body_templates(&value) else {
+        panic!("Unknown body part part key was accepted");
+    };
+    assert_eq!(
+        err.to_string(),
+        "Body template human: human.head.head.eyes must be one of bone_groups, organs, properties"
+    );
+}
+
+#[test]
+fn data_directory_is_valid() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let errors = check_data(&data_path);
+    assert!(errors.is_empty(), "{errors:?}");
+}
+
+#[test]
+fn template_errors_collected() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let body_templates = Resources::load_body_templates(&data_path).unwrap();
+    let source = "
+nettle:
+- position: 10x10
+- mob
+- sigth: 40
+rat:
+- body: elf
+";
+    let context = TemplateContext {
+        body_templates: &body_templates,
+    };
+    let registry = ComponentRegistry::with_game_components();
+    let Err(errors) = parse_templates(Path::new("templates.yaml"), source, &registry, &context)
+    else {
+        panic!("Malformed templates were accepted");
+    };
+    let errors: Vec<_> = errors
+        .0
+        .iter()
+        .map(|err| match err {
+            TemplateError::Component {
+                template, index, ..
+            } => (template.as_str(), *index),
+            _ => panic!("Unexpected error {err}"),
+        })
+        .collect();
+    assert_eq!(errors, [("nettle", 0), ("nettle", 2), ("rat", 0)]);
+}
+
+fn parse_test_templates(
+    source: &str,
+) -> Result<BTreeMap<Arc<str>, EntityTemplate>, TemplateErrors> {
+    let body_templates = BTreeMap::new();
+    let context = TemplateContext {
+        body_templates: &body_templates,
+    };
+    let registry = ComponentRegistry::with_game_components();
+    parse_templates(Path::new("templates.yaml"), source, &registry, &context)
+}
+
+#[test]
+fn templates_extend() {
+    let source = "
+old_nettle:
+- extends: nettle
+- sight: 5
+- position: 12x10x0
+nettle:
+- position: 10x10x0
+- mob
+grown_nettle:
+- extends: old_nettle
+- sight: 10
+";
+    let Ok(templates) = parse_test_templates(source) else {
+        panic!("Templates with extends were rejected");
+    };
+    let names = |name: &str| {
+        templates[name]
+            .components
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names("nettle"), ["position", "mob"]);
+    assert_eq!(names("old_nettle"), ["position", "mob", "sight"]);
+    assert_eq!(names("grown_nettle"), ["position", "mob", "sight"]);
+    let old_nettle = &templates["old_nettle"];
+    assert_eq!(old_nettle.get("position"), Some(&Value::from("12x10x0")));
+    assert_eq!(old_nettle.get("sight"), Some(&Value::from(5)));
+    let grown_nettle = &templates["grown_nettle"];
+    assert_eq!(grown_nettle.get("position"), Some(&Value::from("12x10x0")));
+    assert_eq!(grown_nettle.get("sight"), Some(&Value::from(10)));
+}
+
+#[test]
+fn templates_extend_errors() {
+    let source = "
+a:
+- extends: b
+b:
+- extends: c
+c:
+- extends: a
+child:
+- extends: a
+orphan:
+- extends: nothing
+orphan_child:
+- extends: orphan
+broken: 5
+broken_child:
+- extends: broken
+";
+    let Err(errors) = parse_test_templates(source) else {
+        panic!("Broken inheritance was accepted");
+    };
+    let errors: Vec<_> = errors.0.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        errors,
+        [
+            "templates.yaml: template broken must be a list of components",
+            "templates.yaml: templates extend each other in a cycle: a -> b -> c -> a",
+            "templates.yaml: template orphan extends unknown template nothing",
+        ]
+    );
+}
+
+/// Ресурсы из каталога data без текстур
+pub fn test_resources() -> Resources {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let components = ComponentRegistry::with_game_components();
+    let body_templates = Resources::load_body_templates(&data_path).unwrap();
+    let context = TemplateContext {
+        body_templates: &body_templates,
+    };
+    let entity_templates = Resources::load_templates(&data_path, &components, &context).unwrap();
+    let tiles = Resources::load_tiles(&data_path).unwrap();
+    let prefabs = Resources::load_prefabs(&data_path, &tiles, &entity_templates).unwrap();
+    Resources {
+        assets: Assets {
+            sprites: HashMap::with_hasher(hasher()),
+        },
+        components,
+        body_templates,
+        entity_templates,
+        tiles: Arc::new(tiles),
+        prefabs: Arc::new(prefabs),
+    }
+}
+
+#[test]
+fn spawn_template() {
+    let resources = test_resources();
+    let mut world = World::new();
+    let first = Position(Vec3::new(1, 2, 3));
+    let second = Position(Vec3::new(-4, 5, 0));
+    let a = resources.spawn(&mut world, "nettle", first).unwrap();
+    let b = resources.spawn(&mut world, "nettle", second).unwrap();
+    assert_ne!(a, b);
+    assert_eq!(*world.get::<&Position>(a).unwrap(), first);
+    assert_eq!(*world.get::<&Position>(b).unwrap(), second);
+    assert!(world.get::<&Mob>(b).is_ok());
+    assert!(world.get::<&Body>(b).is_ok());
+    assert!(matches!(
+        resources.spawn(&mut world, "nettel", first),
+        Err(SpawnError::UnknownTemplate(name)) if name == "nettel"
+    ));
+}
+
+#[test]
+fn spawn_in_region() {
+    let resources = test_resources();
+    let mut world = World::new();
+    let mut rng = StdRng::seed_from_u64(0);
+    let from = Position(Vec3::new(5, -3, 0));
+    let to = Position(Vec3::new(-5, 3, 1));
+    let entities = resources
+        .spawn_in_region(&mut world, "old_nettle", 50, from, to, &mut rng)
+        .unwrap();
+    assert_eq!(entities.len(), 50);
+    for entity in entities {
+        let position = world.get::<&Position>(entity).unwrap().0;
+        assert!((-5..=5).contains(&position.x));
+        assert!((-3..=3).contains(&position.y));
+        assert!((0..=1).contains(&position.z));
+        assert_eq!(world.get::<&Sight>(entity).unwrap().0, 5);
+    }
+}
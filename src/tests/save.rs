@@ -0,0 +1,387 @@
+#![cfg(test)]
+
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use hecs::World;
+use rand::{rngs::StdRng, SeedableRng};
+use serde_yaml::Value;
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    items::Item,
+    map::{Map, WorldMap},
+    mob::{Inventory, Log},
+    player::{is_player_dead, new_player, Player, PLAYER_LANTERN},
+    registry::ComponentRegistry,
+    save::{load_world, save_world, GameMode, SaveError, SaveSlot, SAVE_VERSION},
+    systems::health::{
+        attack_with_rng, Body, BodyPart, BodyPartPart, Dead, Organ, WantsAttack, Wound,
+    },
+    systems::lighting::LightSource,
+    worldgen::ClassicGenerator,
+    Property,
+};
+
+use super::{resources::test_resources, with_big_stack};
+
+/// Временный каталог для сохранения, удаляемый в конце теста
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("game123-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Проверяет, что в мирах одни и те же сущности, независимо от порядка их хранения.
+/// Описания сравниваются как значения YAML, в которых порядок ключей не важен.
+fn assert_entities_eq(a: &World, b: &World, registry: &ComponentRegistry) {
+    let saved = |world: &World| -> Vec<Vec<Value>> {
+        world
+            .iter()
+            .map(|entity| registry.save(entity).unwrap())
+            .filter(|components| !components.is_empty())
+            .collect()
+    };
+    let a = saved(a);
+    let mut b = saved(b);
+    assert_eq!(a.len(), b.len());
+    for entity in a {
+        let index = b
+            .iter()
+            .position(|other| *other == entity)
+            .unwrap_or_else(|| panic!("Сущность не сохранилась: {entity:?}"));
+        b.swap_remove(index);
+    }
+}
+
+fn assert_maps_eq(a: &World, b: &mut World) {
+    let mut a = a.query::<(&WorldMap,)>();
+    let (_, (a,)) = a.iter().next().unwrap();
+    let (_, (b,)) = b
+        .query_mut::<(&mut WorldMap,)>()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert_eq!(a.seed, b.seed);
+    // Сохраняются только изменённые чанки, остальные генерируются заново,
+    // а загруженная карта берёт чанки из хранилища, когда они понадобятся
+    let mut stored = b.store.as_ref().unwrap().load_all().unwrap();
+    stored.sort_by_key(|(position, _)| *position);
+    let mut dirty = a.dirty.iter().copied().collect::<Vec<_>>();
+    dirty.sort();
+    assert_eq!(
+        stored
+            .into_iter()
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>(),
+        dirty
+    );
+    for ((x, y, z), chunk) in a.chunks.iter() {
+        let other = b.get_chunk_or_create(*x, *y, *z).lock().unwrap();
+        assert!(*chunk.lock().unwrap() == *other, "Чанк не сохранился");
+    }
+}
+
+#[test]
+fn save_round_trip() {
+    with_big_stack(save_round_trip_impl);
+}
+
+fn save_round_trip_impl() {
+    let resources = test_resources();
+    let mut world = World::new();
+    let mut map = WorldMap::new(12345, Arc::new(ClassicGenerator), resources.tiles.clone());
+    for position in [(0, 0, 0), (-1, 0, 0), (0, 2, -1), (1, 1, 1)] {
+        map.get_chunk_or_create(position.0, position.1, position.2);
+    }
+    let wall = resources.tiles.id("wall").unwrap();
+    map.set_tile(Vec3::new(0, 0, 20), wall);
+    map.set_tile(Vec3::new(-40, 5, 3), wall);
+    world.spawn((map,));
+    let mut player = new_player();
+    player.add(resources.body_templates["human"].clone());
+    let player = world.spawn(player.build());
+    let mut item = Item::new("thing".into(), "item".into());
+    item.add_props(&[("weight".into(), Property::Float(1.5))]);
+    {
+        let mut query = world.query_one_mut::<(&mut Inventory, &mut Log)>(player);
+        let (inventory, log) = query.as_mut().unwrap();
+        inventory.0.push(item.clone());
+        log.write("Picked up thing");
+    }
+    world.spawn(item.to_map_entity(2, 2, 0));
+    resources
+        .spawn(&mut world, "nettle", Position(Vec3::new(10, 10, 0)))
+        .unwrap();
+    resources
+        .spawn(&mut world, "old_nettle", Position(Vec3::new(-3, 7, 0)))
+        .unwrap();
+
+    let dir = TempDir::new("save-round-trip");
+    save_world(&dir.0, &world, 42, GameMode::Normal, &resources.components).unwrap();
+    let (mut loaded, meta) = load_world(
+        &dir.0,
+        &resources.components,
+        &resources.template_context(),
+        &resources.tiles,
+        &dir.0.join("cache"),
+    )
+    .unwrap();
+
+    assert_eq!(meta.turn, 42);
+    assert_maps_eq(&world, &mut loaded);
+    assert_entities_eq(&world, &loaded, &resources.components);
+    let mut players = loaded.query::<(&Player, &Inventory)>();
+    let (_, (_, inventory)) = players.iter().next().unwrap();
+    assert_eq!(inventory.0.len(), 1);
+}
+
+#[test]
+fn save_version_checked() {
+    with_big_stack(save_version_checked_impl);
+}
+
+fn save_version_checked_impl() {
+    let resources = test_resources();
+    let mut world = World::new();
+    world.spawn((WorldMap::new(
+        0,
+        Arc::new(ClassicGenerator),
+        resources.tiles.clone(),
+    ),));
+    let dir = TempDir::new("save-version");
+    save_world(&dir.0, &world, 0, GameMode::Normal, &resources.components).unwrap();
+    // В версии 1 не было режима игры
+    fs::write(dir.0.join("meta.yaml"), "version: 1\nturn: 7\n").unwrap();
+    let (_, meta) = load_world(
+        &dir.0,
+        &resources.components,
+        &resources.template_context(),
+        &resources.tiles,
+        &dir.0.join("cache"),
+    )
+    .unwrap();
+    assert_eq!(meta.turn, 7);
+    assert_eq!(meta.mode, GameMode::Normal);
+    fs::write(dir.0.join("meta.yaml"), "version: 1000\nturn: 0\n").unwrap();
+    let result = load_world(
+        &dir.0,
+        &resources.components,
+        &resources.template_context(),
+        &resources.tiles,
+        &dir.0.join("cache"),
+    );
+    assert!(matches!(
+        result,
+        Err(SaveError::UnsupportedVersion { found: 1000 })
+    ));
+}
+
+#[test]
+fn old_saves_get_lantern() {
+    with_big_stack(old_saves_get_lantern_impl);
+}
+
+fn old_saves_get_lantern_impl() {
+    let resources = test_resources();
+    let mut world = World::new();
+    world.spawn((WorldMap::new(
+        0,
+        Arc::new(ClassicGenerator),
+        resources.tiles.clone(),
+    ),));
+    let player = world.spawn(new_player().build());
+    world.remove_one::<LightSource>(player).unwrap();
+    let dir = TempDir::new("old-lantern");
+    save_world(&dir.0, &world, 3, GameMode::Normal, &resources.components).unwrap();
+    let load = || {
+        let (mut world, _) = load_world(
+            &dir.0,
+            &resources.components,
+            &resources.template_context(),
+            &resources.tiles,
+            &dir.0.join("cache"),
+        )
+        .unwrap();
+        let (_, (_, light)) = world
+            .query_mut::<(&Player, Option<&LightSource>)>()
+            .into_iter()
+            .next()
+            .unwrap();
+        light.map(|LightSource(light)| *light)
+    };
+    // Персонаж, который сам лишился фонаря, остаётся без него
+    assert_eq!(load(), None);
+    // В сохранениях до освещения фонарь появляется при загрузке
+    let meta = fs::read_to_string(dir.0.join("meta.yaml")).unwrap();
+    let old_meta = meta.replace(&format!("version: {SAVE_VERSION}"), "version: 6");
+    assert_ne!(meta, old_meta);
+    fs::write(dir.0.join("meta.yaml"), old_meta).unwrap();
+    assert_eq!(load(), Some(PLAYER_LANTERN));
+}
+
+#[test]
+fn responsible_mode() {
+    with_big_stack(responsible_mode_impl);
+}
+
+fn responsible_mode_impl() {
+    let resources = test_resources();
+    let context = resources.template_context();
+    let mut world = World::new();
+    world.spawn((WorldMap::new(
+        0,
+        Arc::new(ClassicGenerator),
+        resources.tiles.clone(),
+    ),));
+    world.spawn(new_player().build());
+    let dir = TempDir::new("responsible-mode");
+    let path = dir.0.join("save");
+    let slot = SaveSlot::new(path.clone(), GameMode::Responsible);
+
+    assert!(matches!(
+        slot.save(&world, 1, &resources.components),
+        Err(SaveError::SavingDisabled)
+    ));
+    assert!(!slot.exists());
+    slot.save_on_exit(&world, 1, &resources.components).unwrap();
+    assert!(slot.exists());
+
+    // Загруженное и не закрытое сохранение нельзя загрузить ещё раз
+    let (slot, mut world, turn) = SaveSlot::load(
+        path.clone(),
+        &resources.components,
+        &context,
+        &resources.tiles,
+    )
+    .unwrap();
+    assert_eq!(slot.mode, GameMode::Responsible);
+    assert_eq!(turn, 1);
+    assert!(matches!(
+        SaveSlot::load(
+            path.clone(),
+            &resources.components,
+            &context,
+            &resources.tiles
+        ),
+        Err(SaveError::NotClosed { .. })
+    ));
+    slot.save_on_exit(&world, 2, &resources.components).unwrap();
+    let (slot, _, turn) = SaveSlot::load(
+        path.clone(),
+        &resources.components,
+        &context,
+        &resources.tiles,
+    )
+    .unwrap();
+    assert_eq!(turn, 2);
+
+    // После смерти сохранение удаляется, а при выходе не создаётся заново
+    assert!(!is_player_dead(&world));
+    let (player, _) = world.query_mut::<(&Player,)>().into_iter().next().unwrap();
+    world.insert_one(player, Dead).unwrap();
+    assert!(is_player_dead(&world));
+    slot.on_player_death().unwrap();
+    assert!(!slot.exists());
+    slot.save_on_exit(&world, 3, &resources.components).unwrap();
+    assert!(!slot.exists());
+}
+
+#[test]
+fn normal_mode() {
+    with_big_stack(normal_mode_impl);
+}
+
+fn normal_mode_impl() {
+    let resources = test_resources();
+    let context = resources.template_context();
+    let mut world = World::new();
+    world.spawn((WorldMap::new(
+        0,
+        Arc::new(ClassicGenerator),
+        resources.tiles.clone(),
+    ),));
+    world.spawn((Player, Dead));
+    let dir = TempDir::new("normal-mode");
+    let slot = SaveSlot::new(dir.0.clone(), GameMode::Normal);
+    slot.save(&world, 5, &resources.components).unwrap();
+    // Обычное сохранение можно загружать сколько угодно раз, и смерть его не удаляет
+    SaveSlot::load(
+        dir.0.clone(),
+        &resources.components,
+        &context,
+        &resources.tiles,
+    )
+    .unwrap();
+    SaveSlot::load(
+        dir.0.clone(),
+        &resources.components,
+        &context,
+        &resources.tiles,
+    )
+    .unwrap();
+    slot.on_player_death().unwrap();
+    assert!(slot.exists());
+}
+
+#[test]
+fn killed_player_loses_save() {
+    with_big_stack(killed_player_loses_save_impl);
+}
+
+fn killed_player_loses_save_impl() {
+    let resources = test_resources();
+    let mut world = World::new();
+    world.spawn((WorldMap::new(
+        0,
+        Arc::new(ClassicGenerator),
+        resources.tiles.clone(),
+    ),));
+    // Голова без костей, в которую каждый удар попадает с шансом на мозг
+    let head = ["brain", "left_eye", "right_eye"]
+        .into_iter()
+        .fold(BodyPartPart::new(), |part, organ| {
+            part.with_organ(organ.to_owned(), Organ::new())
+        });
+    let mut player = new_player();
+    player.add(Body::new().with_part(
+        "head".into(),
+        BodyPart::new().with_part("head".into(), head),
+    ));
+    let player = world.spawn(player.build());
+    let attacker = world.spawn((Log::default(),));
+    let dir = TempDir::new("killed-player");
+    let slot = SaveSlot::new(dir.0.join("save"), GameMode::Responsible);
+    slot.save_on_exit(&world, 1, &resources.components).unwrap();
+    assert!(slot.exists());
+
+    // С одним и тем же зерном удары всегда попадают в одни и те же органы
+    let mut rng = StdRng::seed_from_u64(7);
+    for _ in 0..100 {
+        if is_player_dead(&world) {
+            break;
+        }
+        world
+            .insert_one(attacker, WantsAttack(Wound::Stabbed, player))
+            .unwrap();
+        attack_with_rng(&mut world, &mut rng).unwrap();
+    }
+    assert!(
+        is_player_dead(&world),
+        "Персонаж пережил сотню ударов в голову"
+    );
+    // Так же, как в конце хода игры
+    slot.on_player_death().unwrap();
+    assert!(!slot.exists());
+}
@@ -0,0 +1,91 @@
+#![cfg(test)]
+
+use crate::{
+    map::CHUNK_SIZE,
+    storage::{ChunkBits, TileStorage, CHUNK_TILES},
+    systems::memory::MemoryChunk,
+    tiles::TileId,
+    worldgen::{ChunkGenerator, EmptyGenerator, FlatGenerator, NoiseGenerator},
+};
+
+use super::tiles::test_tiles;
+
+#[test]
+fn tile_storage_palette() {
+    let uniform = TileStorage::from_tiles(std::iter::repeat(TileId(3)).take(CHUNK_TILES));
+    assert!(matches!(uniform, TileStorage::Uniform(TileId(3))));
+    assert_eq!(uniform.heap_size(), 0);
+
+    // Номер тайла зависит от позиции, чтобы проверить все ширины упаковки
+    for kinds in [2, 3, 5, 17, 300] {
+        let tile_at = |idx: usize| TileId((idx * 7 % kinds) as u16 + 1);
+        let storage = TileStorage::from_tiles((0..CHUNK_TILES).map(tile_at));
+        assert!(matches!(storage, TileStorage::Paletted { .. }));
+        for idx in (0..CHUNK_TILES).step_by(97).chain([CHUNK_TILES - 1]) {
+            assert_eq!(storage.get(idx), tile_at(idx), "{kinds} тайлов, тайл {idx}");
+        }
+        assert!(storage.iter().eq((0..CHUNK_TILES).map(tile_at)));
+    }
+}
+
+#[test]
+fn tile_storage_set() {
+    let mut storage = TileStorage::Uniform(TileId(0));
+    storage.set(10, TileId(0));
+    assert!(matches!(storage, TileStorage::Uniform(_)));
+    // Палитра растёт, и номера перепаковываются, не теряя уже записанных тайлов
+    for n in 1..20u16 {
+        storage.set(n as usize * 1000, TileId(n));
+    }
+    for n in 1..20u16 {
+        assert_eq!(storage.get(n as usize * 1000), TileId(n));
+    }
+    assert_eq!(storage.get(1), TileId(0));
+    assert_eq!(storage.get(CHUNK_TILES - 1), TileId(0));
+
+    let same = TileStorage::from_tiles(storage.iter());
+    assert!(same == storage);
+    let mut other = same.clone();
+    other.set(5, TileId(7));
+    assert!(other != storage);
+}
+
+#[test]
+fn chunk_bits() {
+    let mut bits = ChunkBits::Uniform(false);
+    assert!(!bits.get(123));
+    bits.set(123, true);
+    bits.set(CHUNK_TILES - 1, true);
+    assert!(bits.get(123) && bits.get(CHUNK_TILES - 1) && !bits.get(124));
+    assert_eq!(bits.heap_size(), CHUNK_TILES / 8);
+    assert_eq!(bits.runs(), [123, 1, CHUNK_TILES as u32 - 125, 1]);
+    assert!(ChunkBits::from_runs(&bits.runs()).unwrap() == bits);
+    assert!(ChunkBits::from_runs(&[1, 2]).is_err());
+
+    let mut full = ChunkBits::Uniform(true);
+    full.set(0, false);
+    assert!(!full.get(0) && full.get(1));
+    assert!(matches!(
+        ChunkBits::from_bits(std::iter::repeat(true).take(CHUNK_TILES)),
+        ChunkBits::Uniform(true)
+    ));
+}
+
+#[test]
+fn chunks_are_compact() {
+    let tiles = test_tiles();
+    let air = EmptyGenerator.generate(0, &tiles, 0, 0, 0);
+    let ground = FlatGenerator { ground_level: 0 }.generate(0, &tiles, 0, 0, -1);
+    assert!(air.memory_size() < 1024);
+    assert!(ground.memory_size() < 1024);
+    assert!(ground.get_obstacle(5, 5, -40) && !air.get_obstacle(5, 5, 5));
+
+    let terrain = NoiseGenerator.generate(1, &tiles, 0, 0, 0);
+    assert!(matches!(terrain.tiles, TileStorage::Paletted { .. }));
+    // Не больше 16 типов тайлов: по 4 бита на тайл и бит на препятствие
+    assert!(terrain.memory_size() <= 1024 + CHUNK_TILES / 2 + CHUNK_TILES / 8);
+
+    let memory = MemoryChunk::new();
+    assert_eq!(memory.memorized.heap_size(), 0);
+    assert!(!memory.is_memorized(0, 0, CHUNK_SIZE as i32 - 1));
+}
@@ -0,0 +1,263 @@
+#![cfg(test)]
+
+use std::{fs, sync::Arc};
+
+use hecs::{Entity, World};
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    coords::{global_coord, global_of},
+    map::{Map, WorldMap, CHUNK_SIZE},
+    mob::Log,
+    player::new_player,
+    region::RegionStore,
+    save::{load_world, save_world, GameMode},
+    storage::CHUNK_TILES,
+    systems::{memory::MapMemory, streaming::run_chunk_streaming_system},
+    tiles::TileRegistry,
+    worldgen::FlatGenerator,
+    Mob,
+};
+
+use super::{resources::test_resources, save::TempDir, tiles::test_tiles};
+
+fn stored_map(tiles: Arc<TileRegistry>, dir: &TempDir) -> WorldMap {
+    let mut map = WorldMap::new(1, Arc::new(FlatGenerator { ground_level: 0 }), tiles);
+    map.store = Some(RegionStore::new(dir.0.join("cache").join("chunks")));
+    map
+}
+
+/// Ставит стену в верхний угол чанка, над землёй, чтобы отличить его
+/// от сгенерированного
+fn build_wall(map: &mut WorldMap, chunk: (i32, i32, i32)) {
+    let wall = map.tiles.id("wall").unwrap();
+    map.set_tile(global_of(chunk, CHUNK_TILES - 1).into(), wall);
+}
+
+fn has_wall(map: &mut WorldMap, (x, y, z): (i32, i32, i32)) -> bool {
+    let wall = map.tiles.id("wall").unwrap();
+    let chunk = map.get_chunk_or_create(x, y, z).lock().unwrap();
+    chunk.tiles.get(CHUNK_TILES - 1) == wall && chunk.obstacles.get(CHUNK_TILES - 1)
+}
+
+/// Позиция тайла в середине чанка
+fn chunk_center(chunk: (i32, i32, i32)) -> Vec3<i32> {
+    Vec3::from(chunk).map(|k| global_coord(k, CHUNK_SIZE / 2))
+}
+
+#[test]
+fn unloaded_chunks_keep_changes() {
+    let dir = TempDir::new("unload-chunks");
+    let mut map = stored_map(test_tiles(), &dir);
+    for x in -1..=5 {
+        map.get_chunk_or_create(x, 0, 0);
+    }
+    build_wall(&mut map, (5, 0, 0));
+    build_wall(&mut map, (-1, 0, 0));
+
+    map.unload_chunks(|(x, _, _)| x.abs() <= 1).unwrap();
+    assert_eq!(map.chunks.len(), 3);
+    assert!(map.get_chunk(5, 0, 0).is_none());
+    assert!(has_wall(&mut map, (5, 0, 0)));
+    assert!(has_wall(&mut map, (-1, 0, 0)));
+    assert!(!has_wall(&mut map, (4, 0, 0)));
+}
+
+#[test]
+fn unloaded_chunks_forget_revisions() {
+    let dir = TempDir::new("chunk-revisions");
+    let mut map = stored_map(test_tiles(), &dir);
+    for x in 0..=5 {
+        build_wall(&mut map, (x, 0, 0));
+    }
+    let (min, max) = (chunk_center((1, 0, 0)), chunk_center((5, 0, 0)));
+    let before_unload = map.revision_in(min, max);
+    map.unload_chunks(|(x, _, _)| x == 0).unwrap();
+    assert_eq!(map.chunk_revisions.len(), 1);
+    assert_eq!(map.revision_in(min, max), 0);
+
+    // Загруженный обратно чанк новее всех кэшей, построенных до выгрузки
+    // и пока его не было
+    let revision = map.revision;
+    map.get_chunk_or_create(5, 0, 0);
+    assert_eq!(map.revision_in(min, max), revision + 1);
+    assert!(map.revision_in(min, max) > before_unload);
+}
+
+#[test]
+fn corrupted_cache_is_regenerated() {
+    let dir = TempDir::new("corrupted-cache");
+    let mut map = stored_map(test_tiles(), &dir);
+    build_wall(&mut map, (5, 0, 0));
+    map.unload_chunks(|_| false).unwrap();
+    let mut memory = MapMemory::new();
+    memory.store = Some(RegionStore::new(dir.0.join("cache").join("memory")));
+    memory
+        .get_chunk_or_create(5, 0, 0)
+        .lock()
+        .unwrap()
+        .memorized
+        .set(0, true);
+    memory.unload_chunks(|_| false).unwrap();
+    let region = |store: &str| dir.0.join("cache").join(store).join("2_0_0.yaml");
+    let corrupt = |store: &str| region(store).with_extension("yaml.corrupt");
+    let mut damaged = Vec::new();
+    for store in ["chunks", "memory"] {
+        let source = fs::read_to_string(region(store)).unwrap();
+        damaged.push(source[..source.len() / 2].to_owned());
+        fs::write(region(store), damaged.last().unwrap()).unwrap();
+    }
+
+    // Испорченный чанк генерируется заново, а испорченная память пуста.
+    // Испорченные регионы не теряются, а откладываются
+    assert!(!has_wall(&mut map, (5, 0, 0)));
+    let chunk = memory.get_chunk_or_create(5, 0, 0).lock().unwrap();
+    assert!(!chunk.memorized.get(0));
+    drop(chunk);
+    for (store, damaged) in ["chunks", "memory"].into_iter().zip(damaged) {
+        assert_eq!(fs::read_to_string(corrupt(store)).unwrap(), damaged);
+    }
+    let messages = map.store.as_ref().unwrap().take_corrupted();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("the chunk is generated anew"));
+    assert!(map.store.as_ref().unwrap().take_corrupted().is_empty());
+    map.request_chunk(4, 0, 0);
+    assert!(map.get_chunk(4, 0, 0).is_some());
+    // Регион записывается заново, и изменения снова сохраняются
+    build_wall(&mut map, (5, 0, 0));
+    map.unload_chunks(|_| false).unwrap();
+    assert!(has_wall(&mut map, (5, 0, 0)));
+}
+
+#[test]
+fn corrupted_chunk_is_reported_to_player() {
+    let dir = TempDir::new("corrupted-chunk");
+    let mut map = stored_map(test_tiles(), &dir);
+    build_wall(&mut map, (4, 0, 0));
+    let window = map.tiles.id("window").unwrap();
+    map.set_tile(global_of((5, 0, 0), CHUNK_TILES - 1).into(), window);
+    map.unload_chunks(|_| false).unwrap();
+    // Регион читается, но в записи чанка (5, 0, 0) неизвестный тайл
+    let region = dir.0.join("cache").join("chunks").join("2_0_0.yaml");
+    let source = fs::read_to_string(&region).unwrap();
+    fs::write(&region, source.replace("window", "no_such_tile")).unwrap();
+
+    map.get_chunk_or_create(5, 0, 0);
+    let mut world = World::new();
+    world.spawn((map,));
+    let mut player = new_player();
+    player.add(Position(chunk_center((5, 0, 0))));
+    let player = world.spawn(player.build());
+    run_chunk_streaming_system(&mut world).unwrap();
+
+    let log = world.get::<&Log>(player).unwrap().0.clone();
+    assert!(log.contains("chunk (5, 0, 0)"), "{log}");
+    assert!(log.contains("2_0_0.yaml.corrupt"), "{log}");
+    // Копия сохранила испорченную запись, а исправные записи остались в регионе
+    let kept = fs::read_to_string(region.with_extension("yaml.corrupt")).unwrap();
+    assert!(kept.contains("no_such_tile"));
+    let mut query = world.query::<(&mut WorldMap,)>();
+    let (_, (map,)) = query.iter().next().unwrap();
+    assert!(has_wall(map, (4, 0, 0)));
+}
+
+#[test]
+fn streaming_follows_mobs() {
+    let dir = TempDir::new("chunk-streaming");
+    let mut map = stored_map(test_tiles(), &dir);
+    for x in 0..=12 {
+        map.get_chunk_or_create(x, 0, 0);
+    }
+    build_wall(&mut map, (6, 0, 0));
+    let mut memory = MapMemory::new();
+    for x in [0, 6] {
+        let mut chunk = memory.get_chunk_or_create(x, 0, 0).lock().unwrap();
+        chunk.memorized.set(0, true);
+    }
+
+    let mut world = World::new();
+    world.spawn((map,));
+    let player = world.spawn((Position(chunk_center((0, 0, 0))), Mob, memory));
+    world.spawn((Position(chunk_center((10, 0, 0))), Mob));
+    run_chunk_streaming_system(&mut world).unwrap();
+
+    let mut query = world.query::<(&mut WorldMap,)>();
+    let (_, (map,)) = query.iter().next().unwrap();
+    let mut loaded = map.chunks.keys().map(|(x, _, _)| *x).collect::<Vec<_>>();
+    loaded.sort();
+    assert_eq!(loaded, [0, 1, 2, 8, 9, 10, 11, 12]);
+    assert!(has_wall(map, (6, 0, 0)));
+
+    let mut memory = world.get::<&mut MapMemory>(player).unwrap();
+    assert!(memory.get_chunk(6, 0, 0).is_none());
+    assert!(memory.get_chunk(0, 0, 0).is_some());
+    let chunk = memory.get_chunk_or_create(6, 0, 0).lock().unwrap();
+    assert!(chunk.memorized.get(0) && !chunk.memorized.get(1));
+}
+
+#[test]
+fn memory_is_not_inherited() {
+    let dir = TempDir::new("memory-reuse");
+    let mut world = World::new();
+    world.spawn((stored_map(test_tiles(), &dir),));
+    let mut memory = MapMemory::new();
+    memory
+        .get_chunk_or_create(6, 0, 0)
+        .lock()
+        .unwrap()
+        .memorized
+        .set(0, true);
+    let old = world.spawn((Position(chunk_center((0, 0, 0))), Mob, memory));
+    run_chunk_streaming_system(&mut world).unwrap();
+    world.despawn(old).unwrap();
+
+    // hecs отдаёт номер удалённой сущности новой, со следующим поколением
+    let new = Entity::from_bits(old.to_bits().get() + (1 << 32)).unwrap();
+    world.spawn_at(
+        new,
+        (Position(chunk_center((0, 0, 0))), Mob, MapMemory::new()),
+    );
+    run_chunk_streaming_system(&mut world).unwrap();
+    let mut memory = world.get::<&mut MapMemory>(new).unwrap();
+    let chunk = memory.get_chunk_or_create(6, 0, 0).lock().unwrap();
+    assert!(!chunk.memorized.get(0));
+}
+
+#[test]
+fn unloaded_chunks_are_saved() {
+    let resources = test_resources();
+    let dir = TempDir::new("save-unloaded");
+    let mut map = stored_map(resources.tiles.clone(), &dir);
+    build_wall(&mut map, (3, 0, 0));
+    map.get_chunk_or_create(0, 0, 0);
+    map.unload_chunks(|(x, _, _)| x == 0).unwrap();
+    let mut world = World::new();
+    world.spawn((map,));
+
+    let save_dir = dir.0.join("save");
+    save_world(
+        &save_dir,
+        &world,
+        1,
+        GameMode::Normal,
+        &resources.components,
+    )
+    .unwrap();
+    let (mut loaded, _) = load_world(
+        &save_dir,
+        &resources.components,
+        &resources.template_context(),
+        &resources.tiles,
+        &save_dir.join("cache"),
+    )
+    .unwrap();
+    let (_, (map,)) = loaded
+        .query_mut::<(&mut WorldMap,)>()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert!(map.chunks.is_empty());
+    assert!(has_wall(map, (3, 0, 0)));
+    assert!(!has_wall(map, (0, 0, 0)));
+}
@@ -0,0 +1,161 @@
+#![cfg(test)]
+
+use std::sync::Arc;
+
+use hecs::{Entity, World};
+use vek::Vec3;
+
+use crate::{
+    components::Position,
+    items::Item,
+    map::{ChunkRuns, Map, WorldMap},
+    mob::{Inventory, Log},
+    player::new_player,
+    region::RegionStore,
+    systems::terrain::{run_terrain_system, WantsBuild, WantsDig, DIGGING_PROPERTY},
+    worldgen::FlatGenerator,
+    Direction, Property,
+};
+
+use super::{save::TempDir, tiles::test_tiles};
+
+fn flat_map() -> WorldMap {
+    WorldMap::new(1, Arc::new(FlatGenerator { ground_level: 0 }), test_tiles())
+}
+
+fn shovel(power: i32) -> Item {
+    let mut item = Item::new("shovel".into(), "item".into());
+    item.add_props(&[(DIGGING_PROPERTY.into(), Property::Int(power))]);
+    item
+}
+
+/// Мир с плоской землёй и персонажем, стоящим на ней в (0, 0, 0)
+fn world_with_player(items: Vec<Item>) -> (World, Entity) {
+    let mut world = World::new();
+    world.spawn((flat_map(),));
+    let mut player = new_player();
+    player.add(Position(Vec3::zero()));
+    let player = world.spawn(player.build());
+    world.get::<&mut Inventory>(player).unwrap().0 = items;
+    (world, player)
+}
+
+fn tile_name(world: &World, pos: Vec3<i32>) -> String {
+    let mut query = world.query::<(&mut WorldMap,)>();
+    let (_, (map,)) = query.iter().next().unwrap();
+    let tile = map.get_tile_or_create(pos.x, pos.y, pos.z);
+    map.tiles.get(tile).name.to_string()
+}
+
+fn last_log_line(world: &World, player: Entity) -> String {
+    let log = world.get::<&Log>(player).unwrap();
+    log.0.lines().last().unwrap_or_default().to_owned()
+}
+
+#[test]
+fn set_tile_keeps_chunk_in_sync() {
+    let mut map = flat_map();
+    let wall = map.tiles.id("wall").unwrap();
+    let empty = map.tiles.id("empty").unwrap();
+    let pos = Vec3::new(-32, 31, 5);
+    assert!(!map.get_obstacle_or_create(pos.x, pos.y, pos.z));
+    assert!(map.dirty.is_empty());
+
+    map.set_tile(pos, wall);
+    assert_eq!(map.get_tile_or_create(pos.x, pos.y, pos.z), wall);
+    assert!(map.get_obstacle_or_create(pos.x, pos.y, pos.z));
+    assert!(map.dirty.contains(&WorldMap::xy_chunk(pos.x, pos.y, pos.z)));
+    assert_eq!(map.revision, 1);
+    // Соседние тайлы не меняются
+    assert!(!map.get_obstacle_or_create(pos.x - 1, pos.y, pos.z));
+    assert!(!map.get_obstacle_or_create(pos.x, pos.y + 1, pos.z));
+
+    // Тот же тайл ничего не меняет
+    map.set_tile(pos, wall);
+    assert_eq!(map.revision, 1);
+    map.set_tile(pos, empty);
+    assert!(!map.get_obstacle_or_create(pos.x, pos.y, pos.z));
+    assert_eq!(map.revision, 2);
+}
+
+#[test]
+fn only_changed_chunks_are_stored() {
+    let dir = TempDir::new("dirty-chunks");
+    let mut map = flat_map();
+    let store: RegionStore<ChunkRuns> = RegionStore::new(dir.0.join("chunks"));
+    map.store = Some(RegionStore::new(store.dir().to_owned()));
+    map.get_chunk_or_create(0, 0, 0);
+    let wall = map.tiles.id("wall").unwrap();
+    map.set_tile(Vec3::new(3 * 64, 0, 10), wall);
+
+    map.unload_chunks(|_| false).unwrap();
+    assert!(map.chunks.is_empty() && map.dirty.is_empty());
+    let stored = store.load_all().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].0, (3, 0, 0));
+    assert!(map.get_obstacle_or_create(3 * 64, 0, 10));
+    // Загруженный без изменений чанк не помечается изменённым
+    assert!(map.dirty.is_empty());
+}
+
+#[test]
+fn dig_and_build() {
+    let (mut world, player) = world_with_player(vec![shovel(10)]);
+    let below = Vec3::new(0, 0, -1);
+    assert_eq!(tile_name(&world, below), "wall");
+
+    world.insert_one(player, WantsDig(Direction::Down)).unwrap();
+    run_terrain_system(&mut world).unwrap();
+    assert_eq!(tile_name(&world, below), "empty");
+    assert_eq!(last_log_line(&world, player), "You dig through the wall");
+    assert!(world.get::<&WantsDig>(player).is_err());
+    let items = world.get::<&Inventory>(player).unwrap().0.clone();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].name, "wall block");
+
+    world
+        .insert_one(player, WantsBuild(Direction::Right))
+        .unwrap();
+    run_terrain_system(&mut world).unwrap();
+    assert_eq!(tile_name(&world, Vec3::new(1, 0, 0)), "wall");
+    assert_eq!(last_log_line(&world, player), "You build a wall");
+    assert_eq!(world.get::<&Inventory>(player).unwrap().0.len(), 1);
+
+    // Блоков больше нет
+    world
+        .insert_one(player, WantsBuild(Direction::Left))
+        .unwrap();
+    run_terrain_system(&mut world).unwrap();
+    assert_eq!(tile_name(&world, Vec3::new(-1, 0, 0)), "empty");
+    assert_eq!(
+        last_log_line(&world, player),
+        "You have no blocks to build with"
+    );
+}
+
+#[test]
+fn digging_needs_tool() {
+    let (mut world, player) = world_with_player(Vec::new());
+    world.insert_one(player, WantsDig(Direction::Down)).unwrap();
+    run_terrain_system(&mut world).unwrap();
+    assert_eq!(last_log_line(&world, player), "You need a digging tool");
+
+    world
+        .get::<&mut Inventory>(player)
+        .unwrap()
+        .0
+        .push(shovel(3));
+    world.insert_one(player, WantsDig(Direction::Down)).unwrap();
+    run_terrain_system(&mut world).unwrap();
+    assert_eq!(
+        last_log_line(&world, player),
+        "The wall is too hard for your tools"
+    );
+    assert_eq!(tile_name(&world, Vec3::new(0, 0, -1)), "wall");
+
+    world
+        .insert_one(player, WantsDig(Direction::Right))
+        .unwrap();
+    run_terrain_system(&mut world).unwrap();
+    assert_eq!(last_log_line(&world, player), "There is nothing to dig");
+}
@@ -0,0 +1,145 @@
+#![cfg(test)]
+
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    map::{Chunk, CHUNK_SIZE},
+    resources::{AssetsConfig, Resources},
+    tiles::{TileId, TileRegistry, TilesError},
+    worldgen::{ChunkGenerator, NoiseGenerator},
+};
+
+use super::with_big_stack;
+
+/// Тайлы из каталога данных игры
+pub fn test_tiles() -> Arc<TileRegistry> {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    Arc::new(Resources::load_tiles(&data_path).unwrap())
+}
+
+#[test]
+fn tiles_load() {
+    let tiles = test_tiles();
+    let empty = tiles.by_name("empty").unwrap();
+    assert!(!empty.blocks_movement && !empty.blocks_sight);
+    let wall = tiles.by_name("wall").unwrap();
+    assert!(wall.blocks_movement && wall.blocks_sight);
+    assert_eq!(&*wall.material, "stone");
+    assert_eq!(&*tiles.by_name("toxic_sludge").unwrap().sprite, "vomit_lol");
+    assert_eq!(wall.light, None);
+    let lamp = tiles.by_name("lamp").unwrap().light.unwrap();
+    assert_eq!((lamp.radius, lamp.color), (10, 0xFFD080));
+    // Номера выдаются по порядку описания в файле
+    for (n, (id, tile)) in tiles.iter().enumerate() {
+        assert_eq!(id, TileId(n as u16));
+        assert_eq!(tiles.id(&tile.name), Some(id));
+    }
+    assert_eq!(tiles.id("unobtainium"), None);
+}
+
+#[test]
+fn tile_errors() {
+    let file = Path::new("tiles.yaml");
+    let parse = |source: &str| TileRegistry::parse(file, source);
+
+    let tiles = parse(
+        "
+glass:
+  sprite: wall
+  fallback_sprite: empty
+  blocks_movement: true
+  material: glass
+  hardness: 2
+",
+    )
+    .unwrap();
+    let glass = tiles.by_name("glass").unwrap();
+    assert!(glass.blocks_movement && !glass.blocks_sight);
+    assert_eq!(glass.fallback_sprite.as_deref(), Some("empty"));
+    assert_eq!(glass.flammability, 0.);
+
+    assert!(matches!(
+        parse("glass:\n  sprite: wall\n  material: glass\n  transparent: true\n"),
+        Err(TilesError::Tile { tile, .. }) if tile == "glass"
+    ));
+    assert!(matches!(
+        parse("glass:\n  sprite: wall\n"),
+        Err(TilesError::Tile { .. })
+    ));
+    assert!(matches!(
+        parse("wood:\n  sprite: wall\n  material: wood\n  flammability: 2\n"),
+        Err(TilesError::Flammability { tile, .. }) if tile == "wood"
+    ));
+    let lamp = |light: &str| {
+        parse(&format!(
+            "lamp:\n  sprite: wall\n  material: glass\n  light: {{{light}}}\n"
+        ))
+    };
+    assert!(lamp("radius: 3, color: 0xFF0000, intensity: 0.5").is_ok());
+    assert!(matches!(
+        lamp("radius: 3, color: 0xFF0000, intensity: 0"),
+        Err(TilesError::Light { tile, .. }) if tile == "lamp"
+    ));
+    assert!(matches!(
+        lamp("radius: 3, color: 0x1000000, intensity: 1"),
+        Err(TilesError::Light { .. })
+    ));
+    assert!(matches!(
+        lamp("radius: 3, colour: 0xFF0000, intensity: 1"),
+        Err(TilesError::Tile { .. })
+    ));
+    assert!(matches!(
+        parse("1:\n  sprite: wall\n  material: stone\n"),
+        Err(TilesError::Name { .. })
+    ));
+    assert!(matches!(
+        tiles.require(file, &["glass", "wall"]),
+        Err(TilesError::Missing { name, .. }) if name == "wall"
+    ));
+
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let assets = AssetsConfig::load(&data_path.join("gfx")).unwrap();
+    assert!(assets.check_tiles(file, &tiles).is_empty());
+    let tiles = parse("glass:\n  sprite: glass\n  material: glass\n").unwrap();
+    let errors = assets.check_tiles(file, &tiles);
+    assert!(matches!(
+        errors.as_slice(),
+        [TilesError::UnknownSprite { sprite, .. }] if sprite == "glass"
+    ));
+}
+
+#[test]
+fn chunk_keeps_tile_names() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let chunk = NoiseGenerator.generate(3, &tiles, 0, 0, 0);
+        let runs = chunk.to_runs(&tiles);
+        // В реестре с другим порядком тайлов номера другие, но тайлы те же
+        let mut source = String::new();
+        for (_, tile) in tiles.iter().collect::<Vec<_>>().into_iter().rev() {
+            let blocks = tile.blocks_movement;
+            source += &format!(
+                "{}:\n  sprite: {}\n  material: {}\n  blocks_movement: {blocks}\n",
+                tile.name, tile.sprite, tile.material
+            );
+        }
+        let reversed = TileRegistry::parse(Path::new("tiles.yaml"), &source).unwrap();
+        let loaded = Chunk::from_runs(runs, &reversed).unwrap();
+        for idx in 0..CHUNK_SIZE.pow(3) {
+            let (a, b) = (
+                tiles.get(chunk.tiles.get(idx)),
+                reversed.get(loaded.tiles.get(idx)),
+            );
+            assert_eq!(a.name, b.name);
+            assert_eq!(chunk.obstacles.get(idx), loaded.obstacles.get(idx));
+        }
+
+        let wall_only = TileRegistry::parse(
+            Path::new("tiles.yaml"),
+            "wall:\n  sprite: wall\n  material: stone\n",
+        )
+        .unwrap();
+        let runs = chunk.to_runs(&tiles);
+        assert!(Chunk::from_runs(runs, &wall_only).is_err());
+    });
+}
@@ -0,0 +1,125 @@
+#![cfg(test)]
+
+use crate::{
+    coords::{chunk_coord, global_coord},
+    map::{Chunk, CHUNK_SIZE},
+    worldgen::{ChunkGenerator, EmptyGenerator, FlatGenerator, NoiseGenerator, PatternGenerator},
+};
+
+use super::{tiles::test_tiles, with_big_stack};
+
+fn is_wall(chunk: &Chunk, x: usize, y: usize, z: usize) -> bool {
+    chunk
+        .obstacles
+        .get(x + y * CHUNK_SIZE + z * CHUNK_SIZE.pow(2))
+}
+
+#[test]
+fn flat_and_empty_generators() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let flat = FlatGenerator { ground_level: 5 };
+        for ch_z in -1..=1 {
+            let chunk = flat.generate(0, &tiles, 3, -2, ch_z);
+            for z in 0..CHUNK_SIZE {
+                let expected = global_coord(ch_z, z) < 5;
+                assert_eq!(is_wall(&chunk, 0, 0, z), expected);
+                assert_eq!(is_wall(&chunk, 63, 17, z), expected);
+            }
+        }
+        let chunk = EmptyGenerator.generate(0, &tiles, 0, 0, -1);
+        assert!(chunk.obstacles.iter().all(|obstacle| !obstacle));
+        assert!(chunk
+            .tiles
+            .iter()
+            .all(|tile| &*tiles.get(tile).name == "empty"));
+    });
+}
+
+#[test]
+fn pattern_generator_uses_world_coords() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let pattern = PatternGenerator {
+            is_wall: |x, y, z| x == -96 || (x + y + z).rem_euclid(7) == 0,
+        };
+        let chunk = pattern.generate(0, &tiles, -1, 0, 0);
+        // Чанк -1 по x начинается с x = -96
+        for y in 0..CHUNK_SIZE {
+            assert!(is_wall(&chunk, 0, y, 10));
+        }
+        // Тайлы (-95, -32, -32) и (-90, -32, -32)
+        assert!(!is_wall(&chunk, 1, 0, 0));
+        assert!(is_wall(&chunk, 6, 0, 0));
+        let walls = chunk.obstacles.iter().filter(|obstacle| *obstacle).count();
+        assert!(walls > CHUNK_SIZE.pow(3) / 8);
+    });
+}
+
+#[test]
+fn noise_generator_terrain() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let chunk = NoiseGenerator.generate(7, &tiles, 0, 0, 0);
+        assert!(chunk == NoiseGenerator.generate(7, &tiles, 0, 0, 0));
+        assert!(chunk != NoiseGenerator.generate(8, &tiles, 0, 0, 0));
+        // Рельеф другого зерна не остаётся от прошлой генерации
+        assert!(chunk == NoiseGenerator.generate(7, &tiles, 0, 0, 0));
+
+        // Высота поверхности в разных столбцах разная: есть холмы и долины
+        let surface = |x, y| (0..CHUNK_SIZE).rev().find(|&z| is_wall(&chunk, x, y, z));
+        let heights: Vec<_> = (0..CHUNK_SIZE)
+            .flat_map(|y| (0..CHUNK_SIZE).map(move |x| (x, y)))
+            .map(|(x, y)| surface(x, y))
+            .collect();
+        let min = heights.iter().min().unwrap();
+        let max = heights.iter().max().unwrap();
+        assert!(max > min, "Поверхность плоская");
+
+        // Под поверхностью есть пустоты: пещеры или нависающие склоны
+        let hollows = (0..CHUNK_SIZE)
+            .flat_map(|y| (0..CHUNK_SIZE).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                let Some(top) = surface(x, y) else {
+                    return false;
+                };
+                (0..top).any(|z| !is_wall(&chunk, x, y, z))
+            })
+            .count();
+        assert!(hollows > 0, "Под поверхностью нет пустот");
+    });
+}
+
+#[test]
+fn noise_spawns_stand_on_floor() {
+    with_big_stack(|| {
+        let tiles = test_tiles();
+        let seed = 5;
+        let mut spawned = 0;
+        for ch_y in -3..3 {
+            for ch_x in -3..3 {
+                let ground = NoiseGenerator.ground_level(
+                    seed,
+                    global_coord(ch_x, 32),
+                    global_coord(ch_y, 32),
+                );
+                let ch_z = chunk_coord(ground.unwrap());
+                let chunk = NoiseGenerator.generate(seed, &tiles, ch_x, ch_y, ch_z);
+                for spawn in NoiseGenerator.spawns(seed, &chunk, ch_x, ch_y, ch_z) {
+                    let (x, y, z) = spawn.position.into_tuple();
+                    assert_eq!(chunk_coord(z), ch_z);
+                    assert!(
+                        !chunk.get_obstacle(x, y, z),
+                        "Существо в стене на ({x}, {y}, {z})"
+                    );
+                    assert!(
+                        chunk.get_obstacle(x, y, z - 1),
+                        "Существо висит в воздухе на ({x}, {y}, {z})"
+                    );
+                    spawned += 1;
+                }
+            }
+        }
+        assert!(spawned > 0, "Никто не появился");
+    });
+}
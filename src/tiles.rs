@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use thiserror::Error;
+
+use crate::{hasher, light::Light, GameHasher};
+
+/// Номер типа тайла в реестре тайлов. Чанки хранят только эти номера,
+/// а всё остальное о тайле берётся из реестра.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileId(pub u16);
+
+/// Тип тайла, описанный в файле tiles.yaml
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub name: Arc<str>,
+    /// имя спрайта, которым нужно отображать этот тайл
+    pub sprite: Arc<str>,
+    /// имя спрайта, который рисуется под sprite
+    pub fallback_sprite: Option<Arc<str>>,
+    /// через тайл нельзя пройти
+    pub blocks_movement: bool,
+    /// сквозь тайл ничего не видно
+    pub blocks_sight: bool,
+    /// материал, из которого состоит тайл
+    pub material: Arc<str>,
+    /// сколько усилий нужно, чтобы разрушить тайл
+    pub hardness: u32,
+    /// насколько легко тайл загорается, от 0 до 1
+    pub flammability: f32,
+    /// свет, который испускает тайл
+    pub light: Option<Light>,
+}
+
+/// Описание тайла в tiles.yaml. Имя тайла записывается ключом.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TileConfig {
+    sprite: String,
+    #[serde(default)]
+    fallback_sprite: Option<String>,
+    #[serde(default)]
+    blocks_movement: bool,
+    #[serde(default)]
+    blocks_sight: bool,
+    material: String,
+    #[serde(default)]
+    hardness: u32,
+    #[serde(default)]
+    flammability: f32,
+    #[serde(default)]
+    light: Option<Light>,
+}
+
+#[derive(Error, Debug)]
+pub enum TilesError {
+    #[error("{}: can't read file: {source}", file.display())]
+    Io {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: file is corrupted: {source}", file.display())]
+    Yaml {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: tile names must be strings, found {name:?}", file.display())]
+    Name { file: PathBuf, name: Value },
+    #[error("{}: tile {tile}: {source}", file.display())]
+    Tile {
+        file: PathBuf,
+        tile: String,
+        source: serde_yaml::Error,
+    },
+    #[error("{}: tile {tile}: flammability must be between 0 and 1", file.display())]
+    Flammability { file: PathBuf, tile: String },
+    #[error("{}: tile {tile}: light intensity must be positive and color must be 0xRRGGBB", file.display())]
+    Light { file: PathBuf, tile: String },
+    #[error("{}: at most {} tile types are supported", file.display(), u16::MAX as usize + 1)]
+    TooMany { file: PathBuf },
+    #[error("{}: tile {name} is used by the game and must be defined", file.display())]
+    Missing { file: PathBuf, name: String },
+    #[error("{}: tile {tile} uses unknown sprite {sprite}", file.display())]
+    UnknownSprite {
+        file: PathBuf,
+        tile: String,
+        sprite: String,
+    },
+}
+
+/// Все типы тайлов игры. Номер тайла - это его место в файле tiles.yaml,
+/// поэтому номера нельзя записывать в сохранения: там тайлы записываются по именам.
+#[derive(Debug)]
+pub struct TileRegistry {
+    tiles: Vec<Tile>,
+    ids: HashMap<Arc<str>, TileId, GameHasher>,
+}
+
+impl TileRegistry {
+    /// Разбирает содержимое tiles.yaml. Путь к файлу используется только
+    /// в сообщениях об ошибках.
+    pub fn parse(file: &Path, source: &str) -> Result<Self, TilesError> {
+        let configs: Mapping = serde_yaml::from_str(source).map_err(|source| TilesError::Yaml {
+            file: file.to_owned(),
+            source,
+        })?;
+        if configs.len() > u16::MAX as usize + 1 {
+            return Err(TilesError::TooMany {
+                file: file.to_owned(),
+            });
+        }
+        let mut registry = TileRegistry {
+            tiles: Vec::with_capacity(configs.len()),
+            ids: HashMap::with_hasher(hasher()),
+        };
+        for (name, config) in configs {
+            let Value::String(name) = name else {
+                return Err(TilesError::Name {
+                    file: file.to_owned(),
+                    name,
+                });
+            };
+            let config: TileConfig =
+                serde_yaml::from_value(config).map_err(|source| TilesError::Tile {
+                    file: file.to_owned(),
+                    tile: name.clone(),
+                    source,
+                })?;
+            if !(0. ..=1.).contains(&config.flammability) {
+                return Err(TilesError::Flammability {
+                    file: file.to_owned(),
+                    tile: name,
+                });
+            }
+            if config.light.is_some_and(|light| !light.is_valid()) {
+                return Err(TilesError::Light {
+                    file: file.to_owned(),
+                    tile: name,
+                });
+            }
+            let name: Arc<str> = name.into();
+            let id = TileId(registry.tiles.len() as u16);
+            registry.ids.insert(name.clone(), id);
+            registry.tiles.push(Tile {
+                name,
+                sprite: config.sprite.into(),
+                fallback_sprite: config.fallback_sprite.map(Into::into),
+                blocks_movement: config.blocks_movement,
+                blocks_sight: config.blocks_sight,
+                material: config.material.into(),
+                hardness: config.hardness,
+                flammability: config.flammability,
+                light: config.light,
+            });
+        }
+        Ok(registry)
+    }
+
+    /// Проверяет, что описаны все тайлы, которые игра использует по именам
+    pub fn require(&self, file: &Path, names: &[&str]) -> Result<(), TilesError> {
+        match names.iter().find(|name| self.id(name).is_none()) {
+            Some(name) => Err(TilesError::Missing {
+                file: file.to_owned(),
+                name: (*name).to_owned(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Тайл по номеру. Номера выдаются только этим реестром, поэтому
+    /// чужой номер - это ошибка в коде.
+    pub fn get(&self, id: TileId) -> &Tile {
+        self.tiles
+            .get(id.0 as usize)
+            .unwrap_or_else(|| panic!("Тайла с номером {} нет в реестре", id.0))
+    }
+
+    pub fn id(&self, name: &str) -> Option<TileId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Tile> {
+        self.id(name).map(|id| self.get(id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TileId, &Tile)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(id, tile)| (TileId(id as u16), tile))
+    }
+}
@@ -11,13 +11,18 @@ use macroquad::{
     },
 };
 
-use crate::{hasher, items::Item, Direction, GameHasher, PlayerAction, Statistics};
+use crate::{
+    hasher, items::Item, systems::terrain::TerrainAction, Direction, GameHasher, PlayerAction,
+    Statistics,
+};
 
 pub enum UIState {
     No,
     Inventory { items: Vec<Item> },
     Log { text: String },
     Debug,
+    /// Игрок выбирает, в какую сторону копать или строить
+    ChooseDirection(TerrainAction),
 }
 
 pub type DialogKeys = HashMap<char, PlayerAction, GameHasher>;
@@ -36,6 +41,15 @@ impl UIConfig {
         let mut log_keys = HashMap::with_hasher(hasher());
         log_keys.insert('q', PlayerAction::CloseLog);
         dialogs_keys.insert("log".into(), log_keys);
+        let mut direction_keys = HashMap::with_hasher(hasher());
+        direction_keys.insert('h', PlayerAction::Direction(Direction::Left));
+        direction_keys.insert('j', PlayerAction::Direction(Direction::Back));
+        direction_keys.insert('k', PlayerAction::Direction(Direction::Forward));
+        direction_keys.insert('l', PlayerAction::Direction(Direction::Right));
+        direction_keys.insert('u', PlayerAction::Direction(Direction::Up));
+        direction_keys.insert('n', PlayerAction::Direction(Direction::Down));
+        direction_keys.insert('q', PlayerAction::CancelDirection);
+        dialogs_keys.insert("direction".into(), direction_keys);
 
         let mut world_keys = HashMap::with_hasher(hasher());
 
@@ -50,6 +64,9 @@ impl UIConfig {
         world_keys.insert('p', PlayerAction::OpenLog);
         world_keys.insert('z', PlayerAction::Zoom);
         world_keys.insert('Z', PlayerAction::Unzoom);
+        world_keys.insert('S', PlayerAction::Save);
+        world_keys.insert('d', PlayerAction::Dig);
+        world_keys.insert('b', PlayerAction::Build);
 
         Self {
             dialogs_keys,
@@ -109,12 +126,25 @@ pub fn log(log: &str) {
     })
 }
 
-pub fn debug(stats: &Statistics) {
+/// Подсказка, в какую сторону копать или строить
+pub fn choose_direction(action: TerrainAction) {
+    let text = match action {
+        TerrainAction::Dig => "Dig in which direction? (hjklun, q to cancel)",
+        TerrainAction::Build => "Build in which direction? (hjklun, q to cancel)",
+    };
+    dialog(|ui| {
+        widgets::Label::new(text).position(vec2(0., 0.)).ui(ui);
+    });
+}
+
+/// Окно со статистикой систем и дополнительными сведениями о мире
+pub fn debug(stats: &Statistics, world_info: &str) {
+    let text = stats.show() + world_info;
     widgets::Window::new(hash!(), vec2(0., 0.), vec2(300., 300.))
         .movable(true)
         .label("Statistics")
         .ui(&mut root_ui(), |ui| {
-            for (n, line) in stats.show().split('\n').enumerate() {
+            for (n, line) in text.split('\n').enumerate() {
                 widgets::Label::new(line)
                     .position(vec2(0., n as f32 * 14.))
                     .ui(ui);
@@ -0,0 +1,554 @@
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+use noise::{Fbm, NoiseFn, Perlin};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use vek::{Vec2, Vec3};
+
+use crate::{
+    biome::{Biome, BiomeMap},
+    coords::{chunk_coord, chunk_of, global_coord},
+    map::{Chunk, CHUNK_SIZE},
+    planner::WorldPlan,
+    prefabs::{PrefabPlacement, PrefabRegistry, PrefabSpawn, PREFAB_CELL},
+    tiles::{TileId, TileRegistry},
+};
+
+/// Генератор содержимого чанков. Чанк должен зависеть только от зерна мира
+/// и координат чанка, чтобы карту можно было воспроизвести по зерну.
+pub trait ChunkGenerator: Send + Sync {
+    /// Имя генератора, под которым он записывается в сохранение
+    fn name(&self) -> &'static str;
+    fn generate(&self, seed: u64, tiles: &TileRegistry, ch_x: i32, ch_y: i32, ch_z: i32) -> Chunk;
+    /// Высота, на которой можно стоять в столбце (x, y), без генерации чанков.
+    /// None, если у генератора нет поверхности, на которую можно ставить префабы.
+    fn ground_level(&self, _seed: u64, _x: i32, _y: i32) -> Option<i32> {
+        None
+    }
+    /// План поселений и дорог, по которому генератор строит мир, если он есть
+    fn plan(&self, _seed: u64) -> Option<Arc<WorldPlan>> {
+        None
+    }
+    /// Сущности, которые появляются вместе с только что сгенерированным чанком chunk
+    fn spawns(
+        &self,
+        _seed: u64,
+        _chunk: &Chunk,
+        _ch_x: i32,
+        _ch_y: i32,
+        _ch_z: i32,
+    ) -> Vec<PrefabSpawn> {
+        Vec::new()
+    }
+}
+
+/// Тайлы, которые генераторы берут из реестра по именам. Они должны быть
+/// описаны в tiles.yaml.
+pub const GENERATOR_TILES: [&str; 7] = [
+    "empty",
+    "wall",
+    "grass",
+    "cobblestone",
+    "toxic_sludge",
+    "tree",
+    "road",
+];
+
+/// Генераторы, которые можно выбрать по имени при запуске игры или при загрузке сохранения
+pub fn generator_by_name(name: &str) -> Option<Arc<dyn ChunkGenerator>> {
+    match name {
+        "classic" => Some(Arc::new(ClassicGenerator)),
+        "noise" => Some(Arc::new(NoiseGenerator)),
+        "flat" => Some(Arc::new(FlatGenerator { ground_level: 0 })),
+        "empty" => Some(Arc::new(EmptyGenerator)),
+        _ => None,
+    }
+}
+
+/// Зерно генератора случайных чисел для чанка, полученное перемешиванием
+/// зерна мира с координатами чанка (как в SplitMix64)
+pub fn chunk_seed(seed: u64, ch_x: i32, ch_y: i32, ch_z: i32) -> u64 {
+    let mut hash = seed;
+    for coord in [ch_x, ch_y, ch_z] {
+        hash = (hash ^ coord as u32 as u64).wrapping_add(0x9e3779b97f4a7c15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+    }
+    hash
+}
+
+fn tile(tiles: &TileRegistry, name: &str) -> TileId {
+    tiles
+        .id(name)
+        .unwrap_or_else(|| panic!("Тайл {name} не описан в tiles.yaml"))
+}
+
+fn wall_and_empty(tiles: &TileRegistry) -> (TileId, TileId) {
+    (tile(tiles, "wall"), tile(tiles, "empty"))
+}
+
+/// Первый генератор игры: плоская земля ниже нулевого уровня, редкие стены
+/// на ней и иногда шар из стен.
+pub struct ClassicGenerator;
+
+impl ChunkGenerator for ClassicGenerator {
+    fn name(&self) -> &'static str {
+        "classic"
+    }
+
+    fn ground_level(&self, _seed: u64, _x: i32, _y: i32) -> Option<i32> {
+        Some(0)
+    }
+
+    fn generate(&self, seed: u64, tiles: &TileRegistry, ch_x: i32, ch_y: i32, ch_z: i32) -> Chunk {
+        let mut rng = StdRng::seed_from_u64(chunk_seed(seed, ch_x, ch_y, ch_z));
+
+        let is_sphere_in_chunk = rng.gen_bool(1. / 5.);
+        let in_sphere = {
+            let radius = 2;
+
+            let x_crd = rng.gen_range(radius..CHUNK_SIZE as i32 - radius);
+            let y_crd = rng.gen_range(radius..CHUNK_SIZE as i32 - radius);
+            let z_crd = rng.gen_range(-1..3);
+            move |x: i32, y: i32, z: i32| {
+                (((x - x_crd) * (x - x_crd) + (y - y_crd) * (y - y_crd) + (z - z_crd) * (z - z_crd))
+                    as f64)
+                    .sqrt()
+                    < radius as f64
+            }
+        };
+
+        let (wall, empty) = wall_and_empty(tiles);
+        Chunk::from_fn(tiles, |x, y, z| {
+            let is_ground = ch_z < 0 || (z < CHUNK_SIZE / 2 && ch_z == 0);
+            let is_wall_on_ground = z == CHUNK_SIZE / 2 && rng.gen_bool(1. / 300.);
+            let is_sphere_on_ground =
+                is_sphere_in_chunk && ch_z == 0 && in_sphere(x as i32, y as i32, z as i32);
+            if is_ground || is_wall_on_ground || is_sphere_on_ground {
+                wall
+            } else {
+                empty
+            }
+        })
+    }
+}
+
+/// Генератор рельефа на шумах. Высота поверхности задаётся двумерным шумом
+/// (холмы и долины) и параметрами биомов, у поверхности трёхмерный шум делает
+/// нависающие склоны, а под поверхностью другой трёхмерный шум прорезает пещеры.
+/// Верхний слой земли и постройки на нём зависят от биома. Дороги из плана мира
+/// идут по поверхности без нависающих склонов, а на дорогах и в поселениях
+/// нет деревьев и стен. На поверхности появляются существа из таблиц биомов.
+pub struct NoiseGenerator;
+
+/// Шумы рельефа и план мира с биомами. Они зависят только от зерна, но строятся
+/// не быстро, а нужны каждому чанку и каждой ячейке размещения префабов
+struct Terrain {
+    hills: Fbm<Perlin>,
+    overhangs: Perlin,
+    caves: Perlin,
+    plan: Arc<WorldPlan>,
+}
+
+thread_local! {
+    /// Рельеф последнего зерна, с которым генератор работал в этом потоке
+    static TERRAIN: RefCell<Option<(u64, Rc<Terrain>)>> = const { RefCell::new(None) };
+}
+
+impl NoiseGenerator {
+    const HILLS_SCALE: f64 = 1. / 256.;
+    /// Насколько далеко от поверхности склоны могут нависать
+    const OVERHANG_DEPTH: f64 = 6.;
+    const OVERHANG_SCALE: f64 = 1. / 24.;
+    /// Пещеры бывают только в этом слое под поверхностью
+    const CAVES_DEPTH: i32 = 48;
+    const CAVES_SCALE: f64 = 1. / 32.;
+    /// Чем больше, тем шире пещеры
+    const CAVES_WIDTH: f64 = 0.08;
+    /// Размер квартала в развалинах города
+    const CITY_BLOCK: i32 = 20;
+    /// Сколько раз на чанк пробуется поставить существо из таблицы биома
+    const CREATURE_ATTEMPTS: usize = 2;
+
+    /// Столбцы, в которых пробуют появиться существа чанка, и их шаблоны,
+    /// выбранные по весам из таблицы биома столбца. Зависят только от зерна
+    /// и координат чанка, а не от его тайлов.
+    pub fn creature_columns(
+        seed: u64,
+        ch_x: i32,
+        ch_y: i32,
+        ch_z: i32,
+    ) -> Vec<(i32, i32, &'static str)> {
+        let biomes = &Self::terrain(seed).plan.biomes;
+        let mut rng = StdRng::seed_from_u64(chunk_seed(seed, ch_x, ch_y, ch_z));
+        (0..Self::CREATURE_ATTEMPTS)
+            .filter_map(|_| {
+                let x = global_coord(ch_x, rng.gen_range(0..CHUNK_SIZE));
+                let y = global_coord(ch_y, rng.gen_range(0..CHUNK_SIZE));
+                let (template, _) = biomes
+                    .biome_at(x, y)
+                    .spawn_table()
+                    .choose_weighted(&mut rng, |(_, weight)| *weight)
+                    .ok()?;
+                Some((x, y, *template))
+            })
+            .collect()
+    }
+
+    fn terrain(seed: u64) -> Rc<Terrain> {
+        TERRAIN.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            match &*cache {
+                Some((cached_seed, terrain)) if *cached_seed == seed => terrain.clone(),
+                _ => {
+                    let layer_seed = |layer: i32| chunk_seed(seed, layer, 0, 0) as u32;
+                    let terrain = Rc::new(Terrain {
+                        hills: Fbm::<Perlin>::new(layer_seed(0)),
+                        overhangs: Perlin::new(layer_seed(1)),
+                        caves: Perlin::new(layer_seed(2)),
+                        plan: WorldPlan::for_seed(seed),
+                    });
+                    *cache = Some((seed, terrain.clone()));
+                    terrain
+                }
+            }
+        })
+    }
+
+    /// Высота поверхности в столбце без нависающих склонов и пещер
+    fn height(hills: &Fbm<Perlin>, biomes: &BiomeMap, wx: i32, wy: i32) -> f64 {
+        let terrain = biomes.terrain_at(wx, wy);
+        let point = [wx as f64, wy as f64].map(|c| c * Self::HILLS_SCALE);
+        terrain.base_height + hills.get(point) * terrain.hills_height
+    }
+
+    /// Постройка или растение над поверхностью в столбце: тайл и высота над землёй
+    fn feature(seed: u64, biome: Biome, x: i32, y: i32) -> Option<(&'static str, i32)> {
+        let hash = chunk_seed(seed, x, y, 3);
+        match biome {
+            Biome::Forest if hash % 40 == 0 => Some(("tree", 3 + (hash >> 8) as i32 % 3)),
+            Biome::Wasteland if hash % 400 == 0 => Some(("wall", 1 + (hash >> 8) as i32 % 2)),
+            Biome::RuinedCity => {
+                let (block_x, block_y) = (
+                    x.rem_euclid(Self::CITY_BLOCK),
+                    y.rem_euclid(Self::CITY_BLOCK),
+                );
+                if block_x != 0 && block_y != 0 {
+                    return None;
+                }
+                // Уцелевшая высота одинакова для отрезка стены из нескольких тайлов
+                let segment = chunk_seed(seed, x.div_euclid(4), y.div_euclid(4), 4);
+                match segment % 5 {
+                    0 => None,
+                    height => Some(("cobblestone", height as i32)),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ChunkGenerator for NoiseGenerator {
+    fn name(&self) -> &'static str {
+        "noise"
+    }
+
+    fn ground_level(&self, seed: u64, x: i32, y: i32) -> Option<i32> {
+        let terrain = Self::terrain(seed);
+        let height = Self::height(&terrain.hills, &terrain.plan.biomes, x, y);
+        Some(height.ceil() as i32)
+    }
+
+    fn plan(&self, seed: u64) -> Option<Arc<WorldPlan>> {
+        Some(Self::terrain(seed).plan.clone())
+    }
+
+    /// Для каждой попытки по зерну чанка выбирается столбец, и если поверхность
+    /// в нём лежит в этом чанке, на ней появляется существо, выбранное по весам
+    /// из таблицы биома столбца. Существо встаёт на первый снизу от высоты холмов
+    /// свободный тайл чанка, под которым есть опора: поверхность может закрывать
+    /// нависающий склон или префаб, а под ней может быть пещера.
+    fn spawns(
+        &self,
+        seed: u64,
+        chunk: &Chunk,
+        ch_x: i32,
+        ch_y: i32,
+        ch_z: i32,
+    ) -> Vec<PrefabSpawn> {
+        let terrain = Self::terrain(seed);
+        Self::creature_columns(seed, ch_x, ch_y, ch_z)
+            .into_iter()
+            .filter_map(|(x, y, template)| {
+                let biome = terrain.plan.biomes.biome_at(x, y);
+                let ground = Self::height(&terrain.hills, &terrain.plan.biomes, x, y).ceil() as i32;
+                if chunk_coord(ground) != ch_z || Self::feature(seed, biome, x, y).is_some() {
+                    return None;
+                }
+                // Опора под нижним тайлом чанка лежит в другом чанке
+                let z = (ground.max(global_coord(ch_z, 1))..=global_coord(ch_z, CHUNK_SIZE - 1))
+                    .find(|&z| !chunk.get_obstacle(x, y, z) && chunk.get_obstacle(x, y, z - 1))?;
+                Some(PrefabSpawn {
+                    template: template.into(),
+                    position: Vec3::new(x, y, z),
+                })
+            })
+            .collect()
+    }
+
+    fn generate(&self, seed: u64, tiles: &TileRegistry, ch_x: i32, ch_y: i32, ch_z: i32) -> Chunk {
+        let terrain = Self::terrain(seed);
+        let (overhangs, caves) = (&terrain.overhangs, &terrain.caves);
+        let plan = &terrain.plan;
+        let biomes = &plan.biomes;
+        let (min, max) = (
+            Vec2::new(global_coord(ch_x, 0), global_coord(ch_y, 0)),
+            Vec2::new(
+                global_coord(ch_x, CHUNK_SIZE - 1),
+                global_coord(ch_y, CHUNK_SIZE - 1),
+            ),
+        );
+        let roads = plan.roads_in(min, max);
+        let settlements = plan.settlements_in(min, max);
+        let road = tile(tiles, "road");
+
+        struct Column {
+            height: f64,
+            surface: TileId,
+            feature: Option<(TileId, i32)>,
+            is_road: bool,
+        }
+        let mut columns = Vec::with_capacity(CHUNK_SIZE.pow(2));
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let (wx, wy) = (global_coord(ch_x, x), global_coord(ch_y, y));
+                let biome = biomes.biome_at(wx, wy);
+                let is_road = roads.iter().any(|road| road.contains(wx, wy));
+                let is_cleared = is_road
+                    || settlements
+                        .iter()
+                        .any(|settlement| settlement.contains(wx, wy));
+                columns.push(Column {
+                    height: Self::height(&terrain.hills, biomes, wx, wy),
+                    surface: if is_road {
+                        road
+                    } else {
+                        tile(tiles, biome.surface_tile())
+                    },
+                    feature: Self::feature(seed, biome, wx, wy)
+                        .filter(|_| !is_cleared)
+                        .map(|(name, height)| (tile(tiles, name), height)),
+                    is_road,
+                });
+            }
+        }
+
+        // Земля вместе с нависающими склонами, но без пещер.
+        // Шум склонов считается только там, где он может что-то изменить.
+        let is_ground = |column: &Column, wx: i32, wy: i32, wz: i32| {
+            let depth = column.height - wz as f64;
+            if depth.abs() < Self::OVERHANG_DEPTH && !column.is_road {
+                let point = [wx as f64, wy as f64, wz as f64].map(|c| c * Self::OVERHANG_SCALE);
+                depth + overhangs.get(point) * Self::OVERHANG_DEPTH > 0.
+            } else {
+                depth > 0.
+            }
+        };
+        let (wall, empty) = wall_and_empty(tiles);
+        Chunk::from_fn(tiles, |x, y, z| {
+            let column = &columns[x + y * CHUNK_SIZE];
+            let (wx, wy, wz) = (
+                global_coord(ch_x, x),
+                global_coord(ch_y, y),
+                global_coord(ch_z, z),
+            );
+            let depth = column.height - wz as f64;
+            let is_solid = is_ground(column, wx, wy, wz);
+            // Пещеры начинаются ниже нависающих склонов, чтобы не выходить на поверхность
+            let is_cave = is_solid
+                && depth > Self::OVERHANG_DEPTH + 2.
+                && depth < Self::CAVES_DEPTH as f64
+                && caves
+                    .get([wx as f64, wy as f64, wz as f64 * 2.].map(|c| c * Self::CAVES_SCALE))
+                    .abs()
+                    < Self::CAVES_WIDTH;
+            if is_solid && !is_cave {
+                if is_ground(column, wx, wy, wz + 1) {
+                    wall
+                } else {
+                    column.surface
+                }
+            } else {
+                match column.feature {
+                    Some((feature, height)) if depth <= 0. && depth > -(height as f64) => feature,
+                    _ => empty,
+                }
+            }
+        })
+    }
+}
+
+/// Ровная земля: всё ниже ground_level заполнено стенами
+pub struct FlatGenerator {
+    pub ground_level: i32,
+}
+
+impl ChunkGenerator for FlatGenerator {
+    fn name(&self) -> &'static str {
+        "flat"
+    }
+
+    fn ground_level(&self, _seed: u64, _x: i32, _y: i32) -> Option<i32> {
+        Some(self.ground_level)
+    }
+
+    fn generate(
+        &self,
+        _seed: u64,
+        tiles: &TileRegistry,
+        _ch_x: i32,
+        _ch_y: i32,
+        ch_z: i32,
+    ) -> Chunk {
+        let (wall, empty) = wall_and_empty(tiles);
+        Chunk::from_fn(tiles, |_, _, z| {
+            if global_coord(ch_z, z) < self.ground_level {
+                wall
+            } else {
+                empty
+            }
+        })
+    }
+}
+
+/// Мир без единой стены
+pub struct EmptyGenerator;
+
+impl ChunkGenerator for EmptyGenerator {
+    fn name(&self) -> &'static str {
+        "empty"
+    }
+
+    fn generate(
+        &self,
+        _seed: u64,
+        tiles: &TileRegistry,
+        _ch_x: i32,
+        _ch_y: i32,
+        _ch_z: i32,
+    ) -> Chunk {
+        let empty = tile(tiles, "empty");
+        Chunk::from_fn(tiles, |_, _, _| empty)
+    }
+}
+
+/// Стены расставляются функцией от глобальных координат тайла.
+/// Удобно для тестов, которым нужна заранее известная карта.
+pub struct PatternGenerator {
+    pub is_wall: fn(i32, i32, i32) -> bool,
+}
+
+impl ChunkGenerator for PatternGenerator {
+    fn name(&self) -> &'static str {
+        "pattern"
+    }
+
+    fn generate(&self, _seed: u64, tiles: &TileRegistry, ch_x: i32, ch_y: i32, ch_z: i32) -> Chunk {
+        let (wall, empty) = wall_and_empty(tiles);
+        Chunk::from_fn(tiles, |x, y, z| {
+            let (wx, wy, wz) = (
+                global_coord(ch_x, x),
+                global_coord(ch_y, y),
+                global_coord(ch_z, z),
+            );
+            if (self.is_wall)(wx, wy, wz) {
+                wall
+            } else {
+                empty
+            }
+        })
+    }
+}
+
+/// Ставит префабы из prefabs.yaml поверх чанков другого генератора. Мир делится
+/// на ячейки сетки размещения, и в каждой ячейке по зерну выбирается не больше
+/// одного префаба. Чанк получает все тайлы префабов из ячеек, которые его
+/// задевают, поэтому префаб на границе чанков собирается из частей одинаково,
+/// в каком бы порядке чанки ни генерировались.
+pub struct PrefabGenerator {
+    pub base: Arc<dyn ChunkGenerator>,
+    pub prefabs: Arc<PrefabRegistry>,
+}
+
+impl PrefabGenerator {
+    /// Префабы, которые задевают чанк
+    pub fn placements(&self, seed: u64, ch_x: i32, ch_y: i32, ch_z: i32) -> Vec<PrefabPlacement> {
+        let min = Vec3::new(ch_x, ch_y, ch_z).map(|ch| global_coord(ch, 0));
+        let max = Vec3::new(ch_x, ch_y, ch_z).map(|ch| global_coord(ch, CHUNK_SIZE - 1));
+        let cells =
+            |axis: usize| min[axis].div_euclid(PREFAB_CELL)..=max[axis].div_euclid(PREFAB_CELL);
+        // Без плана мира биомы всё равно нужны, чтобы выбирать префабы
+        let plan = self.base.plan(seed);
+        let biomes_plan = plan.clone().unwrap_or_else(|| WorldPlan::for_seed(seed));
+        let biomes = &biomes_plan.biomes;
+        let ground_level = |x, y| self.base.ground_level(seed, x, y);
+        cells(1)
+            .flat_map(|cell_y| cells(0).map(move |cell_x| (cell_x, cell_y)))
+            .filter_map(|(cell_x, cell_y)| {
+                self.prefabs
+                    .placement(seed, biomes, plan.as_deref(), cell_x, cell_y, ground_level)
+            })
+            .filter(|placement| placement.intersects(min, max))
+            .collect()
+    }
+}
+
+impl ChunkGenerator for PrefabGenerator {
+    /// Префабы берутся из файла данных, поэтому в сохранение записывается
+    /// только имя основного генератора
+    fn name(&self) -> &'static str {
+        self.base.name()
+    }
+
+    fn ground_level(&self, seed: u64, x: i32, y: i32) -> Option<i32> {
+        self.base.ground_level(seed, x, y)
+    }
+
+    fn plan(&self, seed: u64) -> Option<Arc<WorldPlan>> {
+        self.base.plan(seed)
+    }
+
+    fn generate(&self, seed: u64, tiles: &TileRegistry, ch_x: i32, ch_y: i32, ch_z: i32) -> Chunk {
+        let mut chunk = self.base.generate(seed, tiles, ch_x, ch_y, ch_z);
+        for placement in self.placements(seed, ch_x, ch_y, ch_z) {
+            for (pos, tile) in self.prefabs.placed_tiles(&placement) {
+                if chunk_of(pos.x, pos.y, pos.z) == (ch_x, ch_y, ch_z) {
+                    chunk.set_tile(tiles, pos.x, pos.y, pos.z, tile);
+                }
+            }
+        }
+        chunk
+    }
+
+    /// Существа основного генератора встают уже с учётом тайлов префабов
+    fn spawns(
+        &self,
+        seed: u64,
+        chunk: &Chunk,
+        ch_x: i32,
+        ch_y: i32,
+        ch_z: i32,
+    ) -> Vec<PrefabSpawn> {
+        let mut spawns = self.base.spawns(seed, chunk, ch_x, ch_y, ch_z);
+        spawns.extend(
+            self.placements(seed, ch_x, ch_y, ch_z)
+                .iter()
+                .flat_map(|placement| self.prefabs.placed_spawns(placement))
+                .filter(|spawn| {
+                    chunk_of(spawn.position.x, spawn.position.y, spawn.position.z)
+                        == (ch_x, ch_y, ch_z)
+                }),
+        );
+        spawns
+    }
+}